@@ -0,0 +1,119 @@
+//! Shared-secret authentication of clients and workers to the broker.
+//!
+//! After accepting a peer's [crate::proto::Hello], the broker sends a random [Nonce]. The peer
+//! proves that it knows the [SharedKey] by replying with an HMAC-SHA256 of the nonce. The key
+//! itself never crosses the wire.
+
+use crate::{Error, Result};
+use hmac::{Hmac, Mac as _};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+type HmacSha256 = Hmac<sha2::Sha256>;
+
+/// A random challenge sent by the broker. A fresh one is generated for every connection so that a
+/// recorded response can't be replayed.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Nonce(pub [u8; 32]);
+
+impl Nonce {
+    pub fn random() -> Self {
+        Nonce(rand::random())
+    }
+}
+
+/// The response to a [Nonce]: an HMAC-SHA256 of the nonce keyed by the [SharedKey].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Signature(pub [u8; 32]);
+
+/// A pre-shared key known to the broker and to every client and worker allowed to connect to it.
+#[derive(Clone)]
+pub struct SharedKey(Vec<u8>);
+
+impl SharedKey {
+    /// Create a key from the given bytes. The key must not be empty.
+    pub fn new(bytes: Vec<u8>) -> Result<Self> {
+        if bytes.is_empty() {
+            Err(Error::msg("shared key must not be empty"))
+        } else {
+            Ok(SharedKey(bytes))
+        }
+    }
+
+    /// Load a key from a file. The entire contents of the file, including any trailing newline,
+    /// are used as the key.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Self::new(std::fs::read(path)?)
+    }
+
+    /// Compute the [Signature] a peer should send in response to `nonce`.
+    pub fn sign(&self, nonce: &Nonce) -> Signature {
+        Signature(self.hmac(nonce).finalize().into_bytes().into())
+    }
+
+    /// Check a peer's response to `nonce`. The comparison is done in constant time.
+    pub fn verify(&self, nonce: &Nonce, signature: &Signature) -> bool {
+        self.hmac(nonce).verify_slice(&signature.0).is_ok()
+    }
+
+    fn hmac(&self, nonce: &Nonce) -> HmacSha256 {
+        let mut hmac = HmacSha256::new_from_slice(&self.0).unwrap();
+        hmac.update(&nonce.0);
+        hmac
+    }
+}
+
+impl std::fmt::Debug for SharedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SharedKey(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(bytes: &[u8]) -> SharedKey {
+        SharedKey::new(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn empty_key_rejected() {
+        assert!(SharedKey::new(vec![]).is_err());
+    }
+
+    #[test]
+    fn sign_then_verify() {
+        let nonce = Nonce::random();
+        let key = key(b"secret");
+        assert!(key.verify(&nonce, &key.sign(&nonce)));
+    }
+
+    #[test]
+    fn wrong_key_fails() {
+        let nonce = Nonce::random();
+        assert!(!key(b"secret").verify(&nonce, &key(b"other").sign(&nonce)));
+    }
+
+    #[test]
+    fn wrong_nonce_fails() {
+        let key = key(b"secret");
+        assert!(!key.verify(&Nonce([1; 32]), &key.sign(&Nonce([2; 32]))));
+    }
+
+    #[test]
+    fn from_file() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("key");
+        std::fs::write(&path, b"secret\n").unwrap();
+        let nonce = Nonce::random();
+        assert!(SharedKey::from_file(&path)
+            .unwrap()
+            .verify(&nonce, &key(b"secret\n").sign(&nonce)));
+    }
+
+    #[test]
+    fn debug_does_not_leak_key() {
+        assert_eq!(format!("{:?}", key(b"secret")), "SharedKey(..)");
+    }
+}
@@ -1,4 +1,21 @@
 use clap::{value_parser, Parser};
+use meticulous::{
+    auth::SharedKey,
+    broker::{ClientWeights, HeartbeatConfig, MaxFrameSizes},
+    tls,
+};
+use std::{path::PathBuf, time::Duration};
+
+/// Parse a client weight given as NAME=WEIGHT.
+fn parse_client_weight(arg: &str) -> Result<(String, u32), String> {
+    let Some((name, weight)) = arg.split_once('=') else {
+        return Err(format!("client weight {arg} must be given as NAME=WEIGHT"));
+    };
+    match weight.parse() {
+        Ok(0) | Err(_) => Err(format!("weight {weight} must be a positive integer")),
+        Ok(weight) => Ok((name.to_string(), weight)),
+    }
+}
 
 /// The meticulous worker. This process executes subprocesses as directed by the broker.
 #[derive(Parser)]
@@ -11,12 +28,95 @@ struct Cli {
         value_parser = value_parser!(u16).range(1..)
     )]
     port: Option<u16>,
+
+    /// File containing a shared secret. If provided, clients and workers must prove they know the
+    /// secret before they are admitted.
+    #[arg(short, long)]
+    key_file: Option<PathBuf>,
+
+    /// PEM file containing the broker's TLS certificate chain. If provided, all connections use
+    /// TLS.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM file containing the private key for --tls-cert.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// PEM file containing the certificate authorities used to verify client and worker
+    /// certificates. If provided, clients and workers must present a certificate.
+    #[arg(long, requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// The largest message, in bytes, accepted from a client. Clients that send larger messages
+    /// are disconnected.
+    #[arg(long, default_value_t = MaxFrameSizes::default().client)]
+    max_client_frame_size: u32,
+
+    /// The largest message, in bytes, accepted from a worker. Workers that send larger messages
+    /// are disconnected.
+    #[arg(long, default_value_t = MaxFrameSizes::default().worker)]
+    max_worker_frame_size: u32,
+
+    /// How often, in seconds, clients, workers, and the broker send heartbeats on otherwise idle
+    /// connections.
+    #[arg(
+        long,
+        default_value_t = HeartbeatConfig::default().interval.as_secs(),
+        value_parser = value_parser!(u64).range(1..)
+    )]
+    heartbeat_interval: u64,
+
+    /// How many consecutive heartbeats a peer may miss before its connection is dropped. When a
+    /// worker is dropped, its executions are given to other workers.
+    #[arg(
+        long,
+        default_value_t = HeartbeatConfig::default().missed_beats,
+        value_parser = value_parser!(u32).range(1..)
+    )]
+    missed_heartbeats: u32,
+
+    /// The share of the workers a client gets, given as NAME=WEIGHT, where NAME is the name the
+    /// client connects with. When several clients have work queued at the same priority, each gets
+    /// slots in proportion to its weight. Clients not listed have a weight of 1. May be given
+    /// multiple times.
+    #[arg(long, value_name = "NAME=WEIGHT", value_parser = parse_client_weight)]
+    client_weight: Vec<(String, u32)>,
 }
 
 fn main() -> meticulous::Result<()> {
     let cli = Cli::parse();
+    let key = cli
+        .key_file
+        .as_deref()
+        .map(SharedKey::from_file)
+        .transpose()?;
+    let tls = cli.tls_cert.map(|cert_file| tls::ServerOptions {
+        cert_file,
+        key_file: cli.tls_key.unwrap(),
+        client_ca_file: cli.tls_client_ca,
+    });
+    let max_frame_sizes = MaxFrameSizes {
+        client: cli.max_client_frame_size,
+        worker: cli.max_worker_frame_size,
+    };
+    let heartbeat = HeartbeatConfig {
+        interval: Duration::from_secs(cli.heartbeat_interval),
+        missed_beats: cli.missed_heartbeats,
+    };
+    let client_weights: ClientWeights = cli.client_weight.into_iter().collect();
     let runtime = tokio::runtime::Runtime::new()?;
-    runtime.block_on(async { meticulous::broker::main(cli.port).await })?;
+    runtime.block_on(async {
+        meticulous::broker::main(
+            cli.port,
+            key,
+            tls,
+            max_frame_sizes,
+            heartbeat,
+            client_weights,
+        )
+        .await
+    })?;
     Ok(())
 }
 
@@ -25,3 +125,11 @@ fn test_cli() {
     use clap::CommandFactory;
     Cli::command().debug_assert()
 }
+
+#[test]
+fn test_parse_client_weight() {
+    assert_eq!(parse_client_weight("ci=3"), Ok(("ci".to_string(), 3)));
+    assert!(parse_client_weight("ci").is_err());
+    assert!(parse_client_weight("ci=0").is_err());
+    assert!(parse_client_weight("ci=-1").is_err());
+}
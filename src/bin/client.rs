@@ -1,12 +1,50 @@
-use clap::{builder::NonEmptyStringValueParser, Parser};
-use std::net::SocketAddr;
+use clap::{builder::NonEmptyStringValueParser, value_parser, Parser};
+use meticulous::{
+    auth::SharedKey, tls, ExecutionDetails, ProcessLimits, ResourceLimits, Scratch, Sha256Digest,
+};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
 fn parse_socket_addr(arg: &str) -> std::io::Result<SocketAddr> {
     use std::net::ToSocketAddrs as _;
     let addrs: Vec<SocketAddr> = arg.to_socket_addrs()?.collect();
     // It's not clear how we could end up with an empty iterator. We'll assume
     // that's impossible until proven wrong.
-    Ok(*addrs.get(0).unwrap())
+    Ok(*addrs.first().unwrap())
+}
+
+/// Parse an environment variable given as NAME=VALUE. A bare NAME takes the variable's value from
+/// the client's own environment.
+fn parse_env_var(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some(("", _)) => Err("variable name must not be empty".to_string()),
+        Some((name, value)) => Ok((name.to_string(), value.to_string())),
+        None => match std::env::var(arg) {
+            Ok(value) => Ok((arg.to_string(), value)),
+            Err(_) => Err(format!("{arg} is not set in the client's environment")),
+        },
+    }
+}
+
+/// Parse a label given as KEY=VALUE.
+fn parse_label(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some(("", _)) => Err("label key must not be empty".to_string()),
+        Some((key, value)) => Ok((key.to_string(), value.to_string())),
+        None => Err(format!("label {arg} must be given as KEY=VALUE")),
+    }
+}
+
+/// Parse a signal given as a number or a name, like 2, SIGINT, or INT.
+fn parse_signal(arg: &str) -> Result<i32, String> {
+    use nix::sys::signal::Signal;
+    let signal = match arg.parse::<i32>() {
+        Ok(number) => Signal::try_from(number),
+        Err(_) if arg.starts_with("SIG") => arg.parse::<Signal>(),
+        Err(_) => format!("SIG{arg}").parse::<Signal>(),
+    };
+    signal
+        .map(|signal| signal as i32)
+        .map_err(|_| format!("{arg} is not a signal"))
 }
 
 /// The meticulous client. This process sends work to the broker to be executed by workers.
@@ -25,12 +63,204 @@ struct Cli {
         value_parser = NonEmptyStringValueParser::new()
     )]
     name: String,
+
+    /// File containing the shared secret used to authenticate to the broker. Required if the
+    /// broker was started with a key file.
+    #[arg(short, long)]
+    key_file: Option<PathBuf>,
+
+    /// PEM file containing the certificate authorities used to verify the broker's TLS
+    /// certificate. If provided, the connection to the broker uses TLS.
+    #[arg(long)]
+    tls_ca: Option<PathBuf>,
+
+    /// PEM file containing the certificate chain to present to the broker. Required if the broker
+    /// verifies client certificates.
+    #[arg(long, requires_all = ["tls_ca", "tls_key"])]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM file containing the private key for --tls-cert.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Name to verify the broker's TLS certificate against. Defaults to the broker's IP address.
+    #[arg(long, requires = "tls_ca")]
+    tls_server_name: Option<String>,
+
+    /// Environment variable to set for every test, as NAME=VALUE. A bare NAME passes along the
+    /// client's value of the variable. May be given multiple times.
+    #[arg(short, long = "env", value_name = "NAME[=VALUE]", value_parser = parse_env_var)]
+    environment: Vec<(String, String)>,
+
+    /// Run tests with an empty environment, except for the variables given with --env. By
+    /// default, tests inherit the worker's environment.
+    #[arg(long)]
+    clear_env: bool,
+
+    /// Stream each test's output back as it runs, and print all of a failing test's output, with
+    /// stdout and stderr interleaved. By default, only the last 64 KiB of each is printed.
+    #[arg(long)]
+    stream_output: bool,
+
+    /// Kill any test that runs for longer than this many seconds and report it as timed out.
+    #[arg(short, long, value_parser = value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+
+    /// The signal sent to a test to stop it when it times out, given as a number or a name like
+    /// SIGINT.
+    #[arg(long, default_value = "SIGTERM", value_parser = parse_signal)]
+    termination_signal: i32,
+
+    /// How many seconds a test has to exit after being sent the termination signal before it is
+    /// killed. If 0, tests are killed right away.
+    #[arg(long, default_value_t = 5)]
+    grace_period: u64,
+
+    /// Only run tests on workers with this label, given as KEY=VALUE. May be given multiple
+    /// times, in which case workers must have all of the labels.
+    #[arg(short = 'l', long = "require-label", value_name = "KEY=VALUE", value_parser = parse_label)]
+    required_labels: Vec<(String, String)>,
+
+    /// The number of worker slots each test occupies, for tests that run their own thread pools.
+    #[arg(short, long, default_value_t = 1, value_parser = value_parser!(u32).range(1..))]
+    slots: u32,
+
+    /// The number of bytes of memory each test needs. Workers won't run more tests at once than
+    /// they have memory for.
+    #[arg(short, long)]
+    memory: Option<u64>,
+
+    /// The priority of this run's tests. The broker runs higher-priority tests, from any client,
+    /// before lower-priority ones.
+    #[arg(short, long, default_value_t = 0, allow_negative_numbers = true)]
+    priority: i32,
+
+    /// How many times to retry a test on another worker if the worker running it disconnects.
+    #[arg(long, default_value_t = 2)]
+    retries: u32,
+
+    /// Run each test in its own Linux namespaces, isolated from the other tests and the worker.
+    #[arg(long)]
+    sandbox: bool,
+
+    /// Run each test with a network of its own, with nothing but loopback, so tests can't reach
+    /// the network or collide on ports. Implies --sandbox.
+    #[arg(long)]
+    isolate_network: bool,
+
+    /// SHA-256 digest of an image layer to use as part of each test's root file system, which
+    /// workers get from their layer directory. May be given multiple times, bottom layer first.
+    /// Implies --sandbox.
+    #[arg(long = "layer", value_name = "DIGEST")]
+    layers: Vec<Sha256Digest>,
+
+    /// The most memory, in bytes, each test and the processes it starts may use together. Tests
+    /// that need more are killed. Defaults to the worker's limit, if it has one.
+    #[arg(long)]
+    memory_limit: Option<u64>,
+
+    /// How much CPU time each test may use, in thousandths of a CPU. Tests that try to use more
+    /// are slowed down. Defaults to the worker's limit, if it has one.
+    #[arg(long, value_parser = value_parser!(u32).range(1..))]
+    cpu_limit: Option<u32>,
+
+    /// The most processes and threads each test may have at once. Defaults to the worker's limit,
+    /// if it has one.
+    #[arg(long, value_parser = value_parser!(u32).range(1..))]
+    pids_limit: Option<u32>,
+
+    /// The largest core file, in bytes, each of a test's processes may dump. Zero keeps them from
+    /// dumping core. Defaults to the worker's limit, if it has one.
+    #[arg(long)]
+    core_file_size_limit: Option<u64>,
+
+    /// The most files each of a test's processes may have open. Defaults to the worker's limit, if
+    /// it has one.
+    #[arg(long)]
+    open_files_limit: Option<u64>,
+
+    /// The seconds of CPU time each of a test's processes may use before it's sent SIGXCPU.
+    /// Defaults to the worker's limit, if it has one.
+    #[arg(long)]
+    cpu_time_limit: Option<u64>,
+
+    /// The bytes of virtual memory each of a test's processes may map. Defaults to the worker's
+    /// limit, if it has one.
+    #[arg(long)]
+    address_space_limit: Option<u64>,
+
+    /// The largest file, in bytes, each of a test's processes may write. Defaults to the worker's
+    /// limit, if it has one.
+    #[arg(long)]
+    file_size_limit: Option<u64>,
+
+    /// Give each test an empty directory of its own for temporary files, which TMPDIR is set to.
+    /// Workers remove it once the test completes.
+    #[arg(long)]
+    scratch: bool,
+
+    /// Run each test in its scratch directory. Implies --scratch.
+    #[arg(long)]
+    run_in_scratch: bool,
+
+    /// The most bytes each test may store in its scratch directory. The directory is then kept in
+    /// memory, in the test's sandbox. Implies --scratch and --sandbox.
+    #[arg(long)]
+    scratch_quota: Option<u64>,
 }
 
 fn main() -> meticulous::Result<()> {
     let cli = Cli::parse();
+    let key = cli
+        .key_file
+        .as_deref()
+        .map(SharedKey::from_file)
+        .transpose()?;
+    let tls = cli.tls_ca.map(|ca_file| tls::ClientOptions {
+        ca_file,
+        cert_file: cli.tls_cert,
+        key_file: cli.tls_key,
+        server_name: cli.tls_server_name,
+    });
+    let template = ExecutionDetails {
+        environment: cli.environment.into_iter().collect(),
+        clear_environment: cli.clear_env,
+        timeout: cli.timeout.map(Duration::from_secs),
+        termination_signal: cli.termination_signal,
+        grace_period: Duration::from_secs(cli.grace_period),
+        stream_output: cli.stream_output,
+        required_labels: cli.required_labels.into_iter().collect(),
+        slots: cli.slots,
+        memory: cli.memory,
+        priority: cli.priority,
+        retries: cli.retries,
+        sandbox: cli.sandbox,
+        isolate_network: cli.isolate_network,
+        layers: cli.layers,
+        limits: ResourceLimits {
+            memory: cli.memory_limit,
+            cpu_millis: cli.cpu_limit,
+            pids: cli.pids_limit,
+        },
+        process_limits: ProcessLimits {
+            core_file_size: cli.core_file_size_limit,
+            open_files: cli.open_files_limit,
+            cpu_seconds: cli.cpu_time_limit,
+            address_space: cli.address_space_limit,
+            file_size: cli.file_size_limit,
+        },
+        scratch: (cli.scratch || cli.run_in_scratch || cli.scratch_quota.is_some()).then_some(
+            Scratch {
+                working_directory: cli.run_in_scratch,
+                quota: cli.scratch_quota,
+            },
+        ),
+        ..Default::default()
+    };
     let runtime = tokio::runtime::Runtime::new()?;
-    runtime.block_on(async { meticulous::client::main(cli.name, cli.broker).await })?;
+    runtime.block_on(async {
+        meticulous::client::main(cli.name, cli.broker, key, tls, template).await
+    })?;
     Ok(())
 }
 
@@ -39,3 +269,37 @@ fn test_cli() {
     use clap::CommandFactory;
     Cli::command().debug_assert()
 }
+
+#[test]
+fn test_parse_env_var() {
+    assert_eq!(
+        parse_env_var("FOO=bar=baz"),
+        Ok(("FOO".to_string(), "bar=baz".to_string()))
+    );
+    assert_eq!(
+        parse_env_var("FOO="),
+        Ok(("FOO".to_string(), "".to_string()))
+    );
+    assert!(parse_env_var("=bar").is_err());
+    assert!(parse_env_var("METICULOUS_SURELY_NOT_SET").is_err());
+}
+
+#[test]
+fn test_parse_label() {
+    assert_eq!(
+        parse_label("mem=large=yes"),
+        Ok(("mem".to_string(), "large=yes".to_string()))
+    );
+    assert_eq!(parse_label("gpu="), Ok(("gpu".to_string(), "".to_string())));
+    assert!(parse_label("=large").is_err());
+    assert!(parse_label("mem").is_err());
+}
+
+#[test]
+fn test_parse_signal() {
+    assert_eq!(parse_signal("2"), Ok(2));
+    assert_eq!(parse_signal("SIGINT"), Ok(2));
+    assert_eq!(parse_signal("INT"), Ok(2));
+    assert!(parse_signal("SIGNOPE").is_err());
+    assert!(parse_signal("1000").is_err());
+}
@@ -1,12 +1,22 @@
 use clap::{builder::NonEmptyStringValueParser, value_parser, Parser};
-use std::net::SocketAddr;
+use meticulous::{auth::SharedKey, tls, ProcessLimits, ResourceLimits};
+use std::{net::SocketAddr, path::PathBuf};
 
 fn parse_socket_addr(arg: &str) -> std::io::Result<SocketAddr> {
     use std::net::ToSocketAddrs as _;
     let addrs: Vec<SocketAddr> = arg.to_socket_addrs()?.collect();
     // It's not clear how we could end up with an empty iterator. We'll assume
     // that's impossible until proven wrong.
-    Ok(*addrs.get(0).unwrap())
+    Ok(*addrs.first().unwrap())
+}
+
+/// Parse a label given as KEY=VALUE.
+fn parse_label(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some(("", _)) => Err("label key must not be empty".to_string()),
+        Some((key, value)) => Ok((key.to_string(), value.to_string())),
+        None => Err(format!("label {arg} must be given as KEY=VALUE")),
+    }
 }
 
 /// The meticulous worker. This process executes subprocesses as directed by the broker.
@@ -27,6 +37,29 @@ struct Cli {
     )]
     name: String,
 
+    /// File containing the shared secret used to authenticate to the broker. Required if the
+    /// broker was started with a key file.
+    #[arg(short, long)]
+    key_file: Option<PathBuf>,
+
+    /// PEM file containing the certificate authorities used to verify the broker's TLS
+    /// certificate. If provided, the connection to the broker uses TLS.
+    #[arg(long)]
+    tls_ca: Option<PathBuf>,
+
+    /// PEM file containing the certificate chain to present to the broker. Required if the broker
+    /// verifies client certificates.
+    #[arg(long, requires_all = ["tls_ca", "tls_key"])]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM file containing the private key for --tls-cert.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Name to verify the broker's TLS certificate against. Defaults to the broker's IP address.
+    #[arg(long, requires = "tls_ca")]
+    tls_server_name: Option<String>,
+
     /// The number of execution slots available. Most program executions will take one job slot.
     #[arg(
         short,
@@ -35,13 +68,123 @@ struct Cli {
         value_parser = value_parser!(u32).range(1..1000)
     )]
     slots: u32,
+
+    /// The number of bytes of memory executions may reserve on this worker. Defaults to all of the
+    /// machine's RAM.
+    #[arg(short, long)]
+    memory: Option<u64>,
+
+    /// Label to advertise to the broker, given as KEY=VALUE, so that executions requiring it can
+    /// be placed on this worker. May be given multiple times. The "arch" and "os" labels are
+    /// detected automatically, but can be overridden.
+    #[arg(short, long = "label", value_name = "KEY=VALUE", value_parser = parse_label)]
+    labels: Vec<(String, String)>,
+
+    /// Directory to extract image layers into and to create tests' scratch directories in. What an
+    /// earlier run left there is removed when the worker starts, so each worker needs its own.
+    #[arg(long, default_value = "/var/tmp/meticulous-worker/cache")]
+    cache_root: PathBuf,
+
+    /// The number of bytes of extracted image layers to keep once no execution is using them.
+    #[arg(long, default_value_t = 1 << 30)]
+    cache_size: u64,
+
+    /// Directory containing the image layers that executions may use, as tar files named
+    /// DIGEST.tar, where DIGEST is the file's SHA-256 digest in hex. It may be shared between
+    /// workers. If not provided, executions that use layers fail.
+    #[arg(long)]
+    layer_dir: Option<PathBuf>,
+
+    /// A cgroup v2 directory delegated to the worker, like /sys/fs/cgroup/meticulous. Each
+    /// execution runs in a cgroup of its own inside of it, which enforces the execution's resource
+    /// limits. If not provided, executions with resource limits fail.
+    #[arg(long)]
+    cgroup: Option<PathBuf>,
+
+    /// The memory limit, in bytes, for executions that don't set their own.
+    #[arg(long, requires = "cgroup")]
+    default_memory_limit: Option<u64>,
+
+    /// The CPU limit, in thousandths of a CPU, for executions that don't set their own.
+    #[arg(long, requires = "cgroup", value_parser = value_parser!(u32).range(1..))]
+    default_cpu_limit: Option<u32>,
+
+    /// The limit on the number of processes and threads for executions that don't set their own.
+    #[arg(long, requires = "cgroup", value_parser = value_parser!(u32).range(1..))]
+    default_pids_limit: Option<u32>,
+
+    /// The largest core file, in bytes, processes of executions that don't set their own limit
+    /// may dump.
+    #[arg(long)]
+    default_core_file_size_limit: Option<u64>,
+
+    /// The most files each process of an execution may have open, for executions that don't set
+    /// their own limit.
+    #[arg(long)]
+    default_open_files_limit: Option<u64>,
+
+    /// The seconds of CPU time each process of an execution may use, for executions that don't
+    /// set their own limit.
+    #[arg(long)]
+    default_cpu_time_limit: Option<u64>,
+
+    /// The bytes of virtual memory each process of an execution may map, for executions that
+    /// don't set their own limit.
+    #[arg(long)]
+    default_address_space_limit: Option<u64>,
+
+    /// The largest file, in bytes, processes of executions that don't set their own limit may
+    /// write.
+    #[arg(long)]
+    default_file_size_limit: Option<u64>,
 }
 
 fn main() -> meticulous::Result<()> {
     let cli = Cli::parse();
+    let key = cli
+        .key_file
+        .as_deref()
+        .map(SharedKey::from_file)
+        .transpose()?;
+    let tls = cli.tls_ca.map(|ca_file| tls::ClientOptions {
+        ca_file,
+        cert_file: cli.tls_cert,
+        key_file: cli.tls_key,
+        server_name: cli.tls_server_name,
+    });
+    let cgroup = cli.cgroup.map(|path| meticulous::worker::CgroupConfig {
+        path,
+        defaults: ResourceLimits {
+            memory: cli.default_memory_limit,
+            cpu_millis: cli.default_cpu_limit,
+            pids: cli.default_pids_limit,
+        },
+    });
     let runtime = tokio::runtime::Runtime::new()?;
     runtime.block_on(async {
-        meticulous::worker::main(cli.name, cli.slots as usize, cli.broker).await
+        meticulous::worker::main(
+            cli.name,
+            cli.slots as usize,
+            cli.memory,
+            cli.labels.into_iter().collect(),
+            cli.broker,
+            key,
+            tls,
+            meticulous::worker::CacheConfig {
+                root: cli.cache_root,
+                bytes_used_goal: cli.cache_size,
+                layer_dir: cli.layer_dir,
+            },
+            cgroup,
+            ProcessLimits {
+                core_file_size: cli.default_core_file_size_limit,
+                open_files: cli.default_open_files_limit,
+                cpu_seconds: cli.default_cpu_time_limit,
+                address_space: cli.default_address_space_limit,
+                file_size: cli.default_file_size_limit,
+            },
+        )
+        .await
     })?;
     Ok(())
 }
@@ -51,3 +194,14 @@ fn test_cli() {
     use clap::CommandFactory;
     Cli::command().debug_assert()
 }
+
+#[test]
+fn test_parse_label() {
+    assert_eq!(
+        parse_label("mem=large=yes"),
+        Ok(("mem".to_string(), "large=yes".to_string()))
+    );
+    assert_eq!(parse_label("gpu="), Ok(("gpu".to_string(), "".to_string())));
+    assert!(parse_label("=large").is_err());
+    assert!(parse_label("mem").is_err());
+}
@@ -2,9 +2,33 @@
 
 mod scheduler;
 
-use crate::{channel_reader, proto, ClientId, Error, Result, WorkerId};
+use crate::{auth::SharedKey, channel_reader, proto, tls, ClientId, Error, Result, WorkerId};
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
+pub use proto::HeartbeatConfig;
+
+/// The largest frames, in bytes, that the broker will accept from each type of peer. A peer that
+/// sends a larger frame is disconnected.
+#[derive(Clone, Copy, Debug)]
+pub struct MaxFrameSizes {
+    pub client: u32,
+    pub worker: u32,
+}
+
+impl Default for MaxFrameSizes {
+    fn default() -> Self {
+        MaxFrameSizes {
+            client: proto::DEFAULT_MAX_FRAME_SIZE,
+            worker: proto::DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+}
+
+/// The fair-share weight of each client, by name. A client with weight 2 gets twice as many worker
+/// slots as a client with weight 1 when they're both busy. Clients not listed get a weight of 1.
+pub type ClientWeights = HashMap<String, u32>;
+
 struct PassThroughDeps;
 
 /// The production implementation of [scheduler::SchedulerDeps]. This implementation just hands the
@@ -56,20 +80,25 @@ async fn scheduler_main(receiver: UnboundedReceiver<SchedulerMessage>) {
 
 /// Main loop for a client or worker socket. There should be one of these for each connected client
 /// or worker socket. This function will run until the client is closed. There is no error return
-/// since this function will always eventually run into an error.
+/// since this function will always eventually run into an error. If the peer sends a frame that
+/// violates the protocol, such as one larger than `max_frame_size`, or if it goes silent for longer
+/// than `heartbeat` allows, the error is logged and the peer is disconnected.
 // XXX: Unit test this function.
+#[allow(clippy::too_many_arguments)]
 async fn socket_main<IdT, SenderT, RequestT>(
     read_stream: impl tokio::io::AsyncRead + Send + Unpin + 'static,
     write_stream: impl tokio::io::AsyncWrite + Send + Unpin + 'static,
+    max_frame_size: u32,
+    heartbeat: HeartbeatConfig,
     scheduler_sender: UnboundedSender<SchedulerMessage>,
     id: IdT,
     connected_msg: impl FnOnce(IdT, UnboundedSender<SenderT>) -> SchedulerMessage,
     transform_msg: impl Fn(IdT, RequestT) -> SchedulerMessage + Send + 'static,
     disconnected_msg: impl FnOnce(IdT) -> SchedulerMessage,
 ) where
-    IdT: Copy + Send + 'static,
-    SenderT: serde::Serialize + Send + 'static,
-    RequestT: serde::de::DeserializeOwned + 'static,
+    IdT: Copy + Debug + Send + 'static,
+    SenderT: serde::Serialize + proto::Heartbeat + Send + 'static,
+    RequestT: serde::de::DeserializeOwned + proto::Heartbeat + 'static,
 {
     let (socket_sender, socket_receiver) = tokio::sync::mpsc::unbounded_channel();
 
@@ -89,13 +118,25 @@ async fn socket_main<IdT, SenderT, RequestT>(
     let mut join_set = tokio::task::JoinSet::new();
     join_set.spawn(proto::socket_reader(
         read_stream,
+        max_frame_size,
+        heartbeat,
         scheduler_sender.clone(),
         move |req| transform_msg(id, req),
     ));
-    join_set.spawn(proto::socket_writer(socket_receiver, write_stream));
+    join_set.spawn(proto::socket_writer(
+        socket_receiver,
+        write_stream,
+        heartbeat,
+    ));
 
     // Wait for one task to complete and then cancel the other one and wait for it.
-    join_set.join_next().await;
+    if let Some(Ok(Err(err))) = join_set.join_next().await {
+        if let Some(err) = err.downcast_ref::<proto::FrameError>() {
+            println!("{id:?} sent a bad frame: {err}");
+        } else if let Some(err) = err.downcast_ref::<proto::HeartbeatTimeout>() {
+            println!("{id:?} presumed dead: {err}");
+        }
+    }
     join_set.shutdown().await;
 
     // Tell the scheduler we're done. We do this after waiting for all tasks to complete, since we
@@ -103,12 +144,76 @@ async fn socket_main<IdT, SenderT, RequestT>(
     scheduler_sender.send(disconnected_msg(id)).ok();
 }
 
+/// Perform the broker's side of the connection handshake: read the peer's [proto::Hello], and if
+/// `key` is provided, challenge the peer to prove it knows the key. On success, send
+/// [proto::HelloResponse::Accepted], carrying the `heartbeat` settings the peer must use and the
+/// capabilities offered by the peer that we support too, and return the [proto::Hello]. If the
+/// peer should be rejected, return the reason without sending anything, so the caller can log it
+/// and tell the peer.
+async fn handshake(
+    read_stream: &mut (impl tokio::io::AsyncRead + Unpin),
+    write_stream: &mut (impl tokio::io::AsyncWrite + Unpin),
+    key: Option<&SharedKey>,
+    heartbeat: HeartbeatConfig,
+) -> Result<std::result::Result<proto::Hello, String>> {
+    let hello = match proto::read_hello(read_stream).await? {
+        Ok(hello) => hello,
+        Err(reason) => return Ok(Err(reason)),
+    };
+    if let Some(key) = key {
+        let nonce = crate::auth::Nonce::random();
+        proto::write_message(write_stream, proto::HelloResponse::Challenge(nonce.clone())).await?;
+        let proto::AuthResponse(signature) =
+            proto::read_message(read_stream, proto::MAX_HELLO_FRAME_SIZE).await?;
+        if !key.verify(&nonce, &signature) {
+            return Ok(Err("authentication failed".to_string()));
+        }
+    }
+    proto::write_message(
+        write_stream,
+        proto::HelloResponse::Accepted {
+            capabilities: hello
+                .capabilities
+                .intersection(&proto::capabilities())
+                .cloned()
+                .collect(),
+            heartbeat,
+        },
+    )
+    .await?;
+    Ok(Ok(hello))
+}
+
+/// Run [handshake] on a new connection from `peer_addr`. If the peer is rejected, log the reason
+/// and tell the peer, then return `None`.
+async fn accept_peer(
+    read_stream: &mut (impl tokio::io::AsyncRead + Unpin),
+    write_stream: &mut (impl tokio::io::AsyncWrite + Unpin),
+    key: Option<&SharedKey>,
+    heartbeat: HeartbeatConfig,
+    peer_addr: impl std::fmt::Display,
+) -> Result<Option<proto::Hello>> {
+    match handshake(read_stream, write_stream, key, heartbeat).await? {
+        Ok(hello) => Ok(Some(hello)),
+        Err(reason) => {
+            println!("connection from {peer_addr} rejected: {reason}");
+            proto::write_message(write_stream, proto::HelloResponse::Rejected(reason)).await?;
+            Ok(None)
+        }
+    }
+}
+
 /// Main loop for the listener. This should be run on a task of its own. There should be at least
 /// one of these in a broker process. It will only return when it encounters an error. Until then,
 /// it listens on a socket and spawns new tasks for each client or worker that connects.
 // XXX: Unit test this function.
 async fn listener_main(
     port: Option<u16>,
+    key: Option<SharedKey>,
+    acceptor: tls::Acceptor,
+    max_frame_sizes: MaxFrameSizes,
+    heartbeat: HeartbeatConfig,
+    client_weights: Arc<ClientWeights>,
     scheduler_sender: UnboundedSender<SchedulerMessage>,
 ) -> Result<()> {
     let sockaddr =
@@ -120,34 +225,88 @@ async fn listener_main(
 
     loop {
         let (socket, peer_addr) = listener.accept().await?;
-        let (read_stream, write_stream) = socket.into_split();
-        let mut read_stream = tokio::io::BufReader::new(read_stream);
 
         let scheduler_sender_clone = scheduler_sender.clone();
+        let key_clone = key.clone();
+        let acceptor_clone = acceptor.clone();
+        let client_weights_clone = client_weights.clone();
 
         tokio::task::spawn(async move {
-            let hello = proto::read_message(&mut read_stream).await?;
+            // Don't let a peer that never finishes the TLS handshake or our own handshake hold on
+            // to its task and socket.
+            let handshake_timeout = heartbeat.timeout();
+            let deadline = tokio::time::Instant::now() + handshake_timeout;
+            let timed_out = || Error::msg(format!("timed out after {handshake_timeout:?}"));
+            let stream = match tokio::time::timeout_at(deadline, acceptor_clone.accept(socket))
+                .await
+                .unwrap_or_else(|_| Err(timed_out()))
+            {
+                Ok(stream) => stream,
+                Err(err) => {
+                    println!("connection from {peer_addr} failed TLS handshake: {err}");
+                    return Ok(());
+                }
+            };
+            let (read_stream, mut write_stream) = tokio::io::split(stream);
+            let mut read_stream = tokio::io::BufReader::new(read_stream);
+
+            let handshake_result = tokio::time::timeout_at(
+                deadline,
+                accept_peer(
+                    &mut read_stream,
+                    &mut write_stream,
+                    key_clone.as_ref(),
+                    heartbeat,
+                    peer_addr,
+                ),
+            )
+            .await
+            .unwrap_or_else(|_| Err(timed_out()));
+            let hello = match handshake_result {
+                Err(err) => {
+                    println!("connection from {peer_addr} failed during handshake: {err}");
+                    return Ok(());
+                }
+                Ok(None) => return Ok(()),
+                Ok(Some(hello)) => hello,
+            };
             println!("{hello:?} from {peer_addr} connected, assigned id: {id}");
-            match hello {
-                proto::Hello::Client { .. } => {
+            match hello.peer {
+                proto::Peer::Client { ref name } => {
+                    let weight = client_weights_clone.get(name).copied().unwrap_or(1);
                     socket_main(
                         read_stream,
                         write_stream,
+                        max_frame_sizes.client,
+                        heartbeat,
                         scheduler_sender_clone,
                         ClientId(id),
-                        SchedulerMessage::ClientConnected,
+                        |id, sender| SchedulerMessage::ClientConnected(id, weight, sender),
                         SchedulerMessage::FromClient,
                         SchedulerMessage::ClientDisconnected,
                     )
                     .await
                 }
-                proto::Hello::Worker { slots, .. } => {
+                proto::Peer::Worker {
+                    ref name,
+                    slots,
+                    memory,
+                    ref labels,
+                } => {
+                    let info = scheduler::WorkerInfo {
+                        name: name.clone(),
+                        slots: slots as usize,
+                        memory,
+                        labels: labels.clone(),
+                    };
                     socket_main(
                         read_stream,
                         write_stream,
+                        max_frame_sizes.worker,
+                        heartbeat,
                         scheduler_sender_clone,
                         WorkerId(id),
-                        |id, sender| SchedulerMessage::WorkerConnected(id, slots as usize, sender),
+                        |id, sender| SchedulerMessage::WorkerConnected(id, info, sender),
                         SchedulerMessage::FromWorker,
                         SchedulerMessage::WorkerDisconnected,
                     )
@@ -171,12 +330,31 @@ async fn signal_handler(kind: tokio::signal::unix::SignalKind) -> Result<()> {
 
 /// The main function for the broker. This should be called on a task of its own. It will return
 /// if there is an error establishing the listener socket, when a signal is received, or when the
-/// listener socket returns an error at accept time.
-pub async fn main(port: Option<u16>) -> Result<()> {
+/// listener socket returns an error at accept time. If `key` is provided, every client and worker
+/// must prove it knows the key before being admitted. If `tls` is provided, all connections use
+/// TLS. Every client and worker is told to use `heartbeat`, and is disconnected if it misses too
+/// many heartbeats. Clients share the workers according to `client_weights`.
+pub async fn main(
+    port: Option<u16>,
+    key: Option<SharedKey>,
+    tls: Option<tls::ServerOptions>,
+    max_frame_sizes: MaxFrameSizes,
+    heartbeat: HeartbeatConfig,
+    client_weights: ClientWeights,
+) -> Result<()> {
+    let acceptor = tls::Acceptor::new(tls.as_ref())?;
     let (scheduler_sender, scheduler_receiver) = tokio::sync::mpsc::unbounded_channel();
 
     let mut join_set = tokio::task::JoinSet::new();
-    join_set.spawn(listener_main(port, scheduler_sender));
+    join_set.spawn(listener_main(
+        port,
+        key,
+        acceptor,
+        max_frame_sizes,
+        heartbeat,
+        Arc::new(client_weights),
+        scheduler_sender,
+    ));
     join_set.spawn(async move {
         scheduler_main(scheduler_receiver).await;
         Ok(())
@@ -193,3 +371,163 @@ pub async fn main(port: Option<u16>) -> Result<()> {
             .expect("no task should panic or be canceled")?;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn handshake_accepts_only_supported_capabilities() {
+        let (mut peer, broker) = tokio::io::duplex(1024);
+        let (mut read_stream, mut write_stream) = tokio::io::split(broker);
+        let mut hello = proto::Hello::new(proto::Peer::Client {
+            name: "client".to_string(),
+        });
+        hello.capabilities.insert("from-the-future".to_string());
+        proto::write_message(&mut peer, hello).await.unwrap();
+
+        let heartbeat = HeartbeatConfig::default();
+        let hello = handshake(&mut read_stream, &mut write_stream, None, heartbeat)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(hello.capabilities.contains("from-the-future"));
+        assert_eq!(
+            proto::read_message::<proto::HelloResponse>(&mut peer, proto::MAX_HELLO_FRAME_SIZE)
+                .await
+                .unwrap(),
+            proto::HelloResponse::Accepted {
+                capabilities: proto::capabilities(),
+                heartbeat,
+            }
+        );
+    }
+
+    fn client_hello() -> proto::Hello {
+        proto::Hello::new(proto::Peer::Client {
+            name: "client".to_string(),
+        })
+    }
+
+    fn key(bytes: &[u8]) -> SharedKey {
+        SharedKey::new(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn handshake_accepts_peer_with_right_key() {
+        let (mut peer, broker) = tokio::io::duplex(1024);
+        let (mut peer_read, mut peer_write) = tokio::io::split(&mut peer);
+        let (mut read_stream, mut write_stream) = tokio::io::split(broker);
+        let broker_key = key(b"secret");
+        let peer_key = key(b"secret");
+
+        let heartbeat = HeartbeatConfig::default();
+        let (broker_result, peer_result) = tokio::join!(
+            handshake(
+                &mut read_stream,
+                &mut write_stream,
+                Some(&broker_key),
+                heartbeat
+            ),
+            proto::send_hello(
+                &mut peer_read,
+                &mut peer_write,
+                client_hello(),
+                Some(&peer_key)
+            ),
+        );
+        let hello = broker_result.unwrap().unwrap();
+        assert!(matches!(hello.peer, proto::Peer::Client { name } if name == "client"));
+        assert_eq!(peer_result.unwrap(), heartbeat);
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_peer_with_wrong_key() {
+        let (mut peer, broker) = tokio::io::duplex(1024);
+        let (mut peer_read, mut peer_write) = tokio::io::split(&mut peer);
+        let (mut read_stream, mut write_stream) = tokio::io::split(broker);
+        let broker_key = key(b"secret");
+        let peer_key = key(b"guess");
+
+        let (broker_result, ()) = tokio::join!(
+            handshake(
+                &mut read_stream,
+                &mut write_stream,
+                Some(&broker_key),
+                HeartbeatConfig::default()
+            ),
+            async {
+                proto::write_message(&mut peer_write, client_hello())
+                    .await
+                    .unwrap();
+                let proto::HelloResponse::Challenge(nonce) =
+                    proto::read_message(&mut peer_read, proto::MAX_HELLO_FRAME_SIZE)
+                        .await
+                        .unwrap()
+                else {
+                    panic!("expected a challenge");
+                };
+                proto::write_message(&mut peer_write, proto::AuthResponse(peer_key.sign(&nonce)))
+                    .await
+                    .unwrap();
+            },
+        );
+        assert_eq!(broker_result.unwrap().unwrap_err(), "authentication failed");
+    }
+
+    #[tokio::test]
+    async fn listener_tells_peer_with_wrong_key_it_was_rejected() {
+        let (mut peer, broker) = tokio::io::duplex(1024);
+        let (mut peer_read, mut peer_write) = tokio::io::split(&mut peer);
+        let (mut read_stream, mut write_stream) = tokio::io::split(broker);
+        let broker_key = key(b"secret");
+        let peer_key = key(b"guess");
+
+        let (broker_result, peer_result) = tokio::join!(
+            accept_peer(
+                &mut read_stream,
+                &mut write_stream,
+                Some(&broker_key),
+                HeartbeatConfig::default(),
+                "peer"
+            ),
+            proto::send_hello(
+                &mut peer_read,
+                &mut peer_write,
+                client_hello(),
+                Some(&peer_key)
+            ),
+        );
+        assert!(broker_result.unwrap().is_none());
+        assert_eq!(
+            peer_result.unwrap_err().to_string(),
+            "broker rejected connection: authentication failed"
+        );
+    }
+
+    #[tokio::test]
+    async fn peer_without_key_fails_handshake_with_keyed_broker() {
+        let (peer, broker) = tokio::io::duplex(1024);
+        let (mut read_stream, mut write_stream) = tokio::io::split(broker);
+        let broker_key = key(b"secret");
+
+        let (broker_result, peer_result) = tokio::join!(
+            handshake(
+                &mut read_stream,
+                &mut write_stream,
+                Some(&broker_key),
+                HeartbeatConfig::default()
+            ),
+            // The peer's end of the connection is closed as soon as it gives up.
+            async move {
+                let (mut peer_read, mut peer_write) = tokio::io::split(peer);
+                proto::send_hello(&mut peer_read, &mut peer_write, client_hello(), None).await
+            },
+        );
+        assert_eq!(
+            peer_result.unwrap_err().to_string(),
+            "broker requires authentication, but no key was provided"
+        );
+        assert!(broker_result.is_err());
+    }
+}
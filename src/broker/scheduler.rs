@@ -4,9 +4,14 @@
 use crate::{
     heap::{Heap, HeapDeps, HeapIndex},
     proto::{ClientRequest, ClientResponse, WorkerRequest, WorkerResponse},
-    ClientExecutionId, ClientId, ExecutionDetails, ExecutionId, ExecutionResult, WorkerId,
+    ClientExecutionId, ClientId, ExecutionDetails, ExecutionId, ExecutionResult, ExecutionStatus,
+    OutputStream, WorkerId,
+};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    ops::Bound,
 };
-use std::collections::{HashMap, VecDeque};
 
 /*              _     _ _
  *  _ __  _   _| |__ | (_) ___
@@ -21,8 +26,11 @@ use std::collections::{HashMap, VecDeque};
 pub struct Scheduler<DepsT: SchedulerDeps> {
     clients: HashMap<ClientId, DepsT::ClientSender>,
     workers: HashMap<WorkerId, Worker<DepsT>>,
-    queued_requests: VecDeque<(ExecutionId, ExecutionDetails)>,
+    queued_requests: Queue,
     worker_heap: Heap<HashMap<WorkerId, Worker<DepsT>>>,
+    /// The names of the workers that disconnected while running each execution, for executions
+    /// that are being retried.
+    lost_workers: HashMap<ExecutionId, Vec<String>>,
 }
 
 /// The external dependencies for [Scheduler]. All of these methods must be asynchronous: they
@@ -38,12 +46,23 @@ pub trait SchedulerDeps {
     fn send_request_to_worker(&mut self, sender: &mut Self::WorkerSender, request: WorkerRequest);
 }
 
+/// What a worker told the broker about itself when it connected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkerInfo {
+    pub name: String,
+    pub slots: usize,
+    /// How many bytes of memory executions may reserve on the worker.
+    pub memory: u64,
+    pub labels: BTreeMap<String, String>,
+}
+
 #[derive(Debug)]
 pub enum Message<DepsT: SchedulerDeps> {
-    ClientConnected(ClientId, DepsT::ClientSender),
+    /// A client connected with the given weight, which determines its share of the workers.
+    ClientConnected(ClientId, u32, DepsT::ClientSender),
     ClientDisconnected(ClientId),
     FromClient(ClientId, ClientRequest),
-    WorkerConnected(WorkerId, usize, DepsT::WorkerSender),
+    WorkerConnected(WorkerId, WorkerInfo, DepsT::WorkerSender),
     WorkerDisconnected(WorkerId),
     FromWorker(WorkerId, WorkerResponse),
 }
@@ -52,23 +71,34 @@ impl<DepsT: SchedulerDeps> Scheduler<DepsT> {
     pub fn receive_message(&mut self, deps: &mut DepsT, msg: Message<DepsT>) {
         use Message::*;
         match msg {
-            ClientConnected(id, sender) => self.receive_client_connected(id, sender),
+            ClientConnected(id, weight, sender) => {
+                self.receive_client_connected(id, weight, sender)
+            }
 
             ClientDisconnected(id) => self.receive_client_disconnected(deps, id),
 
-            FromClient(cid, ClientRequest(ceid, details)) => {
-                self.receive_client_request(deps, cid, ceid, details)
+            FromClient(cid, ClientRequest::EnqueueExecution(ceid, details)) => {
+                self.receive_client_request(deps, cid, ceid, *details)
             }
 
-            WorkerConnected(id, slots, sender) => {
-                self.receive_worker_connected(deps, id, slots, sender)
+            // Heartbeats are consumed by [crate::proto::socket_reader] and never make it here.
+            FromClient(_, ClientRequest::Heartbeat) => {}
+
+            WorkerConnected(id, info, sender) => {
+                self.receive_worker_connected(deps, id, info, sender)
             }
 
             WorkerDisconnected(id) => self.receive_worker_disconnected(deps, id),
 
-            FromWorker(wid, WorkerResponse(eid, result)) => {
+            FromWorker(wid, WorkerResponse::ExecutionCompleted(eid, result)) => {
                 self.receive_worker_response(deps, wid, eid, result)
             }
+
+            FromWorker(wid, WorkerResponse::ExecutionOutput(eid, stream, chunk)) => {
+                self.receive_worker_output(deps, wid, eid, stream, chunk)
+            }
+
+            FromWorker(_, WorkerResponse::Heartbeat) => {}
         }
     }
 }
@@ -84,19 +114,251 @@ impl<DepsT: SchedulerDeps> Scheduler<DepsT> {
 
 #[derive(Debug)]
 struct Worker<DepsT: SchedulerDeps> {
+    name: String,
     slots: usize,
+    memory: u64,
+    labels: BTreeMap<String, String>,
     pending: HashMap<ExecutionId, ExecutionDetails>,
+    /// The total number of slots used by the executions in `pending`.
+    pending_slots: usize,
     heap_index: HeapIndex,
     sender: DepsT::WorkerSender,
 }
 
+impl<DepsT: SchedulerDeps> Worker<DepsT> {
+    fn is_full(&self) -> bool {
+        self.pending_slots >= 2 * self.slots
+    }
+
+    fn has_room_for(&self, details: &ExecutionDetails) -> bool {
+        details.slots as usize <= self.room()
+    }
+
+    /// The number of slots' worth of executions that can still be sent to the worker.
+    fn room(&self) -> usize {
+        (2 * self.slots).saturating_sub(self.pending_slots)
+    }
+
+    /// Whether the worker is big enough for the execution and has all of the labels it requires.
+    /// The worker's dispatcher makes sure executions don't use more memory at once than the
+    /// worker has, so we don't track the memory in use here.
+    fn can_run(&self, details: &ExecutionDetails) -> bool {
+        details.slots as usize <= self.slots
+            && details.memory.is_none_or(|memory| memory <= self.memory)
+            && details
+                .required_labels
+                .iter()
+                .all(|(key, value)| self.labels.get(key) == Some(value))
+    }
+
+    fn add_pending(&mut self, eid: ExecutionId, details: ExecutionDetails) {
+        self.pending_slots += details.slots as usize;
+        self.pending.insert(eid, details);
+    }
+
+    fn remove_pending(&mut self, eid: &ExecutionId) -> Option<ExecutionDetails> {
+        let details = self.pending.remove(eid)?;
+        self.pending_slots -= details.slots as usize;
+        Some(details)
+    }
+}
+
+/// Whether the execution needs anything other than a single slot on any worker.
+fn has_requirements(details: &ExecutionDetails) -> bool {
+    details.slots != 1 || details.memory.is_some() || !details.required_labels.is_empty()
+}
+
+/// What an execution needs from a worker, apart from not being one it should avoid.
+#[derive(PartialEq)]
+struct Requirements {
+    slots: u32,
+    memory: Option<u64>,
+    labels: BTreeMap<String, String>,
+}
+
+impl Requirements {
+    fn of(details: &ExecutionDetails) -> Self {
+        Requirements {
+            slots: details.slots,
+            memory: details.memory,
+            labels: details.required_labels.clone(),
+        }
+    }
+
+    fn are_those_of(&self, details: &ExecutionDetails) -> bool {
+        self.slots == details.slots
+            && self.memory == details.memory
+            && self.labels == details.required_labels
+    }
+}
+
+fn unplaceable_status(details: &ExecutionDetails) -> ExecutionStatus {
+    let mut requirements = vec![];
+    if details.slots != 1 {
+        requirements.push(format!("{} slots", details.slots));
+    }
+    if let Some(memory) = details.memory {
+        requirements.push(format!("{memory} bytes of memory"));
+    }
+    if !details.required_labels.is_empty() {
+        let labels: Vec<_> = details
+            .required_labels
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        requirements.push(format!("the labels {}", labels.join(", ")));
+    }
+    ExecutionStatus::Error(format!(
+        "no connected worker has {}",
+        requirements.join(" and ")
+    ))
+}
+
+/// Requests waiting to be sent to a worker, in a queue for each client. Each client's queue is
+/// ordered by priority, highest first, and then by the order the requests were queued in.
+///
+/// Across clients, higher priority requests always go first. Among clients with requests at the
+/// same priority, the client with the fewest slots in use on workers, relative to its weight, goes
+/// first. This gives each busy client a share of the workers proportional to its weight, no matter
+/// how many requests it has queued. Ties go to the client with the oldest request.
+#[derive(Default)]
+struct Queue {
+    clients: HashMap<ClientId, ClientQueue>,
+    next_back: i64,
+    next_front: i64,
+}
+
+struct ClientQueue {
+    weight: u32,
+    /// The total number of slots used by this client's executions that have been sent to workers.
+    running_slots: usize,
+    requests: BTreeMap<QueueKey, (ExecutionId, ExecutionDetails)>,
+    /// How many of the queued requests need each number of slots.
+    slot_counts: BTreeMap<u32, usize>,
+}
+
+impl ClientQueue {
+    fn insert(&mut self, key: QueueKey, request: (ExecutionId, ExecutionDetails)) {
+        *self.slot_counts.entry(request.1.slots).or_default() += 1;
+        self.requests.insert(key, request);
+    }
+
+    fn remove(&mut self, key: &QueueKey) -> Option<(ExecutionId, ExecutionDetails)> {
+        let request = self.requests.remove(key)?;
+        let count = self.slot_counts.get_mut(&request.1.slots).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.slot_counts.remove(&request.1.slots);
+        }
+        Some(request)
+    }
+}
+
+#[derive(Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
+struct QueueKey(Reverse<i32>, i64);
+
+/// How far into each client's queue [Queue::next] should start looking. Requests up to and
+/// including the one at a client's cursor are skipped.
+type Cursors = HashMap<ClientId, QueueKey>;
+
+impl Queue {
+    fn add_client(&mut self, cid: ClientId, weight: u32) {
+        let queue = ClientQueue {
+            weight,
+            running_slots: 0,
+            requests: BTreeMap::default(),
+            slot_counts: BTreeMap::default(),
+        };
+        self.clients.insert(cid, queue);
+    }
+
+    /// Remove the client along with all of its queued requests.
+    fn remove_client(&mut self, cid: ClientId) {
+        self.clients.remove(&cid);
+    }
+
+    fn push_back(&mut self, request: (ExecutionId, ExecutionDetails)) {
+        let key = QueueKey(Reverse(request.1.priority), self.next_back);
+        self.next_back += 1;
+        self.insert(key, request);
+    }
+
+    /// Queue the request ahead of all of the others with the same priority.
+    fn push_front(&mut self, request: (ExecutionId, ExecutionDetails)) {
+        self.next_front -= 1;
+        let key = QueueKey(Reverse(request.1.priority), self.next_front);
+        self.insert(key, request);
+    }
+
+    fn insert(&mut self, key: QueueKey, request: (ExecutionId, ExecutionDetails)) {
+        let queue = self.clients.get_mut(&request.0 .0).unwrap();
+        queue.insert(key, request);
+    }
+
+    /// The request that should be sent to a worker next, skipping the requests before `cursors`.
+    fn next(&self, cursors: &Cursors) -> Option<(QueueKey, ExecutionId, &ExecutionDetails)> {
+        self.clients
+            .iter()
+            .filter_map(|(cid, queue)| {
+                let lower = cursors.get(cid).map_or(Bound::Unbounded, Bound::Excluded);
+                let (key, request) = queue.requests.range((lower, Bound::Unbounded)).next()?;
+                Some((queue, *key, request))
+            })
+            .min_by(|(lhs_queue, lhs_key, _), (rhs_queue, rhs_key, _)| {
+                let lhs_share = lhs_queue.running_slots as u64 * rhs_queue.weight as u64;
+                let rhs_share = rhs_queue.running_slots as u64 * lhs_queue.weight as u64;
+                (lhs_key.0, lhs_share, lhs_key.1).cmp(&(rhs_key.0, rhs_share, rhs_key.1))
+            })
+            .map(|(_, key, (eid, details))| (key, *eid, details))
+    }
+
+    /// Remove the request so it can be sent to a worker.
+    fn take(&mut self, cid: ClientId, key: QueueKey) -> (ExecutionId, ExecutionDetails) {
+        let queue = self.clients.get_mut(&cid).unwrap();
+        let request = queue.remove(&key).unwrap();
+        queue.running_slots += request.1.slots as usize;
+        request
+    }
+
+    /// Skip the rest of the client's requests if none of them fit in `room` slots.
+    fn skip_client_if_too_big(&self, cid: ClientId, room: usize, cursors: &mut Cursors) {
+        let queue = self.clients.get(&cid).unwrap();
+        let smallest = queue.slot_counts.keys().next();
+        if smallest.is_some_and(|&slots| slots as usize > room) {
+            let (last, _) = queue.requests.last_key_value().unwrap();
+            cursors.insert(cid, *last);
+        }
+    }
+
+    /// Note that an execution taken with [Queue::take] is no longer on a worker.
+    fn finished(&mut self, eid: ExecutionId, details: &ExecutionDetails) {
+        let queue = self.clients.get_mut(&eid.0).unwrap();
+        queue.running_slots -= details.slots as usize;
+    }
+
+    fn retain(&mut self, mut f: impl FnMut(&(ExecutionId, ExecutionDetails)) -> bool) {
+        for queue in self.clients.values_mut() {
+            let removed: Vec<_> = queue
+                .requests
+                .iter()
+                .filter(|(_, request)| !f(request))
+                .map(|(key, _)| *key)
+                .collect();
+            for key in removed {
+                queue.remove(&key);
+            }
+        }
+    }
+}
+
 impl<DepsT: SchedulerDeps> Default for Scheduler<DepsT> {
     fn default() -> Self {
         Scheduler {
             clients: HashMap::default(),
             workers: HashMap::default(),
-            queued_requests: VecDeque::default(),
+            queued_requests: Queue::default(),
             worker_heap: Heap::default(),
+            lost_workers: HashMap::default(),
         }
     }
 }
@@ -107,8 +369,8 @@ impl<DepsT: SchedulerDeps> HeapDeps for HashMap<WorkerId, Worker<DepsT>> {
     fn is_element_less_than(&self, lhs_id: &WorkerId, rhs_id: &WorkerId) -> bool {
         let lhs_worker = self.get(lhs_id).unwrap();
         let rhs_worker = self.get(rhs_id).unwrap();
-        let lhs = (lhs_worker.pending.len() * rhs_worker.slots, *lhs_id);
-        let rhs = (rhs_worker.pending.len() * lhs_worker.slots, *rhs_id);
+        let lhs = (lhs_worker.pending_slots * rhs_worker.slots, *lhs_id);
+        let rhs = (rhs_worker.pending_slots * lhs_worker.slots, *rhs_id);
         lhs.cmp(&rhs) == std::cmp::Ordering::Less
     }
 
@@ -118,45 +380,152 @@ impl<DepsT: SchedulerDeps> HeapDeps for HashMap<WorkerId, Worker<DepsT>> {
 }
 
 impl<DepsT: SchedulerDeps> Scheduler<DepsT> {
-    fn possibly_start_executions(&mut self, deps: &mut DepsT) {
-        while !self.queued_requests.is_empty() && !self.workers.is_empty() {
-            let wid = self.worker_heap.peek().unwrap();
-            let worker = self.workers.get_mut(wid).unwrap();
+    /// The names of the workers a retried execution should stay off of, because they disconnected
+    /// while running it before. This is only a preference: if every connected worker that could
+    /// run the execution has lost it before, it may go to any of them, so none are returned.
+    fn workers_to_avoid(&self, eid: ExecutionId, details: &ExecutionDetails) -> &[String] {
+        let Some(lost) = self.lost_workers.get(&eid) else {
+            return &[];
+        };
+        if self
+            .workers
+            .values()
+            .any(|worker| !lost.contains(&worker.name) && worker.can_run(details))
+        {
+            lost
+        } else {
+            &[]
+        }
+    }
 
-            if worker.pending.len() == 2 * worker.slots {
-                break;
+    /// The most room any worker has. See [Worker::room].
+    fn most_room(&self) -> usize {
+        self.workers.values().map(Worker::room).max().unwrap_or(0)
+    }
+
+    /// Find the least loaded worker that has room for the execution and can run it. Executions
+    /// without requirements that aren't being retried can run anywhere, so they just go to the
+    /// top of the heap.
+    fn find_worker_for(&self, eid: ExecutionId, details: &ExecutionDetails) -> Option<WorkerId> {
+        if !has_requirements(details) && !self.lost_workers.contains_key(&eid) {
+            let wid = self.worker_heap.peek()?;
+            return (!self.workers.get(wid).unwrap().is_full()).then_some(*wid);
+        }
+        let avoid = self.workers_to_avoid(eid, details);
+        self.workers
+            .iter()
+            .filter(|(_, worker)| {
+                worker.has_room_for(details)
+                    && worker.can_run(details)
+                    && !avoid.contains(&worker.name)
+            })
+            .map(|(wid, _)| *wid)
+            .reduce(|lhs, rhs| {
+                if self.workers.is_element_less_than(&rhs, &lhs) {
+                    rhs
+                } else {
+                    lhs
+                }
+            })
+    }
+
+    /// Fail every queued execution that no connected worker can run.
+    fn fail_unplaceable_requests(&mut self, deps: &mut DepsT) {
+        let workers = &self.workers;
+        let clients = &mut self.clients;
+        let lost_workers = &mut self.lost_workers;
+        self.queued_requests.retain(|(eid, details)| {
+            !has_requirements(details)
+                || workers.values().any(|worker| worker.can_run(details))
+                || {
+                    let attempts = lost_workers.remove(eid).map_or(1, |lost| lost.len() as u32);
+                    deps.send_response_to_client(
+                        clients.get_mut(&eid.0).unwrap(),
+                        ClientResponse::ExecutionCompleted(
+                            eid.1,
+                            ExecutionResult {
+                                attempts,
+                                ..unplaceable_status(details).into()
+                            },
+                        ),
+                    );
+                    false
+                }
+        });
+    }
+
+    fn possibly_start_executions(&mut self, deps: &mut DepsT) {
+        // Requests that can't be placed right now stay in the queue, in order, but don't block
+        // the requests behind them that can be placed elsewhere. Once no worker has room for any
+        // of a client's requests, the rest of its queue is skipped. The most room is only needed
+        // then, so it's computed lazily, and again after each placement.
+        //
+        // Placing requests only ever takes room away from workers, so once a request can't be
+        // placed, no other request with the same requirements can be either, and we don't look
+        // for a worker for them. This doesn't go for retried requests, which avoid some workers.
+        let mut cursors = Cursors::default();
+        let mut most_room = None;
+        let mut unplaceable: Vec<Requirements> = vec![];
+        while let Some((key, eid, details)) = self.queued_requests.next(&cursors) {
+            match self.worker_heap.peek() {
+                Some(wid) if !self.workers.get(wid).unwrap().is_full() => {}
+                // Every worker is full, or there aren't any.
+                _ => break,
             }
 
-            let (eid, details) = self.queued_requests.pop_front().unwrap();
+            let known_unplaceable = unplaceable
+                .iter()
+                .any(|requirements| requirements.are_those_of(details));
+            let found = if known_unplaceable {
+                None
+            } else {
+                self.find_worker_for(eid, details)
+            };
+            let Some(wid) = found else {
+                if !known_unplaceable && !self.lost_workers.contains_key(&eid) {
+                    unplaceable.push(Requirements::of(details));
+                }
+                cursors.insert(eid.0, key);
+                let room = *most_room.get_or_insert_with(|| self.most_room());
+                self.queued_requests
+                    .skip_client_if_too_big(eid.0, room, &mut cursors);
+                continue;
+            };
+            most_room = None;
+            let worker = self.workers.get_mut(&wid).unwrap();
+
+            let (eid, details) = self.queued_requests.take(eid.0, key);
             deps.send_request_to_worker(
                 &mut worker.sender,
-                WorkerRequest::EnqueueExecution(eid, details.clone()),
+                WorkerRequest::EnqueueExecution(eid, Box::new(details.clone())),
             );
 
-            worker.pending.insert(eid, details);
+            worker.add_pending(eid, details);
             let heap_index = worker.heap_index;
             self.worker_heap.sift_down(&mut self.workers, heap_index);
         }
     }
 
-    fn receive_client_connected(&mut self, id: ClientId, sender: DepsT::ClientSender) {
+    fn receive_client_connected(&mut self, id: ClientId, weight: u32, sender: DepsT::ClientSender) {
         assert!(
             self.clients.insert(id, sender).is_none(),
             "duplicate client id {id:?}"
         );
+        self.queued_requests.add_client(id, weight);
     }
 
     fn receive_client_disconnected(&mut self, deps: &mut DepsT, id: ClientId) {
         assert!(self.clients.remove(&id).is_some());
-        self.queued_requests
-            .retain(|(ExecutionId(cid, _), _)| *cid != id);
+        self.queued_requests.remove_client(id);
+        self.lost_workers.retain(|eid, _| eid.0 != id);
         for worker in self.workers.values_mut() {
-            worker.pending.retain(|eid, _| {
+            worker.pending.retain(|eid, details| {
                 eid.0 != id || {
                     deps.send_request_to_worker(
                         &mut worker.sender,
                         WorkerRequest::CancelExecution(*eid),
                     );
+                    worker.pending_slots -= details.slots as usize;
                     false
                 }
             });
@@ -173,6 +542,24 @@ impl<DepsT: SchedulerDeps> Scheduler<DepsT> {
         details: ExecutionDetails,
     ) {
         assert!(self.clients.contains_key(&cid), "unknown client id {cid:?}");
+        let status = if details.slots == 0 {
+            Some(ExecutionStatus::Error(
+                "an execution must use at least one slot".to_string(),
+            ))
+        } else if has_requirements(&details)
+            && !self.workers.values().any(|worker| worker.can_run(&details))
+        {
+            Some(unplaceable_status(&details))
+        } else {
+            None
+        };
+        if let Some(status) = status {
+            deps.send_response_to_client(
+                self.clients.get_mut(&cid).unwrap(),
+                ClientResponse::ExecutionCompleted(ceid, status.into()),
+            );
+            return;
+        }
         self.queued_requests
             .push_back((ExecutionId(cid, ceid), details));
         self.possibly_start_executions(deps);
@@ -182,16 +569,26 @@ impl<DepsT: SchedulerDeps> Scheduler<DepsT> {
         &mut self,
         deps: &mut DepsT,
         id: WorkerId,
-        slots: usize,
+        info: WorkerInfo,
         sender: DepsT::WorkerSender,
     ) {
+        let WorkerInfo {
+            name,
+            slots,
+            memory,
+            labels,
+        } = info;
         let unique = self
             .workers
             .insert(
                 id,
                 Worker {
+                    name,
                     slots,
+                    memory,
+                    labels,
                     pending: HashMap::default(),
+                    pending_slots: 0,
                     heap_index: HeapIndex::default(),
                     sender,
                 },
@@ -207,16 +604,56 @@ impl<DepsT: SchedulerDeps> Scheduler<DepsT> {
         self.worker_heap
             .remove(&mut self.workers, worker.heap_index);
 
-        // We sort the requests to keep our tests deterministic.
+        // Retry the worker's executions, unless they have run out of retries. We sort the
+        // requests to keep our tests deterministic.
         let mut vec: Vec<_> = worker.pending.drain().collect();
         vec.sort_by_key(|x| x.0);
-        for x in vec.into_iter().rev() {
-            self.queued_requests.push_front(x);
+        for (eid, details) in vec.into_iter().rev() {
+            self.queued_requests.finished(eid, &details);
+            let lost = self.lost_workers.entry(eid).or_default();
+            lost.push(worker.name.clone());
+            if lost.len() <= details.retries as usize {
+                self.queued_requests.push_front((eid, details));
+                continue;
+            }
+            let attempts = lost.len() as u32;
+            self.lost_workers.remove(&eid);
+            deps.send_response_to_client(
+                self.clients.get_mut(&eid.0).unwrap(),
+                ClientResponse::ExecutionCompleted(
+                    eid.1,
+                    ExecutionResult {
+                        attempts,
+                        ..ExecutionStatus::Abandoned(attempts).into()
+                    },
+                ),
+            );
         }
 
+        self.fail_unplaceable_requests(deps);
         self.possibly_start_executions(deps);
     }
 
+    fn receive_worker_output(
+        &mut self,
+        deps: &mut DepsT,
+        wid: WorkerId,
+        eid: ExecutionId,
+        stream: OutputStream,
+        chunk: Vec<u8>,
+    ) {
+        let worker = self.workers.get(&wid).unwrap();
+
+        // As with responses, output for executions we no longer think the worker is running
+        // belongs to clients that have gone away. Just drop it.
+        if worker.pending.contains_key(&eid) {
+            deps.send_response_to_client(
+                self.clients.get_mut(&eid.0).unwrap(),
+                ClientResponse::ExecutionOutput(eid.1, stream, chunk),
+            );
+        }
+    }
+
     fn receive_worker_response(
         &mut self,
         deps: &mut DepsT,
@@ -225,33 +662,60 @@ impl<DepsT: SchedulerDeps> Scheduler<DepsT> {
         result: ExecutionResult,
     ) {
         let worker = self.workers.get_mut(&wid).unwrap();
+        let pending_slots = worker.pending_slots;
 
-        if worker.pending.remove(&eid).is_none() {
+        let Some(details) = worker.remove_pending(&eid) else {
             // This indicates that the client isn't around anymore. Just ignore this response from
             // the worker. When the client disconnected, we canceled all of the outstanding
             // requests and updated our version of the worker's pending requests.
             return;
-        }
+        };
+        self.queued_requests.finished(eid, &details);
 
+        let attempts = self
+            .lost_workers
+            .remove(&eid)
+            .map_or(1, |lost| lost.len() as u32 + 1);
         deps.send_response_to_client(
             self.clients.get_mut(&eid.0).unwrap(),
-            ClientResponse(eid.1, result),
+            ClientResponse::ExecutionCompleted(eid.1, ExecutionResult { attempts, ..result }),
         );
 
-        if let Some((eid, details)) = self.queued_requests.pop_front() {
-            // If there are any queued_requests, we can just pop one off of the front of
-            // the queue and not have to update the worker's used slot count or position in the
-            // workers list.
+        // Give the freed slots to the queued_requests this worker can run, in the order the queue
+        // gives them out. Usually this is just one request, which takes the slot that was just
+        // freed, so the worker's load and position in the workers list stay the same.
+        let mut cursors = Cursors::default();
+        while let Some((key, eid, details)) = self.queued_requests.next(&cursors) {
+            let worker = self.workers.get(&wid).unwrap();
+            if worker.is_full() {
+                break;
+            }
+            if !worker.can_run(details)
+                || !worker.has_room_for(details)
+                || self.workers_to_avoid(eid, details).contains(&worker.name)
+            {
+                cursors.insert(eid.0, key);
+                self.queued_requests
+                    .skip_client_if_too_big(eid.0, worker.room(), &mut cursors);
+                continue;
+            }
+            let worker = self.workers.get_mut(&wid).unwrap();
+            let (eid, details) = self.queued_requests.take(eid.0, key);
             deps.send_request_to_worker(
                 &mut worker.sender,
-                WorkerRequest::EnqueueExecution(eid, details.clone()),
+                WorkerRequest::EnqueueExecution(eid, Box::new(details.clone())),
             );
-            worker.pending.insert(eid, details);
-        } else {
-            // Since there are no queued_requests, we're going to have to update the
-            // worker's position in the workers list.
-            let heap_index = worker.heap_index;
-            self.worker_heap.sift_up(&mut self.workers, heap_index);
+            worker.add_pending(eid, details);
+        }
+
+        let worker = self.workers.get(&wid).unwrap();
+        let heap_index = worker.heap_index;
+        match worker.pending_slots.cmp(&pending_slots) {
+            std::cmp::Ordering::Less => self.worker_heap.sift_up(&mut self.workers, heap_index),
+            std::cmp::Ordering::Greater => {
+                self.worker_heap.sift_down(&mut self.workers, heap_index)
+            }
+            std::cmp::Ordering::Equal => {}
         }
     }
 }
@@ -336,6 +800,28 @@ mod tests {
         }
     }
 
+    const GIB: u64 = 1 << 30;
+
+    macro_rules! worker_info {
+        [$n:expr, $slots:expr] => {
+            worker_info![$n, $slots, labels![]]
+        };
+        [$n:expr, $slots:expr, $labels:expr] => {
+            WorkerInfo {
+                name: format!("worker-{}", $n),
+                slots: $slots,
+                memory: GIB,
+                labels: $labels,
+            }
+        };
+    }
+
+    macro_rules! retried_result {
+        [$n:tt, $attempts:expr] => {
+            ExecutionResult { attempts: $attempts, ..result![$n] }
+        };
+    }
+
     macro_rules! client_sender {
         [$n:expr] => { TestClientSender(cid![$n]) };
     }
@@ -359,15 +845,18 @@ mod tests {
 
     script_test! {
         message_from_known_client_ok,
-        ClientConnected(cid![1], client_sender![1]) => {};
-        FromClient(cid![1], ClientRequest(ceid![1], details![1])) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {};
     }
 
     #[test]
     #[should_panic]
     fn request_from_unknown_client_panics() {
         let mut fixture = Fixture::default();
-        fixture.receive_message(FromClient(cid![1], ClientRequest(ceid![1], details![1])));
+        fixture.receive_message(FromClient(
+            cid![1],
+            ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1])),
+        ));
     }
 
     #[test]
@@ -390,9 +879,12 @@ mod tests {
     fn response_from_unknown_worker_panics() {
         let mut fixture = Fixture::default();
         // The response will be ignored unless we use a valid ClientId.
-        fixture.receive_message(ClientConnected(cid![1], client_sender![1]));
+        fixture.receive_message(ClientConnected(cid![1], 1, client_sender![1]));
 
-        fixture.receive_message(FromWorker(wid![1], WorkerResponse(eid![1], result![1])));
+        fixture.receive_message(FromWorker(
+            wid![1],
+            WorkerResponse::ExecutionCompleted(eid![1], result![1]),
+        ));
     }
 
     #[test]
@@ -406,294 +898,302 @@ mod tests {
     #[should_panic]
     fn connect_from_duplicate_worker_panics() {
         let mut fixture = Fixture::default();
-        fixture.receive_message(WorkerConnected(wid![1], 2, worker_sender![1]));
-        fixture.receive_message(WorkerConnected(wid![1], 2, worker_sender![1]));
+        fixture.receive_message(WorkerConnected(
+            wid![1],
+            worker_info![1, 2],
+            worker_sender![1],
+        ));
+        fixture.receive_message(WorkerConnected(
+            wid![1],
+            worker_info![1, 2],
+            worker_sender![1],
+        ));
     }
 
     script_test! {
         response_from_known_worker_for_unknown_execution_ignored,
-        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
-        FromWorker(wid![1], WorkerResponse(eid![1], result![1])) => {};
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1], result![1])) => {};
     }
 
     script_test! {
         one_client_one_worker,
-        ClientConnected(cid![1], client_sender![1]) => {};
-        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
-        FromClient(cid![1], ClientRequest(ceid![1], details![1])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1], details![1])),
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1], Box::new(details![1]))),
         };
-        FromWorker(wid![1], WorkerResponse(eid![1], result![1])) => {
-            ToClient(cid![1], ClientResponse(ceid![1], result![1])),
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
         };
     }
 
     script_test! {
         response_from_worker_for_disconnected_client_ignored,
-        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
-        FromWorker(wid![1], WorkerResponse(eid![1], result![1])) => {};
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1], result![1])) => {};
     }
 
     script_test! {
         requests_go_to_workers_based_on_subscription_percentage,
-        WorkerConnected(wid![1], 2, worker_sender![1]) => {};
-        WorkerConnected(wid![2], 2, worker_sender![2]) => {};
-        WorkerConnected(wid![3], 3, worker_sender![3]) => {};
-        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        WorkerConnected(wid![2], worker_info![2, 2], worker_sender![2]) => {};
+        WorkerConnected(wid![3], worker_info![3, 3], worker_sender![3]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
 
         // 0/2 0/2 0/3
-        FromClient(cid![1], ClientRequest(ceid![1], details![1])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 1], details![1])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
         };
 
         // 1/2 0/2 0/3
-        FromClient(cid![1], ClientRequest(ceid![2], details![2])) => {
-            ToWorker(wid![2], EnqueueExecution(eid![1, 2], details![2])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
         };
 
         // 1/2 1/2 0/3
-        FromClient(cid![1], ClientRequest(ceid![3], details![3])) => {
-            ToWorker(wid![3], EnqueueExecution(eid![1, 3], details![3])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {
+            ToWorker(wid![3], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
         };
 
         // 1/2 1/2 1/3
-        FromClient(cid![1], ClientRequest(ceid![4], details![4])) => {
-            ToWorker(wid![3], EnqueueExecution(eid![1, 4], details![4])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {
+            ToWorker(wid![3], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
         };
 
         // 1/2 1/2 2/3
-        FromClient(cid![1], ClientRequest(ceid![5], details![5])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 5], details![5])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![5], Box::new(details![5]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 5], Box::new(details![5]))),
         };
 
         // 2/2 1/2 2/3
-        FromClient(cid![1], ClientRequest(ceid![6], details![6])) => {
-            ToWorker(wid![2], EnqueueExecution(eid![1, 6], details![6])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![6], Box::new(details![6]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 6], Box::new(details![6]))),
         };
 
         // 2/2 2/2 2/3
-        FromClient(cid![1], ClientRequest(ceid![7], details![7])) => {
-            ToWorker(wid![3], EnqueueExecution(eid![1, 7], details![7])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![7], Box::new(details![7]))) => {
+            ToWorker(wid![3], EnqueueExecution(eid![1, 7], Box::new(details![7]))),
         };
 
-        FromWorker(wid![1], WorkerResponse(eid![1, 1], result![1])) => {
-            ToClient(cid![1], ClientResponse(ceid![1], result![1])),
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
         };
-        FromClient(cid![1], ClientRequest(ceid![8], details![8])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 8], details![8])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![8], Box::new(details![8]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 8], Box::new(details![8]))),
         };
 
-        FromWorker(wid![2], WorkerResponse(eid![1, 2], result![2])) => {
-            ToClient(cid![1], ClientResponse(ceid![2], result![2])),
+        FromWorker(wid![2], WorkerResponse::ExecutionCompleted(eid![1, 2], result![2])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![2], result![2])),
         };
-        FromClient(cid![1], ClientRequest(ceid![9], details![9])) => {
-            ToWorker(wid![2], EnqueueExecution(eid![1, 9], details![9])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![9], Box::new(details![9]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 9], Box::new(details![9]))),
         };
 
-        FromWorker(wid![3], WorkerResponse(eid![1, 3], result![3])) => {
-            ToClient(cid![1], ClientResponse(ceid![3], result![3])),
+        FromWorker(wid![3], WorkerResponse::ExecutionCompleted(eid![1, 3], result![3])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![3], result![3])),
         };
-        FromClient(cid![1], ClientRequest(ceid![10], details![10])) => {
-            ToWorker(wid![3], EnqueueExecution(eid![1, 10], details![10])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![10], Box::new(details![10]))) => {
+            ToWorker(wid![3], EnqueueExecution(eid![1, 10], Box::new(details![10]))),
         };
     }
 
     script_test! {
         requests_start_queueing_at_2x_workers_slot_count,
-        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
-        WorkerConnected(wid![2], 1, worker_sender![2]) => {};
-        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        WorkerConnected(wid![2], worker_info![2, 1], worker_sender![2]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
 
         // 0/1 0/1
-        FromClient(cid![1], ClientRequest(ceid![1], details![1])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 1], details![1])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
         };
 
         // 1/1 0/1
-        FromClient(cid![1], ClientRequest(ceid![2], details![2])) => {
-            ToWorker(wid![2], EnqueueExecution(eid![1, 2], details![2])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
         };
 
         // 1/1 1/1
-        FromClient(cid![1], ClientRequest(ceid![3], details![3])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 3], details![3])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
         };
 
         // 2/1 1/1
-        FromClient(cid![1], ClientRequest(ceid![4], details![4])) => {
-            ToWorker(wid![2], EnqueueExecution(eid![1, 4], details![4])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
         };
 
         // 2/1 2/1
-        FromClient(cid![1], ClientRequest(ceid![5], details![5])) => {};
-        FromClient(cid![1], ClientRequest(ceid![6], details![6])) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![5], Box::new(details![5]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![6], Box::new(details![6]))) => {};
 
         // 2/2 1/2
-        FromWorker(wid![2], WorkerResponse(eid![1, 2], result![2])) => {
-            ToClient(cid![1], ClientResponse(ceid![2], result![2])),
-            ToWorker(wid![2], EnqueueExecution(eid![1, 5], details![5])),
+        FromWorker(wid![2], WorkerResponse::ExecutionCompleted(eid![1, 2], result![2])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![2], result![2])),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 5], Box::new(details![5]))),
         };
 
         // 1/2 2/2
-        FromWorker(wid![1], WorkerResponse(eid![1, 1], result![1])) => {
-            ToClient(cid![1], ClientResponse(ceid![1], result![1])),
-            ToWorker(wid![1], EnqueueExecution(eid![1, 6], details![6])),
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 6], Box::new(details![6]))),
         };
     }
 
     script_test! {
         queued_requests_go_to_workers_on_connect,
-        ClientConnected(cid![1], client_sender![1]) => {};
-
-        FromClient(cid![1], ClientRequest(ceid![1], details![1])) => {};
-        FromClient(cid![1], ClientRequest(ceid![2], details![2])) => {};
-        FromClient(cid![1], ClientRequest(ceid![3], details![3])) => {};
-        FromClient(cid![1], ClientRequest(ceid![4], details![4])) => {};
-        FromClient(cid![1], ClientRequest(ceid![5], details![5])) => {};
-        FromClient(cid![1], ClientRequest(ceid![6], details![6])) => {};
-
-        WorkerConnected(wid![1], 2, worker_sender![1]) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 1], details![1])),
-            ToWorker(wid![1], EnqueueExecution(eid![1, 2], details![2])),
-            ToWorker(wid![1], EnqueueExecution(eid![1, 3], details![3])),
-            ToWorker(wid![1], EnqueueExecution(eid![1, 4], details![4])),
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![5], Box::new(details![5]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![6], Box::new(details![6]))) => {};
+
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
         };
 
-        WorkerConnected(wid![2], 2, worker_sender![2]) => {
-            ToWorker(wid![2], EnqueueExecution(eid![1, 5], details![5])),
-            ToWorker(wid![2], EnqueueExecution(eid![1, 6], details![6])),
+        WorkerConnected(wid![2], worker_info![2, 2], worker_sender![2]) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 5], Box::new(details![5]))),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 6], Box::new(details![6]))),
         };
     }
 
     script_test! {
         requests_outstanding_on_disconnected_worker_get_sent_to_new_workers,
-        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
-        WorkerConnected(wid![2], 1, worker_sender![2]) => {};
-        WorkerConnected(wid![3], 1, worker_sender![3]) => {};
-        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        WorkerConnected(wid![2], worker_info![2, 1], worker_sender![2]) => {};
+        WorkerConnected(wid![3], worker_info![3, 1], worker_sender![3]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
 
-        FromClient(cid![1], ClientRequest(ceid![1], details![1])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 1], details![1])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
         };
 
-        FromClient(cid![1], ClientRequest(ceid![2], details![2])) => {
-            ToWorker(wid![2], EnqueueExecution(eid![1, 2], details![2])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
         };
 
-        FromClient(cid![1], ClientRequest(ceid![3], details![3])) => {
-            ToWorker(wid![3], EnqueueExecution(eid![1, 3], details![3])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {
+            ToWorker(wid![3], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
         };
 
-        FromClient(cid![1], ClientRequest(ceid![4], details![4])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 4], details![4])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
         };
 
-        FromClient(cid![1], ClientRequest(ceid![5], details![5])) => {
-            ToWorker(wid![2], EnqueueExecution(eid![1, 5], details![5])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![5], Box::new(details![5]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 5], Box::new(details![5]))),
         };
 
         WorkerDisconnected(wid![1]) => {
-            ToWorker(wid![3], EnqueueExecution(eid![1, 1], details![1])),
+            ToWorker(wid![3], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
         };
 
-        FromWorker(wid![2], WorkerResponse(eid![1, 2], result![2])) => {
-            ToClient(cid![1], ClientResponse(ceid![2], result![2])),
-            ToWorker(wid![2], EnqueueExecution(eid![1, 4], details![4])),
+        FromWorker(wid![2], WorkerResponse::ExecutionCompleted(eid![1, 2], result![2])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![2], result![2])),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
         };
     }
 
     script_test! {
         requests_outstanding_on_disconnected_worker_get_sent_to_new_workers_2,
-        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
-        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
 
-        FromClient(cid![1], ClientRequest(ceid![1], details![1])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 1], details![1])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
         };
 
-        FromClient(cid![1], ClientRequest(ceid![2], details![2])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 2], details![2])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
         };
 
-        FromClient(cid![1], ClientRequest(ceid![3], details![3])) => {};
-        FromClient(cid![1], ClientRequest(ceid![4], details![4])) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {};
 
-        FromWorker(wid![1], WorkerResponse(eid![1, 1], result![1])) => {
-            ToClient(cid![1], ClientResponse(ceid![1], result![1])),
-            ToWorker(wid![1], EnqueueExecution(eid![1, 3], details![3])),
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
         };
 
-        WorkerConnected(wid![2], 1, worker_sender![2]) => {
-            ToWorker(wid![2], EnqueueExecution(eid![1, 4], details![4])),
+        WorkerConnected(wid![2], worker_info![2, 1], worker_sender![2]) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
         };
 
         WorkerDisconnected(wid![1]) => {
-            ToWorker(wid![2], EnqueueExecution(eid![1, 2], details![2])),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
         };
 
-        FromWorker(wid![2], WorkerResponse(eid![1, 2], result![2])) => {
-            ToClient(cid![1], ClientResponse(ceid![2], result![2])),
-            ToWorker(wid![2], EnqueueExecution(eid![1, 3], details![3])),
+        FromWorker(wid![2], WorkerResponse::ExecutionCompleted(eid![1, 2], result![2])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![2], retried_result![2, 2])),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
         };
     }
 
     script_test! {
         requests_outstanding_on_disconnected_worker_go_to_head_of_queue_for_other_workers,
-        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
-        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
 
-        FromClient(cid![1], ClientRequest(ceid![1], details![1])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 1], details![1])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
         };
 
-        FromClient(cid![1], ClientRequest(ceid![2], details![2])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 2], details![2])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
         };
 
-        FromClient(cid![1], ClientRequest(ceid![3], details![3])) => {};
-        FromClient(cid![1], ClientRequest(ceid![4], details![4])) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {};
 
         WorkerDisconnected(wid![1]) => {};
 
-        WorkerConnected(wid![2], 1, worker_sender![2]) => {
-            ToWorker(wid![2], EnqueueExecution(eid![1, 1], details![1])),
-            ToWorker(wid![2], EnqueueExecution(eid![1, 2], details![2])),
+        WorkerConnected(wid![2], worker_info![2, 1], worker_sender![2]) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
         };
     }
 
     script_test! {
         requests_get_removed_from_workers_pending_map,
 
-        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
-        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
 
-        FromClient(cid![1], ClientRequest(ceid![1], details![1])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 1], details![1])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
         };
 
-        FromClient(cid![1], ClientRequest(ceid![2], details![2])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 2], details![2])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
         };
 
-        FromWorker(wid![1], WorkerResponse(eid![1, 1], result![1])) => {
-            ToClient(cid![1], ClientResponse(ceid![1], result![1])),
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
         };
 
-        FromWorker(wid![1], WorkerResponse(eid![1, 2], result![1])) => {
-            ToClient(cid![1], ClientResponse(ceid![2], result![1])),
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 2], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![2], result![1])),
         };
 
         WorkerDisconnected(wid![1]) => {};
-        WorkerConnected(wid![2], 1, worker_sender![2]) => {};
+        WorkerConnected(wid![2], worker_info![2, 1], worker_sender![2]) => {};
     }
 
     script_test! {
         client_disconnects_with_outstanding_work_1,
-        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
-        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
 
-        FromClient(cid![1], ClientRequest(ceid![1], details![1])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 1], details![1])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
         };
 
         ClientDisconnected(cid![1]) => {
@@ -703,91 +1203,736 @@ mod tests {
 
     script_test! {
         client_disconnects_with_outstanding_work_2,
-        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
-        WorkerConnected(wid![2], 1, worker_sender![2]) => {};
-        ClientConnected(cid![1], client_sender![1]) => {};
-        ClientConnected(cid![2], client_sender![2]) => {};
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        WorkerConnected(wid![2], worker_info![2, 1], worker_sender![2]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        ClientConnected(cid![2], 1, client_sender![2]) => {};
 
-        FromClient(cid![1], ClientRequest(ceid![1], details![1])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 1], details![1])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
         };
 
-        FromClient(cid![2], ClientRequest(ceid![1], details![1])) => {
-            ToWorker(wid![2], EnqueueExecution(eid![2, 1], details![1])),
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![2, 1], Box::new(details![1]))),
         };
 
         //ClientDisconnected(cid![2]) => {
         //    ToWorker(wid![2], CancelExecution(eid![2, 1])),
         //};
 
-        //FromClient(cid![1], ClientRequest(ceid![2], details![2])) => {
-        //    ToWorker(wid![2], EnqueueExecution(eid![1, 2], details![2])),
+        //FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+        //    ToWorker(wid![2], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
         //};
     }
 
     script_test! {
         client_disconnects_with_outstanding_work_3,
-        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
-        ClientConnected(cid![1], client_sender![1]) => {};
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
 
-        FromClient(cid![1], ClientRequest(ceid![1], details![1])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 1], details![1])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
         };
 
-        FromClient(cid![1], ClientRequest(ceid![2], details![2])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 2], details![2])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
         };
 
-        ClientConnected(cid![2], client_sender![2]) => {};
-        FromClient(cid![2], ClientRequest(ceid![1], details![1])) => {};
-        FromClient(cid![1], ClientRequest(ceid![3], details![3])) => {};
+        ClientConnected(cid![2], 1, client_sender![2]) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {};
 
         ClientDisconnected(cid![2]) => {};
 
-        FromWorker(wid![1], WorkerResponse(eid![1, 1], result![1])) => {
-            ToClient(cid![1], ClientResponse(ceid![1], result![1])),
-            ToWorker(wid![1], EnqueueExecution(eid![1, 3], details![3])),
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
         };
     }
 
     script_test! {
         client_disconnects_with_outstanding_work_4,
-        WorkerConnected(wid![1], 1, worker_sender![1]) => {};
-        WorkerConnected(wid![2], 1, worker_sender![2]) => {};
-        ClientConnected(cid![1], client_sender![1]) => {};
-        ClientConnected(cid![2], client_sender![2]) => {};
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        WorkerConnected(wid![2], worker_info![2, 1], worker_sender![2]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        ClientConnected(cid![2], 1, client_sender![2]) => {};
 
-        FromClient(cid![1], ClientRequest(ceid![1], details![1])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![1, 1], details![1])),
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
         };
 
-        FromClient(cid![2], ClientRequest(ceid![1], details![1])) => {
-            ToWorker(wid![2], EnqueueExecution(eid![2, 1], details![1])),
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![2, 1], Box::new(details![1]))),
         };
 
-        FromClient(cid![2], ClientRequest(ceid![2], details![2])) => {
-            ToWorker(wid![1], EnqueueExecution(eid![2, 2], details![2])),
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![2, 2], Box::new(details![2]))),
         };
 
-        FromClient(cid![2], ClientRequest(ceid![3], details![3])) => {
-            ToWorker(wid![2], EnqueueExecution(eid![2, 3], details![3])),
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![2, 3], Box::new(details![3]))),
         };
 
-        FromClient(cid![2], ClientRequest(ceid![4], details![4])) => {};
-        FromClient(cid![1], ClientRequest(ceid![2], details![2])) => {};
-        FromClient(cid![2], ClientRequest(ceid![5], details![5])) => {};
-        FromClient(cid![1], ClientRequest(ceid![3], details![3])) => {};
-        FromClient(cid![1], ClientRequest(ceid![4], details![4])) => {};
-        FromClient(cid![1], ClientRequest(ceid![5], details![5])) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![5], Box::new(details![5]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![5], Box::new(details![5]))) => {};
 
         ClientDisconnected(cid![2]) => {
             ToWorker(wid![2], CancelExecution(eid![2, 1])),
             ToWorker(wid![1], CancelExecution(eid![2, 2])),
             ToWorker(wid![2], CancelExecution(eid![2, 3])),
 
-            ToWorker(wid![2], EnqueueExecution(eid![1, 2], details![2])),
-            ToWorker(wid![1], EnqueueExecution(eid![1, 3], details![3])),
-            ToWorker(wid![2], EnqueueExecution(eid![1, 4], details![4])),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
+        };
+    }
+
+    script_test! {
+        output_forwarded_to_client,
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1], Box::new(details![1]))),
+        };
+        FromWorker(wid![1], WorkerResponse::ExecutionOutput(eid![1], OutputStream::Stdout, b"out".to_vec())) => {
+            ToClient(cid![1], ClientResponse::ExecutionOutput(ceid![1], OutputStream::Stdout, b"out".to_vec())),
+        };
+        FromWorker(wid![1], WorkerResponse::ExecutionOutput(eid![1], OutputStream::Stderr, b"err".to_vec())) => {
+            ToClient(cid![1], ClientResponse::ExecutionOutput(ceid![1], OutputStream::Stderr, b"err".to_vec())),
+        };
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
+        };
+    }
+
+    script_test! {
+        output_for_disconnected_client_ignored,
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1], Box::new(details![1]))),
+        };
+        ClientDisconnected(cid![1]) => {
+            ToWorker(wid![1], CancelExecution(eid![1])),
+        };
+        FromWorker(wid![1], WorkerResponse::ExecutionOutput(eid![1], OutputStream::Stdout, b"out".to_vec())) => {};
+    }
+
+    macro_rules! gpu_details {
+        [$n:expr] => {
+            ExecutionDetails {
+                required_labels: labels!["gpu" => "yes"],
+                ..details![$n]
+            }
+        };
+    }
+
+    macro_rules! arch_details {
+        [$n:expr] => {
+            ExecutionDetails {
+                required_labels: labels!["arch" => "aarch64"],
+                ..details![$n]
+            }
+        };
+    }
+
+    fn no_gpu_result() -> ExecutionResult {
+        ExecutionStatus::Error("no connected worker has the labels gpu=yes".to_string()).into()
+    }
+
+    script_test! {
+        labelled_request_goes_to_matching_worker,
+        WorkerConnected(wid![1], worker_info![1, 1, labels!["gpu" => "no"]], worker_sender![1]) => {};
+        WorkerConnected(wid![2], worker_info![2, 1, labels!["gpu" => "yes"]], worker_sender![2]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(gpu_details![1]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 1], Box::new(gpu_details![1]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(gpu_details![2]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 2], Box::new(gpu_details![2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
+        };
+    }
+
+    script_test! {
+        labelled_request_goes_to_least_loaded_matching_worker,
+        WorkerConnected(wid![1], worker_info![1, 1, labels!["gpu" => "yes"]], worker_sender![1]) => {};
+        WorkerConnected(wid![2], worker_info![2, 1], worker_sender![2]) => {};
+        WorkerConnected(wid![3], worker_info![3, 2, labels!["gpu" => "yes", "arch" => "aarch64"]], worker_sender![3]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(gpu_details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(gpu_details![1]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(gpu_details![2]))) => {
+            ToWorker(wid![3], EnqueueExecution(eid![1, 2], Box::new(gpu_details![2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(gpu_details![3]))) => {
+            ToWorker(wid![3], EnqueueExecution(eid![1, 3], Box::new(gpu_details![3]))),
+        };
+    }
+
+    script_test! {
+        labelled_request_with_no_matching_worker_fails,
+        WorkerConnected(wid![1], worker_info![1, 1, labels!["gpu" => "no"]], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(gpu_details![1]))) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], no_gpu_result())),
+        };
+    }
+
+    script_test! {
+        labelled_request_with_no_workers_fails,
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(gpu_details![1]))) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], no_gpu_result())),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {};
+    }
+
+    script_test! {
+        queued_labelled_request_does_not_block_requests_behind_it,
+        WorkerConnected(wid![1], worker_info![1, 1, labels!["gpu" => "yes"]], worker_sender![1]) => {};
+        WorkerConnected(wid![2], worker_info![2, 1], worker_sender![2]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(gpu_details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(gpu_details![1]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(gpu_details![2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(gpu_details![2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(gpu_details![3]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![5], Box::new(details![5]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 5], Box::new(details![5]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![6], Box::new(details![6]))) => {};
+
+        FromWorker(wid![2], WorkerResponse::ExecutionCompleted(eid![1, 4], result![4])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![4], result![4])),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 6], Box::new(details![6]))),
+        };
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(gpu_details![3]))),
+        };
+    }
+
+    script_test! {
+        queued_requests_with_other_labels_placed_behind_unplaceable_ones,
+        WorkerConnected(wid![1], worker_info![1, 1, labels!["gpu" => "yes"]], worker_sender![1]) => {};
+        WorkerConnected(wid![2], worker_info![2, 1, labels!["arch" => "aarch64"]], worker_sender![2]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(gpu_details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(gpu_details![1]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(gpu_details![2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(gpu_details![2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(gpu_details![3]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(gpu_details![4]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![5], Box::new(arch_details![5]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 5], Box::new(arch_details![5]))),
+        };
+
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(gpu_details![3]))),
+        };
+    }
+
+    script_test! {
+        queued_labelled_requests_fail_when_last_matching_worker_disconnects,
+        WorkerConnected(wid![1], worker_info![1, 1, labels!["gpu" => "yes"]], worker_sender![1]) => {};
+        WorkerConnected(wid![2], worker_info![2, 1], worker_sender![2]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(gpu_details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(gpu_details![1]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(gpu_details![2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(gpu_details![2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(gpu_details![3]))) => {};
+
+        WorkerDisconnected(wid![1]) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], no_gpu_result())),
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![2], no_gpu_result())),
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![3], no_gpu_result())),
+        };
+    }
+
+    macro_rules! slots_details {
+        [$n:expr, $slots:expr] => {
+            ExecutionDetails { slots: $slots, ..details![$n] }
+        };
+    }
+
+    script_test! {
+        smaller_requests_placed_around_ones_without_room,
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(slots_details![1, 2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(slots_details![1, 2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(slots_details![3, 2]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
+        };
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 2], result![2])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![2], result![2])),
+        };
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(slots_details![3, 2]))),
+        };
+    }
+
+    script_test! {
+        multi_slot_requests_weigh_worker_load,
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        WorkerConnected(wid![2], worker_info![2, 2], worker_sender![2]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+
+        // 0/2 0/2
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(slots_details![1, 2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(slots_details![1, 2]))),
+        };
+
+        // 2/2 0/2
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
+        };
+
+        // 2/2 1/2
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
+        };
+
+        // 2/2 2/2
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
+        };
+
+        // 3/2 2/2
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
+        };
+
+        // 1/2 2/2
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![5], Box::new(details![5]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 5], Box::new(details![5]))),
+        };
+    }
+
+    script_test! {
+        multi_slot_request_waits_for_room_on_worker,
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(slots_details![4, 2]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![5], Box::new(details![5]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 5], Box::new(details![5]))),
+        };
+
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
+        };
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 2], result![2])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![2], result![2])),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 4], Box::new(slots_details![4, 2]))),
+        };
+    }
+
+    script_test! {
+        completed_multi_slot_request_makes_room_for_several,
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(slots_details![1, 2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(slots_details![1, 2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(slots_details![2, 2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(slots_details![2, 2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {};
+
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
+        };
+    }
+
+    script_test! {
+        request_needing_more_slots_than_any_worker_has_fails,
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(slots_details![1, 3]))) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(
+                ceid![1],
+                ExecutionStatus::Error("no connected worker has 3 slots".to_string()).into(),
+            )),
+        };
+    }
+
+    script_test! {
+        request_needing_more_memory_than_any_worker_has_fails,
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(
+            ceid![1],
+            Box::new(ExecutionDetails { memory: Some(2 * GIB), ..details![1] }),
+        )) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(
+                ceid![1],
+                ExecutionStatus::Error(
+                    "no connected worker has 2147483648 bytes of memory".to_string()
+                ).into(),
+            )),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(
+            ceid![2],
+            Box::new(ExecutionDetails { memory: Some(GIB), ..details![2] }),
+        )) => {
+            ToWorker(wid![1], EnqueueExecution(
+                eid![1, 2],
+                Box::new(ExecutionDetails { memory: Some(GIB), ..details![2] }),
+            )),
+        };
+    }
+
+    script_test! {
+        request_needing_no_slots_fails,
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(slots_details![1, 0]))) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(
+                ceid![1],
+                ExecutionStatus::Error("an execution must use at least one slot".to_string())
+                    .into(),
+            )),
+        };
+    }
+
+    macro_rules! priority_details {
+        [$n:expr, $priority:expr] => {
+            ExecutionDetails { priority: $priority, ..details![$n] }
+        };
+    }
+
+    script_test! {
+        higher_priority_requests_from_any_client_go_first,
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        ClientConnected(cid![2], 1, client_sender![2]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![1], Box::new(priority_details![1, 10]))) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![2], Box::new(priority_details![2, 10]))) => {};
+
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
+            ToWorker(wid![1], EnqueueExecution(eid![2, 1], Box::new(priority_details![1, 10]))),
+        };
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 2], result![2])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![2], result![2])),
+            ToWorker(wid![1], EnqueueExecution(eid![2, 2], Box::new(priority_details![2, 10]))),
+        };
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![2, 1], result![1])) => {
+            ToClient(cid![2], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
+        };
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![2, 2], result![2])) => {
+            ToClient(cid![2], ClientResponse::ExecutionCompleted(ceid![2], result![2])),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
+        };
+    }
+
+    script_test! {
+        requests_with_same_priority_from_different_clients_go_in_order,
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        ClientConnected(cid![2], 1, client_sender![2]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(priority_details![1, 5]))) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![1], Box::new(priority_details![1, 5]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![2], Box::new(priority_details![2, 5]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(priority_details![3, -5]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(priority_details![4, 5]))) => {};
+
+        WorkerConnected(wid![1], worker_info![1, 3], worker_sender![1]) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(priority_details![1, 5]))),
+            ToWorker(wid![1], EnqueueExecution(eid![2, 1], Box::new(priority_details![1, 5]))),
+            ToWorker(wid![1], EnqueueExecution(eid![2, 2], Box::new(priority_details![2, 5]))),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 4], Box::new(priority_details![4, 5]))),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(priority_details![3, -5]))),
+        };
+    }
+
+    script_test! {
+        requests_from_disconnected_worker_requeued_behind_higher_priority_requests,
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        ClientConnected(cid![2], 1, client_sender![2]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![1], Box::new(priority_details![1, 1]))) => {};
+
+        WorkerDisconnected(wid![1]) => {};
+
+        WorkerConnected(wid![2], worker_info![2, 2], worker_sender![2]) => {
+            ToWorker(wid![2], EnqueueExecution(eid![2, 1], Box::new(priority_details![1, 1]))),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
+        };
+    }
+
+    script_test! {
+        flooding_client_does_not_starve_other_clients,
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        ClientConnected(cid![2], 1, client_sender![2]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![5], Box::new(details![5]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![6], Box::new(details![6]))) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {};
+
+        // Client 1 has 3 slots, client 2 has none.
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
+            ToWorker(wid![1], EnqueueExecution(eid![2, 1], Box::new(details![1]))),
+        };
+
+        // Client 1 has 2 slots, client 2 has 1.
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 2], result![2])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![2], result![2])),
+            ToWorker(wid![1], EnqueueExecution(eid![2, 2], Box::new(details![2]))),
+        };
+
+        // Client 2 has nothing left queued.
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![1, 3], result![3])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![3], result![3])),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 5], Box::new(details![5]))),
+        };
+    }
+
+    script_test! {
+        clients_share_workers_in_proportion_to_weight,
+        ClientConnected(cid![1], 2, client_sender![1]) => {};
+        ClientConnected(cid![2], 1, client_sender![2]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![5], Box::new(details![5]))) => {};
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![6], Box::new(details![6]))) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {};
+
+        WorkerConnected(wid![1], worker_info![1, 3], worker_sender![1]) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
+            ToWorker(wid![1], EnqueueExecution(eid![2, 1], Box::new(details![1]))),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
+            ToWorker(wid![1], EnqueueExecution(eid![2, 2], Box::new(details![2]))),
+            ToWorker(wid![1], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
+        };
+    }
+
+    script_test! {
+        fair_share_counts_slots_not_executions,
+        WorkerConnected(wid![1], worker_info![1, 2], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+        ClientConnected(cid![2], 1, client_sender![2]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(slots_details![1, 2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(slots_details![1, 2]))),
+        };
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![2, 1], Box::new(details![1]))),
+        };
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![2, 2], Box::new(details![2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {};
+        FromClient(cid![2], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {};
+
+        // Each client has one execution running, but client 1's uses more slots, so client 2's
+        // request goes first even though client 1's is older.
+        FromWorker(wid![1], WorkerResponse::ExecutionCompleted(eid![2, 1], result![1])) => {
+            ToClient(cid![2], ClientResponse::ExecutionCompleted(ceid![1], result![1])),
+            ToWorker(wid![1], EnqueueExecution(eid![2, 3], Box::new(details![3]))),
+        };
+    }
+
+    macro_rules! retries_details {
+        [$n:expr, $retries:expr] => {
+            ExecutionDetails { retries: $retries, ..details![$n] }
+        };
+    }
+
+    fn abandoned_result(lost: u32) -> ExecutionResult {
+        ExecutionResult {
+            attempts: lost,
+            ..ExecutionStatus::Abandoned(lost).into()
+        }
+    }
+
+    script_test! {
+        retried_executions_avoid_workers_that_lost_them,
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        WorkerConnected(wid![2], worker_info![2, 1], worker_sender![2]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![4], Box::new(details![4]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 4], Box::new(details![4]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![5], Box::new(details![5]))) => {};
+
+        WorkerDisconnected(wid![1]) => {};
+
+        // The same worker reconnects. It only gets the execution that it didn't lose, even though
+        // the others are ahead of it in the queue.
+        WorkerConnected(wid![3], worker_info![1, 1], worker_sender![3]) => {
+            ToWorker(wid![3], EnqueueExecution(eid![1, 5], Box::new(details![5]))),
+        };
+
+        FromWorker(wid![2], WorkerResponse::ExecutionCompleted(eid![1, 2], result![2])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![2], result![2])),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
+        };
+
+        FromWorker(wid![2], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], retried_result![1, 2])),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
+        };
+    }
+
+    script_test! {
+        retried_execution_goes_back_to_same_worker_if_no_other_can_run_it,
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(details![1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
+        };
+
+        WorkerDisconnected(wid![1]) => {};
+
+        WorkerConnected(wid![2], worker_info![1, 1], worker_sender![2]) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 1], Box::new(details![1]))),
+        };
+
+        FromWorker(wid![2], WorkerResponse::ExecutionCompleted(eid![1, 1], result![1])) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], retried_result![1, 2])),
+        };
+    }
+
+    script_test! {
+        execution_abandoned_after_losing_more_workers_than_retries,
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(retries_details![1, 1]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(retries_details![1, 1]))),
+        };
+
+        WorkerConnected(wid![2], worker_info![2, 1], worker_sender![2]) => {};
+
+        WorkerDisconnected(wid![1]) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 1], Box::new(retries_details![1, 1]))),
+        };
+
+        WorkerDisconnected(wid![2]) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], abandoned_result(2))),
+        };
+
+        // The abandoned execution isn't sent to new workers.
+        WorkerConnected(wid![3], worker_info![3, 1], worker_sender![3]) => {};
+    }
+
+    script_test! {
+        execution_without_retries_abandoned_after_losing_one_worker,
+        WorkerConnected(wid![1], worker_info![1, 1], worker_sender![1]) => {};
+        WorkerConnected(wid![2], worker_info![2, 1], worker_sender![2]) => {};
+        ClientConnected(cid![1], 1, client_sender![1]) => {};
+
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![1], Box::new(retries_details![1, 0]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 1], Box::new(retries_details![1, 0]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![2], Box::new(details![2]))) => {
+            ToWorker(wid![2], EnqueueExecution(eid![1, 2], Box::new(details![2]))),
+        };
+        FromClient(cid![1], ClientRequest::EnqueueExecution(ceid![3], Box::new(details![3]))) => {
+            ToWorker(wid![1], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
+        };
+
+        WorkerDisconnected(wid![1]) => {
+            ToClient(cid![1], ClientResponse::ExecutionCompleted(ceid![1], abandoned_result(1))),
+            ToWorker(wid![2], EnqueueExecution(eid![1, 3], Box::new(details![3]))),
         };
     }
 }
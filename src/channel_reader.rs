@@ -20,7 +20,7 @@ mod tests {
         let (_, rx) = tokio::sync::mpsc::unbounded_channel::<u8>();
         let mut vec = vec![];
         run(rx, |s| vec.push(s)).await;
-        assert_eq!(vec, vec![]);
+        assert_eq!(vec, Vec::<u8>::new());
     }
 
     #[tokio::test]
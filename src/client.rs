@@ -1,18 +1,95 @@
 //! Code for the client binary.
 
-use crate::{proto, ClientExecutionId, ExecutionDetails, Result};
-use std::collections::HashMap;
+use crate::{
+    auth::SharedKey, proto, tls, CapturedOutput, ClientExecutionId, Error, ExecutionDetails,
+    ExecutionStatus, ResourceUsage, Result,
+};
+use serde::Deserialize;
+use std::{collections::HashMap, path::PathBuf};
 
-async fn get_test_binaries() -> Result<Vec<String>> {
+/// How much of each failing test's stdout and stderr to print.
+const CAPTURED_OUTPUT_LIMIT: u64 = 64 * 1024;
+
+fn print_captured_output(output: &CapturedOutput) {
+    if let CapturedOutput::Truncated { truncated, .. } = output {
+        println!("[... {truncated} bytes truncated ...]");
+    }
+    print!("{}", String::from_utf8_lossy(output.bytes()));
+}
+
+/// How many tests to list in each of the slowest and most memory-hungry summaries.
+const SUMMARY_LENGTH: usize = 5;
+
+fn format_resource_usage(usage: &ResourceUsage) -> String {
+    let mut formatted = format!(
+        "{:.3}s wall, {:.3}s user, {:.3}s sys, {} KiB max RSS",
+        usage.wall_time.as_secs_f64(),
+        usage.user_time.as_secs_f64(),
+        usage.system_time.as_secs_f64(),
+        usage.max_rss / 1024,
+    );
+    if let Some(peak_memory) = usage.peak_memory {
+        formatted += &format!(", {} KiB peak memory", peak_memory / 1024);
+    }
+    formatted
+}
+
+/// Print the `SUMMARY_LENGTH` tests with the largest `key`, largest first.
+fn print_summary<K: Ord>(
+    title: &str,
+    usages: &mut [(String, ResourceUsage)],
+    key: impl Fn(&ResourceUsage) -> K,
+) {
+    if usages.is_empty() {
+        return;
+    }
+    usages.sort_by_key(|(_, usage)| std::cmp::Reverse(key(usage)));
+    println!("{title}:");
+    for (case, usage) in usages.iter().take(SUMMARY_LENGTH) {
+        println!("  {case}: {}", format_resource_usage(usage));
+    }
+}
+
+/// The parts we care about of one of the JSON messages printed by `cargo --message-format=json`.
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    #[serde(default)]
+    executable: Option<String>,
+    #[serde(default)]
+    manifest_path: Option<PathBuf>,
+    #[serde(default)]
+    profile: Option<CargoProfile>,
+}
+
+#[derive(Deserialize)]
+struct CargoProfile {
+    test: bool,
+}
+
+/// Parse the output of `cargo test --no-run --message-format=json`, returning each test binary
+/// along with the directory containing its package's manifest.
+fn parse_test_binaries(output: &str) -> Vec<(String, PathBuf)> {
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<CargoMessage>(line).ok())
+        .filter(|message| message.reason == "compiler-artifact")
+        .filter(|message| message.profile.as_ref().is_some_and(|profile| profile.test))
+        .filter_map(|message| {
+            let package_dir = message.manifest_path?.parent()?.to_path_buf();
+            Some((message.executable?, package_dir))
+        })
+        .collect()
+}
+
+async fn get_test_binaries() -> Result<Vec<(String, PathBuf)>> {
     let output = tokio::process::Command::new("cargo")
         .arg("test")
         .arg("--no-run")
+        .arg("--message-format=json")
         .output()
         .await?;
-    Ok(regex::Regex::new(r"Executable unittests.*\((.*)\)")?
-        .captures_iter(std::str::from_utf8(&output.stderr)?)
-        .map(|capture| capture.get(1).unwrap().as_str().to_string())
-        .collect())
+    Ok(parse_test_binaries(std::str::from_utf8(&output.stdout)?))
 }
 
 async fn get_cases_from_binary(binary: &str) -> Result<Vec<String>> {
@@ -22,49 +99,189 @@ async fn get_cases_from_binary(binary: &str) -> Result<Vec<String>> {
         .arg("terse")
         .output()
         .await?;
-    Ok(regex::Regex::new(r"\b([^ ]*): test")?
-        .captures_iter(std::str::from_utf8(&output.stdout)?)
+    Ok(parse_test_cases(std::str::from_utf8(&output.stdout)?))
+}
+
+/// Parse the output of running a test binary with `--list --format terse`.
+fn parse_test_cases(output: &str) -> Vec<String> {
+    regex::Regex::new(r"(?m)^(\S+): test$")
+        .unwrap()
+        .captures_iter(output)
         .map(|capture| capture.get(1).unwrap().as_str().to_string())
-        .collect())
+        .collect()
 }
 
 /// The main function for the client. This should be called on a task of its own. It will return
-/// when a signal is received or when all work has been processed by the broker.
-pub async fn main(name: String, broker_addr: std::net::SocketAddr) -> Result<()> {
+/// when a signal is received, when all work has been processed by the broker, or when the
+/// connection to the broker fails, including when the broker misses too many heartbeats. The
+/// `key`, if provided, is used to answer the broker's authentication challenge. If `tls` is
+/// provided, the connection to the broker uses TLS.
+///
+/// Every test is run with the settings in `template`. Its `program` and `arguments` are replaced
+/// for each test. Like `cargo test`, each test is run in its package's directory, with
+/// `CARGO_MANIFEST_DIR` set to that directory. If the template asks for output to be streamed, a
+/// failing test's whole output is printed, with stdout and stderr interleaved in the order they
+/// arrived. Otherwise, the tails of its stdout and stderr are.
+pub async fn main(
+    name: String,
+    broker_addr: std::net::SocketAddr,
+    key: Option<SharedKey>,
+    tls: Option<tls::ClientOptions>,
+    template: ExecutionDetails,
+) -> Result<()> {
+    let connector = tls::Connector::new(tls.as_ref())?;
     let mut pairs = vec![];
-    for binary in get_test_binaries().await? {
+    for (binary, package_dir) in get_test_binaries().await? {
         for case in get_cases_from_binary(&binary).await? {
-            pairs.push((binary.clone(), case))
+            pairs.push((binary.clone(), package_dir.clone(), case))
         }
     }
-    let (read_stream, mut write_stream) = tokio::net::TcpStream::connect(&broker_addr)
-        .await?
-        .into_split();
+    let (read_stream, mut write_stream) = tokio::io::split(connector.connect(&broker_addr).await?);
     let mut read_stream = tokio::io::BufReader::new(read_stream);
 
-    proto::write_message(&mut write_stream, proto::Hello::Client { name }).await?;
+    let heartbeat = proto::send_hello(
+        &mut read_stream,
+        &mut write_stream,
+        proto::Hello::new(proto::Peer::Client { name }),
+        key.as_ref(),
+    )
+    .await?;
+
+    let (request_sender, request_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (response_sender, mut response_receiver) = tokio::sync::mpsc::unbounded_channel();
+
+    let mut join_set = tokio::task::JoinSet::new();
+    join_set.spawn(proto::socket_reader(
+        read_stream,
+        proto::DEFAULT_MAX_FRAME_SIZE,
+        heartbeat,
+        response_sender,
+        |response: proto::ClientResponse| response,
+    ));
+    join_set.spawn(proto::socket_writer(
+        request_receiver,
+        write_stream,
+        heartbeat,
+    ));
+
     let mut map = HashMap::new();
-    for (id, (binary, case)) in pairs.into_iter().enumerate() {
+    let mut usages = vec![];
+    for (id, (binary, package_dir, case)) in pairs.into_iter().enumerate() {
         let id = ClientExecutionId(id as u32);
-        map.insert(id, case.clone());
-        proto::write_message(
-            &mut write_stream,
-            proto::ClientRequest(
+        map.insert(id, (case.clone(), vec![]));
+        let mut environment = template.environment.clone();
+        environment.insert(
+            "CARGO_MANIFEST_DIR".to_string(),
+            package_dir.to_string_lossy().into_owned(),
+        );
+        request_sender
+            .send(proto::ClientRequest::EnqueueExecution(
                 id,
-                ExecutionDetails {
+                Box::new(ExecutionDetails {
                     program: binary,
                     arguments: vec!["--exact".to_string(), case],
-                },
-            ),
-        )
-        .await?;
+                    environment,
+                    working_directory: Some(package_dir),
+                    stdout_limit: (!template.stream_output).then_some(CAPTURED_OUTPUT_LIMIT),
+                    stderr_limit: (!template.stream_output).then_some(CAPTURED_OUTPUT_LIMIT),
+                    ..template.clone()
+                }),
+            ))
+            .ok();
     }
 
     while !map.is_empty() {
-        let proto::ClientResponse(id, result) = proto::read_message(&mut read_stream).await?;
-        let case = map.remove(&id).unwrap();
-        println!("{case}: {result:?}");
+        match response_receiver.recv().await {
+            Some(proto::ClientResponse::ExecutionOutput(id, _, chunk)) => {
+                // Output from stdout and stderr is kept interleaved in the order it arrived.
+                if let Some((_, output)) = map.get_mut(&id) {
+                    output.extend(chunk);
+                }
+            }
+            Some(proto::ClientResponse::ExecutionCompleted(id, result)) => {
+                let (case, output) = map.remove(&id).unwrap();
+                let attempts = match result.attempts {
+                    1 => String::new(),
+                    attempts => format!(" after {attempts} attempts"),
+                };
+                let status = match result.termination {
+                    None => result.status.to_string(),
+                    Some(termination) => format!("{}, {termination}", result.status),
+                };
+                match &result.resource_usage {
+                    None => println!("{case}: {status}{attempts}"),
+                    Some(usage) => println!(
+                        "{case}: {status}{attempts} ({})",
+                        format_resource_usage(usage)
+                    ),
+                }
+                if result.status != ExecutionStatus::Exited(0) {
+                    if template.stream_output {
+                        print!("{}", String::from_utf8_lossy(&output));
+                    } else {
+                        print_captured_output(&result.stdout);
+                        print_captured_output(&result.stderr);
+                    }
+                }
+                if let Some(usage) = result.resource_usage {
+                    usages.push((case, usage));
+                }
+            }
+            Some(proto::ClientResponse::Heartbeat) => {}
+            None => {
+                // The reader only stops early if it hits an error, which we want to report.
+                while let Some(result) = join_set.join_next().await {
+                    result.expect("no task should panic or be canceled")?;
+                }
+                return Err(Error::msg("connection to broker closed unexpectedly"));
+            }
+        }
     }
 
+    print_summary("Slowest tests", &mut usages, |usage| usage.wall_time);
+    print_summary("Most memory-hungry tests", &mut usages, |usage| {
+        usage.peak_memory.unwrap_or(usage.max_rss)
+    });
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test_binaries_keeps_only_test_executables() {
+        let output = [
+            r#"{"reason":"compiler-artifact","manifest_path":"/src/foo/Cargo.toml","profile":{"test":true},"executable":"/src/target/debug/deps/foo-1234"}"#,
+            r#"{"reason":"compiler-artifact","manifest_path":"/src/foo/Cargo.toml","profile":{"test":false},"executable":null}"#,
+            r#"{"reason":"compiler-artifact","manifest_path":"/src/bar/Cargo.toml","profile":{"test":false},"executable":"/src/target/debug/bar"}"#,
+            r#"{"reason":"compiler-message","message":{}}"#,
+            r#"{"reason":"compiler-artifact","manifest_path":"/src/bar/Cargo.toml","profile":{"test":true},"executable":"/src/target/debug/deps/bar-5678"}"#,
+            r#"{"reason":"build-finished","success":true}"#,
+            "not json",
+        ]
+        .join("\n");
+        assert_eq!(
+            parse_test_binaries(&output),
+            vec![
+                (
+                    "/src/target/debug/deps/foo-1234".to_string(),
+                    PathBuf::from("/src/foo")
+                ),
+                (
+                    "/src/target/debug/deps/bar-5678".to_string(),
+                    PathBuf::from("/src/bar")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_test_cases_one_per_line() {
+        assert_eq!(
+            parse_test_cases("foo::tests::a: test\nbar: test\nbaz: benchmark\n"),
+            vec!["foo::tests::a".to_string(), "bar".to_string()]
+        );
+    }
+}
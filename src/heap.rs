@@ -67,7 +67,7 @@ impl<DepsT: HeapDeps> Heap<DepsT> {
     /// the heap is empty. Note that multiple elements in the heap may have the smallest value. In
     /// this case, an arbitrary element will be returned. O(1).
     pub fn peek(&self) -> Option<&DepsT::Element> {
-        self.0.get(0)
+        self.0.first()
     }
 
     /// Remove the element with the smallest value in the heap, or [None] if the heap is empty.
@@ -157,7 +157,7 @@ impl<DepsT: HeapDeps> Heap<DepsT> {
 
     fn sift_up_internal(&mut self, deps: &mut DepsT, HeapIndex(mut idx): HeapIndex) -> HeapIndex {
         while idx != 0 {
-            let parent_idx = (idx + 1) / 2 - 1;
+            let parent_idx = (idx - 1) / 2;
 
             if !deps.is_element_less_than(&self.0[idx], &self.0[parent_idx]) {
                 break;
@@ -240,13 +240,13 @@ mod tests {
     impl Fixture {
         fn validate_indices(&self) {
             for (idx, id) in self.heap.0.iter().enumerate() {
-                assert_eq!(self.elements.get(&id).unwrap().heap_index, HeapIndex(idx));
+                assert_eq!(self.elements.get(id).unwrap().heap_index, HeapIndex(idx));
             }
         }
 
         fn validate_heap_property(&self) {
             for idx in 1..self.heap.0.len() {
-                let parent_idx = (idx + 1) / 2 - 1;
+                let parent_idx = (idx - 1) / 2;
                 let parent_id = self.heap.0[parent_idx];
                 let id = self.heap.0[idx];
                 assert!(
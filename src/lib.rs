@@ -1,12 +1,17 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::{self, Debug};
 use std::hash::Hash;
+use std::path::PathBuf;
+use std::time::Duration;
 
+pub mod auth;
 pub mod broker;
 mod channel_reader;
 pub mod client;
 mod heap;
 mod proto;
+pub mod tls;
 pub mod worker;
 
 #[cfg(test)]
@@ -28,13 +33,373 @@ pub struct ExecutionId(ClientId, ClientExecutionId);
 pub struct ExecutionDetails {
     pub program: String,
     pub arguments: Vec<String>,
+
+    /// Environment variables to set for the execution. Unless `clear_environment` is set, these
+    /// are added to, or override, the worker's own environment.
+    pub environment: BTreeMap<String, String>,
+
+    /// If true, the execution starts with an empty environment, so only the variables in
+    /// `environment` are set.
+    pub clear_environment: bool,
+
+    /// The directory to run the execution in. A relative path is interpreted relative to the root
+    /// of the execution's file system. If None, the execution runs in the worker's current
+    /// directory.
+    pub working_directory: Option<PathBuf>,
+
+    /// If the execution runs for longer than this, the worker terminates it and reports
+    /// [ExecutionStatus::TimedOut].
+    pub timeout: Option<Duration>,
+
+    /// The signal the worker sends the execution's processes to ask them to stop, when it times
+    /// out or is canceled. The default is SIGTERM.
+    pub termination_signal: i32,
+
+    /// How long the execution's processes have to exit after being sent `termination_signal`
+    /// before the worker kills them with SIGKILL. If zero, they're killed right away. The default
+    /// is 5 seconds.
+    pub grace_period: Duration,
+
+    /// If true, the execution's output is streamed back to the client as it is produced.
+    pub stream_output: bool,
+
+    /// The maximum number of bytes of stdout to capture in the [ExecutionResult]. If the
+    /// execution writes more than this, only the tail is kept. If None, stdout isn't captured.
+    /// Limits larger than [MAX_CAPTURED_OUTPUT] are reduced to it.
+    pub stdout_limit: Option<u64>,
+
+    /// Like `stdout_limit`, but for stderr.
+    pub stderr_limit: Option<u64>,
+
+    /// Labels the worker running the execution must have, with these exact values. If no
+    /// connected worker has them all, the broker fails the execution instead of queueing it.
+    pub required_labels: BTreeMap<String, String>,
+
+    /// How many of the worker's slots the execution occupies while it runs, for executions that
+    /// run their own thread pools. Must be at least 1, which is the default.
+    pub slots: u32,
+
+    /// How many bytes of memory the execution needs. A worker won't start it unless that much of
+    /// the memory it advertises isn't reserved by other executions.
+    pub memory: Option<u64>,
+
+    /// The broker sends higher-priority executions to workers before lower-priority ones, no
+    /// matter which client they came from. Among executions with the same priority, each client's
+    /// are sent first in, first out, while across clients the workers are shared fairly in
+    /// proportion to each client's weight, which is set with the broker's `--client-weight`
+    /// option. See [broker::ClientWeights]. The default is 0.
+    pub priority: i32,
+
+    /// How many times the broker resends the execution to another worker if the worker running it
+    /// disconnects first. Once it has lost this many workers and one more, the broker gives up and
+    /// reports [ExecutionStatus::Abandoned]. The default is 2.
+    pub retries: u32,
+
+    /// If true, the execution runs in its own Linux namespaces, where it can't see or signal the
+    /// worker's processes or those of other executions. It runs as root and PID 1 in there.
+    pub sandbox: bool,
+
+    /// If true, the execution runs in the sandbox with a network namespace of its own, in which
+    /// only the loopback interface is up. It can't reach the network, and the ports it binds
+    /// don't conflict with those of other executions.
+    pub isolate_network: bool,
+
+    /// The image layers making up the execution's root file system, bottom layer first. Each
+    /// layer is a tar file, identified by its digest, that the worker fetches and caches. If
+    /// there are any layers, the execution runs in the sandbox, with the layers stacked on top of
+    /// each other as its root. Changes it makes to the root file system are thrown away when it
+    /// completes. If empty, the execution uses the worker's file system.
+    pub layers: Vec<Sha256Digest>,
+
+    /// Limits on the resources the execution may use. Limits that aren't set here are taken from
+    /// the worker's defaults.
+    pub limits: ResourceLimits,
+
+    /// Limits applied to each of the execution's processes on its own. Limits that aren't set
+    /// here are taken from the worker's defaults.
+    pub process_limits: ProcessLimits,
+
+    /// If provided, the worker creates an empty directory for the execution to keep temporary
+    /// files in, and removes it once the execution completes. `TMPDIR` is set to it, unless
+    /// `environment` sets it.
+    pub scratch: Option<Scratch>,
+}
+
+impl Default for ExecutionDetails {
+    fn default() -> Self {
+        ExecutionDetails {
+            program: String::default(),
+            arguments: Vec::default(),
+            environment: BTreeMap::default(),
+            clear_environment: false,
+            working_directory: None,
+            timeout: None,
+            termination_signal: nix::libc::SIGTERM,
+            grace_period: Duration::from_secs(5),
+            stream_output: false,
+            stdout_limit: None,
+            stderr_limit: None,
+            required_labels: BTreeMap::default(),
+            slots: 1,
+            memory: None,
+            priority: 0,
+            retries: 2,
+            sandbox: false,
+            isolate_network: false,
+            layers: Vec::default(),
+            limits: ResourceLimits::default(),
+            process_limits: ProcessLimits::default(),
+            scratch: None,
+        }
+    }
+}
+
+/// Limits on the resources an execution may use, counting all of the processes it starts. The
+/// worker enforces them by running the execution in a cgroup of its own, so executions with limits
+/// fail on workers that weren't given a cgroup to manage.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ResourceLimits {
+    /// The most memory, in bytes, the execution may use. If it needs more, the kernel kills it,
+    /// and the worker reports [ExecutionStatus::OutOfMemory].
+    pub memory: Option<u64>,
+
+    /// How much CPU time the execution may use, in thousandths of a CPU. For example, 1500 lets it
+    /// use one and a half CPUs' worth of time. If it tries to use more, it's throttled.
+    pub cpu_millis: Option<u32>,
+
+    /// The most processes and threads the execution may have at once. Once it has this many,
+    /// attempts to create more fail.
+    pub pids: Option<u32>,
+}
+
+impl ResourceLimits {
+    /// These limits, with the ones that aren't set taken from `defaults`.
+    pub fn or(self, defaults: ResourceLimits) -> Self {
+        ResourceLimits {
+            memory: self.memory.or(defaults.memory),
+            cpu_millis: self.cpu_millis.or(defaults.cpu_millis),
+            pids: self.pids.or(defaults.pids),
+        }
+    }
 }
 
+/// POSIX resource limits, which the worker sets for an execution's process before starting it, and
+/// which each process it starts inherits. Unlike [ResourceLimits], they apply to processes one at
+/// a time, and any worker can enforce them. The execution can lower them, but not raise them. It's
+/// an error to ask for a limit higher than the worker's own.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ProcessLimits {
+    /// The largest core file, in bytes, a process may dump. Zero keeps processes from dumping
+    /// core at all.
+    pub core_file_size: Option<u64>,
+
+    /// The most file descriptors a process may have open at once.
+    pub open_files: Option<u64>,
+
+    /// How many seconds of CPU time a process may use. Once it has used them, it's sent SIGXCPU.
+    pub cpu_seconds: Option<u64>,
+
+    /// The largest amount of virtual memory, in bytes, a process may map.
+    pub address_space: Option<u64>,
+
+    /// The largest file, in bytes, a process may write. Writing past it fails, and the process is
+    /// sent SIGXFSZ.
+    pub file_size: Option<u64>,
+}
+
+impl ProcessLimits {
+    /// These limits, with the ones that aren't set taken from `defaults`.
+    pub fn or(self, defaults: ProcessLimits) -> Self {
+        ProcessLimits {
+            core_file_size: self.core_file_size.or(defaults.core_file_size),
+            open_files: self.open_files.or(defaults.open_files),
+            cpu_seconds: self.cpu_seconds.or(defaults.cpu_seconds),
+            address_space: self.address_space.or(defaults.address_space),
+            file_size: self.file_size.or(defaults.file_size),
+        }
+    }
+}
+
+/// How an execution uses its scratch directory. See [ExecutionDetails::scratch].
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Scratch {
+    /// If true, the execution starts in its scratch directory, instead of in
+    /// [ExecutionDetails::working_directory].
+    pub working_directory: bool,
+
+    /// The most bytes the execution may store in its scratch directory. Writes that would go over
+    /// it fail with ENOSPC. The scratch directory is then a tmpfs of that size in the sandbox, so
+    /// the execution runs in the sandbox, and the files it writes there are kept in memory.
+    pub quota: Option<u64>,
+}
+
+/// The largest amount of output, per stream, that will be captured in an [ExecutionResult]. This
+/// keeps the messages carrying results well under the default maximum frame size.
+pub const MAX_CAPTURED_OUTPUT: u64 = 4 * 1024 * 1024;
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
-pub enum ExecutionResult {
-    Exited(u8),
-    Signalled(u8),
+pub enum ExecutionStatus {
+    /// The execution exited on its own with the given exit code.
+    Exited(i32),
+
+    /// The execution was terminated by a signal. The worker fills in `name` from `signal`, since
+    /// signal numbers differ between platforms.
+    Signalled {
+        signal: i32,
+        name: String,
+        core_dumped: bool,
+    },
+
     Error(String),
+
+    /// The execution exceeded its timeout and was killed after running for the given time.
+    TimedOut(Duration),
+
+    /// The broker gave up on the execution after the given number of workers disconnected while
+    /// running it. See [ExecutionDetails::retries].
+    Abandoned(u32),
+
+    /// The execution was killed by the kernel for using more memory than its limit, which is given
+    /// in bytes. See [ResourceLimits::memory].
+    OutOfMemory(u64),
+}
+
+impl ExecutionStatus {
+    /// A [ExecutionStatus::Signalled] for `signal`, named according to this platform.
+    pub fn signalled(signal: i32, core_dumped: bool) -> Self {
+        let name = match nix::sys::signal::Signal::try_from(signal) {
+            Ok(signal) => signal.as_str().to_string(),
+            Err(_) => format!("signal {signal}"),
+        };
+        ExecutionStatus::Signalled {
+            signal,
+            name,
+            core_dumped,
+        }
+    }
+}
+
+impl fmt::Display for ExecutionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionStatus::Exited(code) => write!(f, "exited with code {code}"),
+            ExecutionStatus::Signalled {
+                name, core_dumped, ..
+            } => {
+                write!(f, "{name}")?;
+                if *core_dumped {
+                    write!(f, " (core dumped)")?;
+                }
+                Ok(())
+            }
+            ExecutionStatus::Error(error) => write!(f, "error: {error}"),
+            ExecutionStatus::TimedOut(elapsed) => {
+                write!(f, "timed out after {:.3}s", elapsed.as_secs_f64())
+            }
+            ExecutionStatus::Abandoned(1) => write!(f, "abandoned after losing 1 worker"),
+            ExecutionStatus::Abandoned(lost) => write!(f, "abandoned after losing {lost} workers"),
+            ExecutionStatus::OutOfMemory(limit) => {
+                write!(f, "killed for exceeding its memory limit of {limit} bytes")
+            }
+        }
+    }
+}
+
+/// How the worker stopped an execution that it had to end early. See
+/// [ExecutionDetails::termination_signal].
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Termination {
+    /// The execution exited within its grace period after being sent its termination signal.
+    Graceful,
+
+    /// The execution was still running at the end of its grace period, so it was killed.
+    Killed,
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Termination::Graceful => write!(f, "stopped by its termination signal"),
+            Termination::Killed => write!(f, "killed at the end of its grace period"),
+        }
+    }
+}
+
+/// Output captured from one of an execution's streams.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum CapturedOutput {
+    /// Capturing wasn't requested, or the execution never started.
+    #[default]
+    None,
+
+    /// Everything the execution wrote.
+    Complete(Vec<u8>),
+
+    /// The execution wrote more than the limit. `tail` holds the last bytes written, and
+    /// `truncated` is the number of bytes dropped before it.
+    Truncated { tail: Vec<u8>, truncated: u64 },
+}
+
+impl CapturedOutput {
+    /// The captured bytes, which are only the tail if the output was truncated.
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            CapturedOutput::None => &[],
+            CapturedOutput::Complete(bytes) => bytes,
+            CapturedOutput::Truncated { tail, .. } => tail,
+        }
+    }
+}
+
+/// Resources used by an execution, as reported by the kernel when the worker reaped it. If the
+/// execution ran in a cgroup, the CPU times include all of the processes it started, even those
+/// that weren't waited for.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ResourceUsage {
+    pub wall_time: Duration,
+    pub user_time: Duration,
+    pub system_time: Duration,
+    /// The peak resident set size, in bytes.
+    pub max_rss: u64,
+    /// The peak memory used by all of the execution's processes together, in bytes. This is only
+    /// known if the execution ran in a cgroup that accounts for memory.
+    pub peak_memory: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ExecutionResult {
+    pub status: ExecutionStatus,
+    pub stdout: CapturedOutput,
+    pub stderr: CapturedOutput,
+    /// None if the execution never started.
+    pub resource_usage: Option<ResourceUsage>,
+    /// How many times the execution was sent to a worker, including any retries after the worker
+    /// running it disconnected.
+    pub attempts: u32,
+    /// If the worker had to stop the execution, because it timed out or was canceled, how it
+    /// ended. None if it ended on its own.
+    pub termination: Option<Termination>,
+}
+
+impl From<ExecutionStatus> for ExecutionResult {
+    /// A result with no captured output or resource usage, from a single attempt.
+    fn from(status: ExecutionStatus) -> Self {
+        ExecutionResult {
+            status,
+            stdout: CapturedOutput::None,
+            stderr: CapturedOutput::None,
+            resource_usage: None,
+            attempts: 1,
+            termination: None,
+        }
+    }
+}
+
+/// Which of an execution's output streams a chunk of output came from.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
 }
 
 #[derive(
@@ -42,7 +407,7 @@ pub enum ExecutionResult {
 )]
 pub struct WorkerId(u32);
 
-#[derive(Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct Sha256Digest(pub [u8; 32]);
 
 impl From<u32> for Sha256Digest {
@@ -118,6 +483,135 @@ impl Debug for Sha256Digest {
 mod tests {
     use super::*;
 
+    #[test]
+    fn signalled_names_signal() {
+        assert_eq!(
+            ExecutionStatus::signalled(11, true),
+            ExecutionStatus::Signalled {
+                signal: 11,
+                name: "SIGSEGV".to_string(),
+                core_dumped: true,
+            }
+        );
+    }
+
+    #[test]
+    fn exit_code_not_truncated() {
+        for code in [-1, 256, i32::MAX] {
+            let result = ExecutionResult::from(ExecutionStatus::Exited(code));
+            let encoded = bincode::serialize(&result).unwrap();
+            assert_eq!(
+                bincode::deserialize::<ExecutionResult>(&encoded).unwrap(),
+                result
+            );
+            assert_eq!(
+                result.status.to_string(),
+                format!("exited with code {code}")
+            );
+        }
+    }
+
+    #[test]
+    fn signalled_unknown_signal() {
+        assert_eq!(
+            ExecutionStatus::signalled(1000, false).to_string(),
+            "signal 1000"
+        );
+    }
+
+    #[test]
+    fn execution_status_display() {
+        assert_eq!(
+            ExecutionStatus::Exited(257).to_string(),
+            "exited with code 257"
+        );
+        assert_eq!(ExecutionStatus::signalled(15, false).to_string(), "SIGTERM");
+        assert_eq!(
+            ExecutionStatus::signalled(11, true).to_string(),
+            "SIGSEGV (core dumped)"
+        );
+        assert_eq!(
+            ExecutionStatus::Error("no such file".to_string()).to_string(),
+            "error: no such file"
+        );
+        assert_eq!(
+            ExecutionStatus::TimedOut(Duration::from_millis(1500)).to_string(),
+            "timed out after 1.500s"
+        );
+        assert_eq!(
+            ExecutionStatus::Abandoned(1).to_string(),
+            "abandoned after losing 1 worker"
+        );
+        assert_eq!(
+            ExecutionStatus::Abandoned(3).to_string(),
+            "abandoned after losing 3 workers"
+        );
+        assert_eq!(
+            ExecutionStatus::OutOfMemory(1 << 20).to_string(),
+            "killed for exceeding its memory limit of 1048576 bytes"
+        );
+    }
+
+    #[test]
+    fn termination_display() {
+        assert_eq!(
+            Termination::Graceful.to_string(),
+            "stopped by its termination signal"
+        );
+        assert_eq!(
+            Termination::Killed.to_string(),
+            "killed at the end of its grace period"
+        );
+    }
+
+    #[test]
+    fn resource_limits_or() {
+        let limits = ResourceLimits {
+            memory: Some(1),
+            cpu_millis: None,
+            pids: None,
+        };
+        let defaults = ResourceLimits {
+            memory: Some(2),
+            cpu_millis: Some(3),
+            pids: None,
+        };
+        assert_eq!(
+            limits.or(defaults),
+            ResourceLimits {
+                memory: Some(1),
+                cpu_millis: Some(3),
+                pids: None,
+            }
+        );
+    }
+
+    #[test]
+    fn process_limits_or() {
+        let limits = ProcessLimits {
+            core_file_size: Some(0),
+            open_files: None,
+            file_size: Some(1),
+            ..Default::default()
+        };
+        let defaults = ProcessLimits {
+            core_file_size: Some(2),
+            open_files: Some(3),
+            cpu_seconds: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(
+            limits.or(defaults),
+            ProcessLimits {
+                core_file_size: Some(0),
+                open_files: Some(3),
+                cpu_seconds: Some(4),
+                address_space: None,
+                file_size: Some(1),
+            }
+        );
+    }
+
     #[test]
     fn from_u32() {
         assert_eq!(
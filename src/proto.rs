@@ -1,85 +1,374 @@
 //! Messages sent between various binaries, and helper functions related to those messages.
 
-use crate::{ClientExecutionId, ExecutionDetails, ExecutionId, ExecutionResult, Result};
+use crate::{
+    auth::{Nonce, SharedKey, Signature},
+    ClientExecutionId, Error, ExecutionDetails, ExecutionId, ExecutionResult, OutputStream, Result,
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+/// The version of the protocol spoken by this build. It must be incremented whenever the encoding
+/// of any message in this module changes. Versions 0 and 1 are never used: peers built before the
+/// version was added to [Hello] send a first message whose leading four bytes decode as one of
+/// those values.
+pub const PROTOCOL_VERSION: u32 = 22;
 
 /// The first message sent by a client or worker to the broker. It identifies the client/worker and
 /// gives any relevant information.
+///
+/// The `protocol_version` field must remain first so that the broker can decode it even when the
+/// rest of the message has a different shape. See [read_hello].
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub capabilities: BTreeSet<String>,
+    pub peer: Peer,
+}
+
+impl Hello {
+    /// Create a [Hello] for this build's [PROTOCOL_VERSION] offering this build's
+    /// [capabilities].
+    pub fn new(peer: Peer) -> Self {
+        Hello {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: capabilities(),
+            peer,
+        }
+    }
+}
+
+/// The optional protocol features this build supports. A peer offers its capabilities in its
+/// [Hello], and the broker accepts the ones it supports too in [HelloResponse::Accepted]. Neither
+/// side may use a capability the broker didn't accept. There are none yet.
+pub fn capabilities() -> BTreeSet<String> {
+    BTreeSet::default()
+}
+
+/// The type of the peer sending a [Hello], along with any type-specific information.
 #[derive(Serialize, Deserialize, Debug)]
-pub enum Hello {
-    Client { name: String },
-    Worker { name: String, slots: u32 },
+pub enum Peer {
+    Client {
+        name: String,
+    },
+    Worker {
+        name: String,
+        slots: u32,
+        /// How many bytes of memory executions may reserve on the worker.
+        memory: u64,
+        /// Key/value pairs describing the worker, matched against
+        /// [crate::ExecutionDetails::required_labels].
+        labels: BTreeMap<String, String>,
+    },
+}
+
+/// Message sent from the broker in response to a [Hello] or an [AuthResponse]. If the broker
+/// rejects the connection, it will close the socket after sending this message. If the broker
+/// requires authentication, it first sends a [HelloResponse::Challenge], to which the peer must
+/// reply with an [AuthResponse] before receiving a final [HelloResponse].
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum HelloResponse {
+    Accepted {
+        capabilities: BTreeSet<String>,
+        heartbeat: HeartbeatConfig,
+    },
+    Rejected(String),
+    Challenge(Nonce),
+}
+
+/// How often each side of a connection sends heartbeats, and how many consecutive heartbeats may
+/// be missed before the other side considers it dead. The broker chooses these and sends them in
+/// [HelloResponse::Accepted] so that both sides agree.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    pub missed_beats: u32,
 }
 
+impl HeartbeatConfig {
+    /// How long to wait for any message from the peer before declaring it dead.
+    pub fn timeout(&self) -> Duration {
+        self.interval * self.missed_beats
+    }
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            interval: Duration::from_secs(5),
+            missed_beats: 3,
+        }
+    }
+}
+
+/// Messages that can be sent as heartbeats. [socket_writer] sends a heartbeat whenever it hasn't
+/// sent anything for an interval, and [socket_reader] discards heartbeats after noting that the
+/// peer is still alive.
+pub trait Heartbeat {
+    fn heartbeat() -> Self;
+    fn is_heartbeat(&self) -> bool;
+}
+
+macro_rules! impl_heartbeat {
+    ($message:ident) => {
+        impl Heartbeat for $message {
+            fn heartbeat() -> Self {
+                $message::Heartbeat
+            }
+
+            fn is_heartbeat(&self) -> bool {
+                matches!(self, $message::Heartbeat)
+            }
+        }
+    };
+}
+
+/// Message sent from a client or worker in response to a [HelloResponse::Challenge].
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuthResponse(pub Signature);
+
 /// Message sent from the broker to a worker. The broker won't send a message until it has received
 /// a [Hello] and determined the type of its interlocutor.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum WorkerRequest {
-    EnqueueExecution(ExecutionId, ExecutionDetails),
+    EnqueueExecution(ExecutionId, Box<ExecutionDetails>),
     CancelExecution(ExecutionId),
+    Heartbeat,
 }
 
-/// Message sent from a worker to the broker. These are responses to previous
+impl_heartbeat!(WorkerRequest);
+
+/// Message sent from a worker to the broker. These are mostly responses to previous
 /// [WorkerRequest::EnqueueExecution] messages. After sending the initial [Hello], a worker will
 /// exclusively send a stream of these messages.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
-pub struct WorkerResponse(pub ExecutionId, pub ExecutionResult);
+pub enum WorkerResponse {
+    /// A chunk of output written by a running execution that asked for its output to be streamed.
+    /// Chunks for a given execution and stream are sent in order, and all of them are sent before
+    /// the execution's [WorkerResponse::ExecutionCompleted].
+    ExecutionOutput(ExecutionId, OutputStream, Vec<u8>),
+    ExecutionCompleted(ExecutionId, ExecutionResult),
+    Heartbeat,
+}
+
+impl_heartbeat!(WorkerResponse);
 
 /// Message sent from a client to the broker. After sending the initial [Hello], a client will
 /// exclusively send a stream of these messages.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
-pub struct ClientRequest(pub ClientExecutionId, pub ExecutionDetails);
+pub enum ClientRequest {
+    EnqueueExecution(ClientExecutionId, Box<ExecutionDetails>),
+    Heartbeat,
+}
+
+impl_heartbeat!(ClientRequest);
 
 /// Message sent from the broker to a client. The broker won't send a message until it has recevied
 /// a [Hello] and determined the type of its interlocutor.
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
-pub struct ClientResponse(pub ClientExecutionId, pub ExecutionResult);
+pub enum ClientResponse {
+    /// A chunk of output forwarded from the worker running the execution. See
+    /// [WorkerResponse::ExecutionOutput].
+    ExecutionOutput(ClientExecutionId, OutputStream, Vec<u8>),
+    ExecutionCompleted(ClientExecutionId, ExecutionResult),
+    Heartbeat,
+}
+
+impl_heartbeat!(ClientResponse);
+
+/// The error returned by [socket_reader] when the peer hasn't sent anything, not even a heartbeat,
+/// for longer than the [HeartbeatConfig::timeout].
+#[derive(Debug)]
+pub struct HeartbeatTimeout(pub Duration);
+
+impl std::fmt::Display for HeartbeatTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no heartbeat received in {:?}", self.0)
+    }
+}
+
+impl std::error::Error for HeartbeatTimeout {}
 
 /// Write a message to a Tokio output stream. Each message is framed by sending a leading 4-byte,
-/// little-endian message size.
+/// little-endian message size. The stream is flushed after each message, since it may be a TLS
+/// session that buffers records.
 pub async fn write_message(
     stream: &mut (impl tokio::io::AsyncWrite + Unpin),
     msg: impl Serialize,
 ) -> Result<()> {
-    let msg_len = bincode::serialized_size(&msg)? as u32;
+    let msg_len: u32 = bincode::serialized_size(&msg)?.try_into()?;
 
     let mut buf = Vec::<u8>::with_capacity(msg_len as usize + 4);
     std::io::Write::write(&mut buf, &msg_len.to_le_bytes())?;
     bincode::serialize_into(&mut buf, &msg)?;
 
-    Ok(tokio::io::AsyncWriteExt::write_all(stream, &buf).await?)
+    tokio::io::AsyncWriteExt::write_all(stream, &buf).await?;
+    Ok(tokio::io::AsyncWriteExt::flush(stream).await?)
+}
+
+/// The maximum size of a [Hello] or [AuthResponse] frame. These are read before the peer has been
+/// admitted, so they get a small fixed limit.
+pub const MAX_HELLO_FRAME_SIZE: u32 = 64 * 1024;
+
+/// The default maximum size of all other frames.
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+
+/// An error reading a frame that indicates the peer isn't following the protocol, as opposed to an
+/// I/O error. [read_message] and friends return these wrapped in an [Error], so use
+/// [Error::downcast_ref] to distinguish them.
+#[derive(Debug)]
+pub enum FrameError {
+    /// The frame's length prefix exceeded the maximum frame size for the connection.
+    TooLarge { size: u32, max: u32 },
+
+    /// The frame's contents couldn't be decoded as the expected message.
+    Undecodable(bincode::Error),
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::TooLarge { size, max } => {
+                write!(f, "frame of {size} bytes exceeds maximum of {max} bytes")
+            }
+            FrameError::Undecodable(err) => write!(f, "undecodable frame: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// Read a frame, checking its length against `max_frame_size` before allocating anything.
+async fn read_frame(
+    stream: &mut (impl tokio::io::AsyncRead + Unpin),
+    max_frame_size: u32,
+) -> Result<Vec<u8>> {
+    let mut msg_len: [u8; 4] = [0; 4];
+    tokio::io::AsyncReadExt::read_exact(stream, &mut msg_len).await?;
+    let msg_len = u32::from_le_bytes(msg_len);
+    if msg_len > max_frame_size {
+        return Err(FrameError::TooLarge {
+            size: msg_len,
+            max: max_frame_size,
+        }
+        .into());
+    }
+
+    let mut buf = vec![0; msg_len as usize];
+    tokio::io::AsyncReadExt::read_exact(stream, &mut buf).await?;
+    Ok(buf)
+}
+
+/// Decode a message from the start of `buf`. No length prefix inside the message may claim more
+/// bytes than `buf` holds, which keeps a hostile frame from causing large allocations. If
+/// `whole_frame` is true, the message must also consume all of `buf`.
+fn decode<MessageT: DeserializeOwned>(buf: &[u8], whole_frame: bool) -> Result<MessageT> {
+    use bincode::Options as _;
+    let options = bincode::options()
+        .with_fixint_encoding()
+        .with_limit(buf.len() as u64);
+    let result = if whole_frame {
+        options.reject_trailing_bytes().deserialize(buf)
+    } else {
+        options.allow_trailing_bytes().deserialize(buf)
+    };
+    Ok(result.map_err(FrameError::Undecodable)?)
 }
 
 /// Read a message from a Tokio input stream. The framing must match that of [write_message].
+/// Frames larger than `max_frame_size` are rejected with a [FrameError::TooLarge] without reading
+/// their contents.
 pub async fn read_message<MessageT>(
     stream: &mut (impl tokio::io::AsyncRead + Unpin),
+    max_frame_size: u32,
 ) -> Result<MessageT>
 where
     MessageT: DeserializeOwned,
 {
-    let mut msg_len: [u8; 4] = [0; 4];
-    tokio::io::AsyncReadExt::read_exact(stream, &mut msg_len).await?;
-    let msg_len = u32::from_le_bytes(msg_len) as usize;
+    decode(&read_frame(stream, max_frame_size).await?, true)
+}
 
-    let mut buf = vec![0; msg_len];
-    tokio::io::AsyncReadExt::read_exact(stream, &mut buf).await?;
-    Ok(bincode::deserialize_from(&mut &buf[..])?)
+/// Read a [Hello] from a Tokio input stream. The protocol version is decoded and checked before
+/// the rest of the message, since a peer speaking a different version may send a [Hello] that
+/// can't be decoded at all. If the version doesn't match [PROTOCOL_VERSION], the inner result
+/// contains a human-readable reason suitable for a [HelloResponse::Rejected].
+pub async fn read_hello(
+    stream: &mut (impl tokio::io::AsyncRead + Unpin),
+) -> Result<std::result::Result<Hello, String>> {
+    let buf = read_frame(stream, MAX_HELLO_FRAME_SIZE).await?;
+    let protocol_version: u32 = decode(&buf, false)?;
+    if protocol_version != PROTOCOL_VERSION {
+        return Ok(Err(format!(
+            "unsupported protocol version {protocol_version}, \
+             broker speaks version {PROTOCOL_VERSION}"
+        )));
+    }
+    Ok(Ok(decode(&buf, true)?))
+}
+
+/// Send a [Hello] to the broker and wait for its [HelloResponse], answering an authentication
+/// challenge with `key` if the broker sends one. Return the [HeartbeatConfig] chosen by the
+/// broker, or an error, including the broker's reason, if the broker rejects the connection.
+pub async fn send_hello(
+    read_stream: &mut (impl tokio::io::AsyncRead + Unpin),
+    write_stream: &mut (impl tokio::io::AsyncWrite + Unpin),
+    hello: Hello,
+    key: Option<&SharedKey>,
+) -> Result<HeartbeatConfig> {
+    write_message(write_stream, hello).await?;
+    let mut challenged = false;
+    loop {
+        match read_message(read_stream, MAX_HELLO_FRAME_SIZE).await? {
+            HelloResponse::Accepted { heartbeat, .. } => return Ok(heartbeat),
+            HelloResponse::Rejected(reason) => {
+                return Err(Error::msg(format!("broker rejected connection: {reason}")))
+            }
+            HelloResponse::Challenge(_) if challenged => {
+                return Err(Error::msg("broker sent more than one challenge"))
+            }
+            HelloResponse::Challenge(nonce) => match key {
+                None => {
+                    return Err(Error::msg(
+                        "broker requires authentication, but no key was provided",
+                    ))
+                }
+                Some(key) => {
+                    write_message(write_stream, AuthResponse(key.sign(&nonce))).await?;
+                    challenged = true;
+                }
+            },
+        }
+    }
 }
 
 /// Loop reading messages from a socket and writing them to an mpsc channel. If this function
 /// encounters an error reading from the socket, it will return that error. On the other hand, if
 /// it encounters an error writing to the sender -- which indicates that there is no longer a
-/// receiver for the channel -- it will return Ok(()).
+/// receiver for the channel -- it will return Ok(()). Frames larger than `max_frame_size` are
+/// treated as errors. Heartbeats are discarded, and if no message at all arrives within
+/// `heartbeat`'s timeout, a [HeartbeatTimeout] is returned.
 pub async fn socket_reader<MessageT, TransformedT>(
-    mut socket: (impl tokio::io::AsyncRead + Unpin),
+    mut socket: impl tokio::io::AsyncRead + Unpin,
+    max_frame_size: u32,
+    heartbeat: HeartbeatConfig,
     channel: tokio::sync::mpsc::UnboundedSender<TransformedT>,
     transform: impl Fn(MessageT) -> TransformedT,
 ) -> Result<()>
 where
-    MessageT: DeserializeOwned,
+    MessageT: DeserializeOwned + Heartbeat,
 {
     loop {
-        let msg = read_message(&mut socket).await?;
+        let msg: MessageT = tokio::time::timeout(
+            heartbeat.timeout(),
+            read_message(&mut socket, max_frame_size),
+        )
+        .await
+        .map_err(|_| HeartbeatTimeout(heartbeat.timeout()))??;
+        if msg.is_heartbeat() {
+            continue;
+        }
         if channel.send(transform(msg)).is_err() {
             return Ok(());
         }
@@ -88,13 +377,263 @@ where
 
 /// Loop reading messages from an mpsc channel and writing them to a socket. This will return
 /// Ok(()) when all producers have closed their mpsc channel senders and there are no more messages
-/// to read.
-pub async fn socket_writer(
-    mut channel: tokio::sync::mpsc::UnboundedReceiver<impl Serialize>,
-    mut socket: (impl tokio::io::AsyncWrite + Unpin),
-) -> Result<()> {
-    while let Some(msg) = channel.recv().await {
-        write_message(&mut socket, msg).await?;
+/// to read. Whenever no message has been written for `heartbeat`'s interval, a heartbeat is
+/// written instead.
+pub async fn socket_writer<MessageT>(
+    mut channel: tokio::sync::mpsc::UnboundedReceiver<MessageT>,
+    mut socket: impl tokio::io::AsyncWrite + Unpin,
+    heartbeat: HeartbeatConfig,
+) -> Result<()>
+where
+    MessageT: Serialize + Heartbeat,
+{
+    loop {
+        match tokio::time::timeout(heartbeat.interval, channel.recv()).await {
+            Err(_) => write_message(&mut socket, MessageT::heartbeat()).await?,
+            Ok(Some(msg)) => write_message(&mut socket, msg).await?,
+            Ok(None) => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::*;
+    use std::fmt::Debug;
+
+    async fn read_hello_from_bytes(bytes: Vec<u8>) -> std::result::Result<Hello, String> {
+        read_hello(&mut &frame(&bytes)[..]).await.unwrap()
+    }
+
+    fn frame(bytes: &[u8]) -> Vec<u8> {
+        let mut frame = (bytes.len() as u32).to_le_bytes().to_vec();
+        frame.extend(bytes);
+        frame
+    }
+
+    fn expect_frame_error<T: Debug>(result: Result<T>) -> FrameError {
+        match result.unwrap_err().downcast::<FrameError>() {
+            Ok(err) => err,
+            Err(err) => panic!("expected frame error, got {err:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn read_message_round_trip() {
+        let mut buf = vec![];
+        write_message(
+            &mut buf,
+            ClientRequest::EnqueueExecution(ClientExecutionId(1), Box::new(details![1])),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            read_message::<ClientRequest>(&mut &buf[..], DEFAULT_MAX_FRAME_SIZE)
+                .await
+                .unwrap(),
+            ClientRequest::EnqueueExecution(ClientExecutionId(1), Box::new(details![1]))
+        );
+    }
+
+    #[tokio::test]
+    async fn read_message_frame_at_limit() {
+        let frame = frame(&bincode::serialize(&7u64).unwrap());
+        assert_eq!(read_message::<u64>(&mut &frame[..], 8).await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn read_message_frame_too_large() {
+        // The contents are never read, so they don't need to exist.
+        let frame = u32::MAX.to_le_bytes();
+        assert!(matches!(
+            expect_frame_error(read_message::<u64>(&mut &frame[..], 1024).await),
+            FrameError::TooLarge {
+                size: u32::MAX,
+                max: 1024
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_message_bogus_length_inside_frame() {
+        // A string claiming to be 4 GiB long inside of a 12-byte frame.
+        let frame = frame(&(u32::MAX as u64).to_le_bytes()[..]);
+        assert!(matches!(
+            expect_frame_error(read_message::<String>(&mut &frame[..], 1024).await),
+            FrameError::Undecodable(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_message_trailing_bytes() {
+        let frame = frame(&[1, 0, 0, 0, 0, 0, 0, 0, 0xff]);
+        assert!(matches!(
+            expect_frame_error(read_message::<u64>(&mut &frame[..], 1024).await),
+            FrameError::Undecodable(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_message_truncated_stream_is_not_frame_error() {
+        let frame = &frame(&[1, 2, 3, 4])[..6];
+        let err = read_message::<u32>(&mut &frame[..], 1024)
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<FrameError>().is_none());
+    }
+
+    #[tokio::test]
+    async fn hello_frame_too_large() {
+        let frame = (MAX_HELLO_FRAME_SIZE + 1).to_le_bytes();
+        assert!(matches!(
+            expect_frame_error(read_hello(&mut &frame[..]).await),
+            FrameError::TooLarge { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn hello_with_current_version_accepted() {
+        let hello = Hello::new(Peer::Worker {
+            name: "worker".to_string(),
+            slots: 2,
+            memory: 1 << 30,
+            labels: BTreeMap::from([("arch".to_string(), "aarch64".to_string())]),
+        });
+        match read_hello_from_bytes(bincode::serialize(&hello).unwrap()).await {
+            Ok(Hello {
+                protocol_version: PROTOCOL_VERSION,
+                peer:
+                    Peer::Worker {
+                        name,
+                        slots: 2,
+                        memory: 0x40000000,
+                        labels,
+                    },
+                ..
+            }) => {
+                assert_eq!(name, "worker");
+                assert_eq!(labels["arch"], "aarch64");
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn hello_with_other_version_rejected_before_decoding_rest() {
+        // A future version might send something we can't decode after the version.
+        let mut bytes = bincode::serialize(&(PROTOCOL_VERSION + 1)).unwrap();
+        bytes.extend([0xff; 3]);
+        assert_eq!(
+            read_hello_from_bytes(bytes).await.unwrap_err(),
+            format!(
+                "unsupported protocol version {}, broker speaks version {PROTOCOL_VERSION}",
+                PROTOCOL_VERSION + 1
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn hello_from_unversioned_peer_rejected() {
+        #[derive(Serialize)]
+        enum UnversionedHello {
+            #[allow(dead_code)]
+            Client {
+                name: String,
+            },
+            Worker {
+                name: String,
+                slots: u32,
+            },
+        }
+        let hello = UnversionedHello::Worker {
+            name: "worker".to_string(),
+            slots: 2,
+        };
+        assert!(read_hello_from_bytes(bincode::serialize(&hello).unwrap())
+            .await
+            .is_err());
+    }
+
+    const HEARTBEAT: HeartbeatConfig = HeartbeatConfig {
+        interval: Duration::from_secs(1),
+        missed_beats: 3,
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn socket_reader_discards_heartbeats() {
+        let (mut near, far) = tokio::io::duplex(1024);
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let reader = tokio::task::spawn(socket_reader(
+            far,
+            DEFAULT_MAX_FRAME_SIZE,
+            HEARTBEAT,
+            sender,
+            |msg: ClientResponse| msg,
+        ));
+        write_message(&mut near, ClientResponse::Heartbeat)
+            .await
+            .unwrap();
+        let response = ClientResponse::ExecutionCompleted(ClientExecutionId(1), result![2]);
+        write_message(&mut near, response.clone()).await.unwrap();
+        assert_eq!(receiver.recv().await, Some(response));
+        drop(near);
+        assert!(reader.await.unwrap().is_err());
+        assert_eq!(receiver.recv().await, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn socket_reader_times_out_without_heartbeats() {
+        let (_near, far) = tokio::io::duplex(1024);
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        let start = tokio::time::Instant::now();
+        let err = socket_reader(
+            far,
+            DEFAULT_MAX_FRAME_SIZE,
+            HEARTBEAT,
+            sender,
+            |msg: ClientResponse| msg,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.downcast_ref::<HeartbeatTimeout>().is_some());
+        assert_eq!(start.elapsed(), HEARTBEAT.timeout());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn socket_writer_sends_heartbeats_when_idle() {
+        let (near, mut far) = tokio::io::duplex(1024);
+        let (_sender, receiver) = tokio::sync::mpsc::unbounded_channel::<ClientRequest>();
+        tokio::task::spawn(socket_writer(receiver, near, HEARTBEAT));
+        let start = tokio::time::Instant::now();
+        for i in 1..=3 {
+            assert_eq!(
+                read_message::<ClientRequest>(&mut far, DEFAULT_MAX_FRAME_SIZE)
+                    .await
+                    .unwrap(),
+                ClientRequest::Heartbeat
+            );
+            assert_eq!(start.elapsed(), HEARTBEAT.interval * i);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn heartbeats_keep_idle_connection_alive() {
+        let (near, far) = tokio::io::duplex(1024);
+        let (writer_sender, writer_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (reader_sender, mut reader_receiver) = tokio::sync::mpsc::unbounded_channel();
+        tokio::task::spawn(socket_writer(writer_receiver, near, HEARTBEAT));
+        let reader = tokio::task::spawn(socket_reader(
+            far,
+            DEFAULT_MAX_FRAME_SIZE,
+            HEARTBEAT,
+            reader_sender,
+            |msg: WorkerResponse| msg,
+        ));
+        tokio::time::sleep(HEARTBEAT.timeout() * 10).await;
+        assert!(!reader.is_finished());
+        let response = WorkerResponse::ExecutionCompleted(eid![1], result![2]);
+        writer_sender.send(response.clone()).unwrap();
+        assert_eq!(reader_receiver.recv().await, Some(response));
     }
-    Ok(())
 }
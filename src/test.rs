@@ -32,47 +32,61 @@ macro_rules! details {
         $crate::ExecutionDetails {
             program: "test_1".to_string(),
             arguments: vec![],
+            ..Default::default()
         }
     };
     [2] => {
         $crate::ExecutionDetails {
             program: "test_2".to_string(),
             arguments: vec!["arg_1".to_string()],
+            ..Default::default()
         }
     };
     [3] => {
         $crate::ExecutionDetails {
             program: "test_3".to_string(),
             arguments: vec!["arg_1".to_string(), "arg_2".to_string()],
+            ..Default::default()
         }
     };
     [4] => {
         $crate::ExecutionDetails {
             program: "test_4".to_string(),
             arguments: vec!["arg_1".to_string(), "arg_2".to_string(), "arg_3".to_string()],
+            ..Default::default()
         }
     };
     [$n:literal] => {
         $crate::ExecutionDetails {
             program: concat!("test_", stringify!($n)).to_string(),
             arguments: vec!["arg_1".to_string()],
+            ..Default::default()
         }
     };
 }
 pub(crate) use details;
 
+macro_rules! labels {
+    [$($key:expr => $value:expr),* $(,)?] => {
+        std::collections::BTreeMap::<String, String>::from([
+            $(($key.to_string(), $value.to_string()),)*
+        ])
+    };
+}
+pub(crate) use labels;
+
 macro_rules! result {
     [1] => {
-        $crate::ExecutionResult::Exited(0)
+        $crate::ExecutionResult::from($crate::ExecutionStatus::Exited(0))
     };
     [2] => {
-        $crate::ExecutionResult::Exited(1)
+        $crate::ExecutionResult::from($crate::ExecutionStatus::Exited(1))
     };
     [3] => {
-        $crate::ExecutionResult::Signalled(15)
+        $crate::ExecutionResult::from($crate::ExecutionStatus::signalled(15, false))
     };
     [$n:expr] => {
-        $crate::ExecutionResult::Exited($n)
+        $crate::ExecutionResult::from($crate::ExecutionStatus::Exited($n))
     };
 }
 pub(crate) use result;
@@ -0,0 +1,369 @@
+//! Optional TLS for connections between the broker and its clients and workers.
+//!
+//! Both sides of a connection end up with a [Stream], which is either a plain TCP socket or a TLS
+//! session wrapped around one. The rest of the code doesn't know the difference.
+
+use crate::{Error, Result};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::rustls;
+
+/*              _     _ _
+ *  _ __  _   _| |__ | (_) ___
+ * | '_ \| | | | '_ \| | |/ __|
+ * | |_) | |_| | |_) | | | (__
+ * | .__/ \__,_|_.__/|_|_|\___|
+ * |_|
+ *  FIGLET: public
+ */
+
+/// A bidirectional byte stream to a peer. Use [tokio::io::split] to get separate read and write
+/// halves.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> AsyncStream for T {}
+
+/// A connection to a peer, with or without TLS.
+pub type Stream = Box<dyn AsyncStream>;
+
+/// TLS settings for the broker's listener. All files are PEM-encoded.
+#[derive(Clone, Debug)]
+pub struct ServerOptions {
+    /// The broker's certificate chain, leaf first.
+    pub cert_file: PathBuf,
+
+    /// The private key for the broker's certificate.
+    pub key_file: PathBuf,
+
+    /// If provided, clients and workers must present a certificate signed by one of the
+    /// certificate authorities in this file.
+    pub client_ca_file: Option<PathBuf>,
+}
+
+/// TLS settings for connecting to the broker. All files are PEM-encoded.
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    /// The certificate authorities used to verify the broker's certificate.
+    pub ca_file: PathBuf,
+
+    /// The certificate chain to present to the broker, leaf first. This is required if the broker
+    /// verifies client certificates.
+    pub cert_file: Option<PathBuf>,
+
+    /// The private key for `cert_file`. It must be provided if and only if `cert_file` is.
+    pub key_file: Option<PathBuf>,
+
+    /// The name to verify the broker's certificate against. If not provided, the broker's IP
+    /// address is used.
+    pub server_name: Option<String>,
+}
+
+/// Accepts connections on the broker, performing the TLS handshake if TLS is configured.
+#[derive(Clone)]
+pub struct Acceptor(Option<tokio_rustls::TlsAcceptor>);
+
+impl Acceptor {
+    pub fn new(options: Option<&ServerOptions>) -> Result<Self> {
+        Ok(Acceptor(match options {
+            None => None,
+            Some(options) => Some(Arc::new(server_config(options)?).into()),
+        }))
+    }
+
+    pub async fn accept(&self, socket: TcpStream) -> Result<Stream> {
+        Ok(match &self.0 {
+            None => Box::new(socket),
+            Some(acceptor) => Box::new(acceptor.accept(socket).await?),
+        })
+    }
+}
+
+/// Connects to the broker, performing the TLS handshake if TLS is configured.
+pub struct Connector(Option<(tokio_rustls::TlsConnector, Option<rustls::ServerName>)>);
+
+impl Connector {
+    pub fn new(options: Option<&ClientOptions>) -> Result<Self> {
+        Ok(Connector(match options {
+            None => None,
+            Some(options) => {
+                let server_name = match &options.server_name {
+                    None => None,
+                    Some(name) => Some(rustls::ServerName::try_from(name.as_str())?),
+                };
+                Some((Arc::new(client_config(options)?).into(), server_name))
+            }
+        }))
+    }
+
+    pub async fn connect(&self, addr: &SocketAddr) -> Result<Stream> {
+        let socket = TcpStream::connect(addr).await?;
+        Ok(match &self.0 {
+            None => Box::new(socket),
+            Some((connector, server_name)) => {
+                let server_name = server_name
+                    .clone()
+                    .unwrap_or(rustls::ServerName::IpAddress(addr.ip()));
+                Box::new(connector.connect(server_name, socket).await?)
+            }
+        })
+    }
+}
+
+/*             _            _
+ *  _ __  _ __(_)_   ____ _| |_ ___
+ * | '_ \| '__| \ \ / / _` | __/ _ \
+ * | |_) | |  | |\ V / (_| | ||  __/
+ * | .__/|_|  |_| \_/ \__,_|\__\___|
+ * |_|
+ *  FIGLET: private
+ */
+
+fn open(path: &Path) -> Result<std::io::BufReader<std::fs::File>> {
+    match std::fs::File::open(path) {
+        Ok(file) => Ok(std::io::BufReader::new(file)),
+        Err(err) => Err(Error::msg(format!(
+            "couldn't open {}: {err}",
+            path.display()
+        ))),
+    }
+}
+
+fn read_certs(path: &Path) -> Result<Vec<rustls::Certificate>> {
+    let certs = rustls_pemfile::certs(&mut open(path)?)?;
+    if certs.is_empty() {
+        return Err(Error::msg(format!(
+            "no certificates found in {}",
+            path.display()
+        )));
+    }
+    Ok(certs.into_iter().map(rustls::Certificate).collect())
+}
+
+fn read_key(path: &Path) -> Result<rustls::PrivateKey> {
+    for item in rustls_pemfile::read_all(&mut open(path)?)? {
+        use rustls_pemfile::Item::*;
+        if let RSAKey(key) | PKCS8Key(key) | ECKey(key) = item {
+            return Ok(rustls::PrivateKey(key));
+        }
+    }
+    Err(Error::msg(format!(
+        "no private key found in {}",
+        path.display()
+    )))
+}
+
+fn read_roots(path: &Path) -> Result<rustls::RootCertStore> {
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in read_certs(path)? {
+        roots.add(&cert)?;
+    }
+    Ok(roots)
+}
+
+fn server_config(options: &ServerOptions) -> Result<rustls::ServerConfig> {
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = match &options.client_ca_file {
+        None => builder.with_no_client_auth(),
+        Some(path) => builder.with_client_cert_verifier(
+            rustls::server::AllowAnyAuthenticatedClient::new(read_roots(path)?).boxed(),
+        ),
+    };
+    Ok(builder.with_single_cert(
+        read_certs(&options.cert_file)?,
+        read_key(&options.key_file)?,
+    )?)
+}
+
+fn client_config(options: &ClientOptions) -> Result<rustls::ClientConfig> {
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(read_roots(&options.ca_file)?);
+    Ok(match (&options.cert_file, &options.key_file) {
+        (None, None) => builder.with_no_client_auth(),
+        (Some(cert_file), Some(key_file)) => {
+            builder.with_client_auth_cert(read_certs(cert_file)?, read_key(key_file)?)?
+        }
+        _ => {
+            return Err(Error::msg(
+                "a client certificate and key must be provided together",
+            ))
+        }
+    })
+}
+
+/*  _            _
+ * | |_ ___  ___| |_ ___
+ * | __/ _ \/ __| __/ __|
+ * | ||  __/\__ \ |_\__ \
+ *  \__\___||___/\__|___/
+ *  FIGLET: tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto;
+
+    /// A certificate authority and certificates signed by it, written out to a temporary
+    /// directory.
+    struct Pki {
+        dir: tempfile::TempDir,
+        ca: rcgen::Certificate,
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let mut params = rcgen::CertificateParams::new(vec![]);
+            params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+            let ca = rcgen::Certificate::from_params(params).unwrap();
+            let pki = Pki {
+                dir: tempfile::tempdir().unwrap(),
+                ca,
+            };
+            std::fs::write(pki.path("ca.pem"), pki.ca.serialize_pem().unwrap()).unwrap();
+            pki
+        }
+
+        fn path(&self, name: &str) -> PathBuf {
+            self.dir.path().join(name)
+        }
+
+        /// Issue a certificate for `names` and write it to `{name}.pem` and `{name}.key`.
+        fn issue(&self, name: &str, names: &[&str]) {
+            let cert = rcgen::Certificate::from_params(rcgen::CertificateParams::new(
+                names.iter().map(|n| n.to_string()).collect::<Vec<_>>(),
+            ))
+            .unwrap();
+            std::fs::write(
+                self.path(&format!("{name}.pem")),
+                cert.serialize_pem_with_signer(&self.ca).unwrap(),
+            )
+            .unwrap();
+            std::fs::write(
+                self.path(&format!("{name}.key")),
+                cert.serialize_private_key_pem(),
+            )
+            .unwrap();
+        }
+
+        fn server_options(&self, verify_clients: bool) -> ServerOptions {
+            self.issue("broker", &["localhost", "127.0.0.1"]);
+            ServerOptions {
+                cert_file: self.path("broker.pem"),
+                key_file: self.path("broker.key"),
+                client_ca_file: verify_clients.then(|| self.path("ca.pem")),
+            }
+        }
+
+        fn client_options(&self, with_cert: bool, server_name: Option<&str>) -> ClientOptions {
+            self.issue("client", &["client"]);
+            ClientOptions {
+                ca_file: self.path("ca.pem"),
+                cert_file: with_cert.then(|| self.path("client.pem")),
+                key_file: with_cert.then(|| self.path("client.key")),
+                server_name: server_name.map(str::to_string),
+            }
+        }
+    }
+
+    /// Connect a client to a server, have the client send a message, and have the server echo it
+    /// back. Return the client's result.
+    async fn echo(server: Option<ServerOptions>, client: Option<ClientOptions>) -> Result<u32> {
+        let acceptor = Acceptor::new(server.as_ref()).unwrap();
+        let connector = Connector::new(client.as_ref()).unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::task::spawn(async move {
+            let (socket, _) = listener.accept().await?;
+            let mut stream = acceptor.accept(socket).await?;
+            let msg: u32 = proto::read_message(&mut stream, proto::DEFAULT_MAX_FRAME_SIZE).await?;
+            proto::write_message(&mut stream, msg).await
+        });
+        let mut stream = connector.connect(&addr).await?;
+        proto::write_message(&mut stream, 42u32).await?;
+        let result = proto::read_message(&mut stream, proto::DEFAULT_MAX_FRAME_SIZE).await;
+        server.await.unwrap().ok();
+        result
+    }
+
+    #[tokio::test]
+    async fn plaintext() {
+        assert_eq!(echo(None, None).await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn tls_by_ip_address() {
+        let pki = Pki::new();
+        let result = echo(
+            Some(pki.server_options(false)),
+            Some(pki.client_options(false, None)),
+        );
+        assert_eq!(result.await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn tls_by_server_name() {
+        let pki = Pki::new();
+        let result = echo(
+            Some(pki.server_options(false)),
+            Some(pki.client_options(false, Some("localhost"))),
+        );
+        assert_eq!(result.await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn tls_wrong_server_name() {
+        let pki = Pki::new();
+        let result = echo(
+            Some(pki.server_options(false)),
+            Some(pki.client_options(false, Some("example.com"))),
+        );
+        assert!(result.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn tls_untrusted_server() {
+        let pki = Pki::new();
+        let other_pki = Pki::new();
+        let result = echo(
+            Some(pki.server_options(false)),
+            Some(other_pki.client_options(false, None)),
+        );
+        assert!(result.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn mutual_tls() {
+        let pki = Pki::new();
+        let result = echo(
+            Some(pki.server_options(true)),
+            Some(pki.client_options(true, None)),
+        );
+        assert_eq!(result.await.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn mutual_tls_without_client_cert() {
+        let pki = Pki::new();
+        let result = echo(
+            Some(pki.server_options(true)),
+            Some(pki.client_options(false, None)),
+        );
+        assert!(result.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn client_cert_without_key() {
+        let pki = Pki::new();
+        let mut options = pki.client_options(true, None);
+        options.key_file = None;
+        assert!(Connector::new(Some(&options)).is_err());
+    }
+}
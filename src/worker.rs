@@ -1,34 +1,399 @@
 //! Code for the worker binary.
 
 pub mod cache;
+mod cgroup;
 mod dispatcher;
 mod executor;
+pub mod fetcher;
+mod sandbox;
 
-use crate::{channel_reader, proto, Error, ExecutionDetails, ExecutionId, Result};
+use crate::{
+    auth::SharedKey, channel_reader, proto, tls, Error, ExecutionDetails, ExecutionId,
+    ExecutionResult, ExecutionStatus, ProcessLimits, ResourceLimits, Result, Sha256Digest,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
 
 type DispatcherReceiver = tokio::sync::mpsc::UnboundedReceiver<dispatcher::Message>;
 type DispatcherSender = tokio::sync::mpsc::UnboundedSender<dispatcher::Message>;
 type BrokerSocketSender = tokio::sync::mpsc::UnboundedSender<proto::WorkerResponse>;
+type CacheReceiver = tokio::sync::mpsc::UnboundedReceiver<CacheMessage>;
+type CacheSender = tokio::sync::mpsc::UnboundedSender<CacheMessage>;
+
+/// Where the worker gets the image layers executions ask for, and where it keeps them, along with
+/// executions' scratch directories.
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    /// The directory to extract layers into and to create scratch directories in. Anything already
+    /// in it is removed when the worker starts, so workers must not share it.
+    pub root: PathBuf,
+
+    /// How many bytes of extracted layers to keep around once no execution is using them. See
+    /// [cache::Cache::new].
+    pub bytes_used_goal: u64,
+
+    /// The directory to get layers from, as tar files named after their digest, like
+    /// `{digest}.tar`. If None, executions with layers fail.
+    pub layer_dir: Option<PathBuf>,
+}
+
+/// The cgroup the worker runs executions' cgroups in, to enforce their [ResourceLimits].
+#[derive(Clone, Debug)]
+pub struct CgroupConfig {
+    /// A cgroup v2 directory that the worker may manage as it pleases. See
+    /// [cgroup::CgroupParent::new].
+    pub path: PathBuf,
+
+    /// The limits for executions that don't set their own.
+    pub defaults: ResourceLimits,
+}
+
+type LayerHandle = cache::CacheHandle<CacheHandleAdapter>;
+
+enum CacheMessage {
+    /// Get a layer for an execution. None is sent back if the layer couldn't be fetched.
+    GetLayer(
+        Sha256Digest,
+        tokio::sync::oneshot::Sender<Option<LayerHandle>>,
+    ),
+    ToCache(cache::Message),
+}
+
+#[derive(Clone)]
+struct CacheHandleAdapter(CacheSender);
+
+impl cache::CacheHandleDeps for CacheHandleAdapter {
+    fn send_increment_refcount(&mut self, digest: Sha256Digest) {
+        let message = cache::Message::IncrementRefcount(digest);
+        self.0.send(CacheMessage::ToCache(message)).ok();
+    }
+
+    fn send_decrement_refcount(&mut self, digest: Sha256Digest) {
+        let message = cache::Message::DecrementRefcount(digest);
+        self.0.send(CacheMessage::ToCache(message)).ok();
+    }
+}
+
+/// Remove a directory tree. Executions can leave behind directories that we can't read or write,
+/// like with `chmod 555 $TMPDIR/sub`, so if removing the tree fails, give ourselves permission to
+/// every directory in it and try again.
+fn remove_recursively(path: &Path) -> Result<()> {
+    if std::fs::remove_dir_all(path).is_err() {
+        make_directories_writable(path)?;
+        std::fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
+
+fn make_directories_writable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt as _;
+    let metadata = path.symlink_metadata()?;
+    if !metadata.is_dir() {
+        return Ok(());
+    }
+    let mut permissions = metadata.permissions();
+    if permissions.mode() & 0o700 != 0o700 {
+        permissions.set_mode(permissions.mode() | 0o700);
+        std::fs::set_permissions(path, permissions)?;
+    }
+    for entry in std::fs::read_dir(path)? {
+        make_directories_writable(&entry?.path())?;
+    }
+    Ok(())
+}
+
+struct CacheAdapter {
+    rng: rand::rngs::StdRng,
+    layer_dir: Option<PathBuf>,
+    cache_handle_deps: CacheHandleAdapter,
+    next_request_id: u64,
+    waiting: HashMap<cache::CacheRequestId, tokio::sync::oneshot::Sender<Option<LayerHandle>>>,
+}
+
+impl cache::CacheDeps for CacheAdapter {
+    type Rng = rand::rngs::StdRng;
+
+    fn rng(&mut self) -> &mut Self::Rng {
+        &mut self.rng
+    }
+
+    fn file_exists(&mut self, path: &Path) -> bool {
+        path.symlink_metadata().is_ok()
+    }
+
+    fn rename(&mut self, source: &Path, destination: &Path) -> Result<()> {
+        Ok(std::fs::rename(source, destination)?)
+    }
+
+    fn remove_recursively_on_thread(&mut self, path: PathBuf) {
+        std::thread::spawn(move || {
+            if let Err(err) = remove_recursively(&path) {
+                eprintln!("couldn't remove {}: {err}", path.display());
+            }
+        });
+    }
+
+    fn mkdir_recursively(&mut self, path: &Path) -> Result<()> {
+        Ok(std::fs::create_dir_all(path)?)
+    }
+
+    type ReadDirIterator = std::vec::IntoIter<PathBuf>;
+
+    fn read_dir(&mut self, path: &Path) -> Result<Self::ReadDirIterator> {
+        let children: std::io::Result<Vec<_>> = std::fs::read_dir(path)?
+            .map(|entry| Ok(entry?.path()))
+            .collect();
+        Ok(children?.into_iter())
+    }
+
+    fn download_and_extract(&mut self, digest: Sha256Digest, path: PathBuf) {
+        let layer_dir = self.layer_dir.clone();
+        let sender = self.cache_handle_deps.0.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = match layer_dir {
+                None => Err(Error::msg("worker has no layer directory")),
+                Some(layer_dir) => fetcher::fetch_layer(&layer_dir, digest.clone(), &path),
+            };
+            if let Err(err) = &result {
+                eprintln!("couldn't fetch layer {digest}: {err}");
+            }
+            let message = cache::Message::DownloadAndExtractCompleted(digest, result);
+            sender.send(CacheMessage::ToCache(message)).ok();
+        });
+    }
+
+    fn get_completed(&mut self, request_id: cache::CacheRequestId, handle: Option<LayerHandle>) {
+        // If the execution has been canceled in the meantime, the handle is just dropped.
+        let sender = self.waiting.remove(&request_id).unwrap();
+        sender.send(handle).ok();
+    }
+
+    type CacheHandleDeps = CacheHandleAdapter;
+
+    fn cache_handle_deps(&self) -> &Self::CacheHandleDeps {
+        &self.cache_handle_deps
+    }
+}
+
+/// Create the cache described by `config`. This clears out its directory, so it has to be done
+/// before any executions are started.
+fn new_cache(
+    config: CacheConfig,
+    cache_sender: CacheSender,
+) -> Result<(cache::Cache, CacheAdapter)> {
+    use rand::SeedableRng as _;
+    let mut adapter = CacheAdapter {
+        rng: rand::rngs::StdRng::from_entropy(),
+        layer_dir: config.layer_dir,
+        cache_handle_deps: CacheHandleAdapter(cache_sender),
+        next_request_id: 0,
+        waiting: HashMap::default(),
+    };
+    let cache = cache::Cache::new(&config.root, &mut adapter, config.bytes_used_goal)?;
+    Ok((cache, adapter))
+}
+
+async fn cache_main(
+    mut cache: cache::Cache,
+    mut adapter: CacheAdapter,
+    cache_receiver: CacheReceiver,
+) {
+    channel_reader::run(cache_receiver, |msg| match msg {
+        CacheMessage::GetLayer(digest, sender) => {
+            let request_id = cache::CacheRequestId(adapter.next_request_id);
+            adapter.next_request_id += 1;
+            adapter.waiting.insert(request_id, sender);
+            let message = cache::Message::GetRequest(request_id, digest);
+            cache.receive_message(&mut adapter, message);
+        }
+        CacheMessage::ToCache(message) => cache.receive_message(&mut adapter, message),
+    })
+    .await;
+}
+
+/// Get a handle on each of the layers from the cache, in order. Return the digest of the first
+/// layer that couldn't be fetched if there is one.
+async fn get_layers(
+    cache_sender: &CacheSender,
+    layers: &[Sha256Digest],
+) -> std::result::Result<Vec<LayerHandle>, Sha256Digest> {
+    let mut receivers = vec![];
+    for digest in layers {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        cache_sender
+            .send(CacheMessage::GetLayer(digest.clone(), sender))
+            .ok();
+        receivers.push((digest, receiver));
+    }
+    let mut handles = vec![];
+    for (digest, receiver) in receivers {
+        match receiver.await {
+            Ok(Some(handle)) => handles.push(handle),
+            _ => return Err(digest.clone()),
+        }
+    }
+    Ok(handles)
+}
+
+/// An execution's scratch directory, which the cache removes once it's dropped.
+struct ScratchDir {
+    path: PathBuf,
+    cache_sender: CacheSender,
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let message = cache::Message::RemoveScratch(std::mem::take(&mut self.path));
+        self.cache_sender.send(CacheMessage::ToCache(message)).ok();
+    }
+}
+
+/// Cancels the execution when dropped, whether it is still waiting for its layers or running.
+struct ExecutionHandle(tokio::task::JoinHandle<()>);
+
+impl Drop for ExecutionHandle {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Tells the dispatcher that an execution is done. The dispatcher keeps an execution's resources
+/// reserved until it hears this, so if the execution is canceled before it starts, this reports
+/// that when it's dropped.
+struct DoneSender {
+    id: ExecutionId,
+    dispatcher_sender: Option<DispatcherSender>,
+}
+
+impl DoneSender {
+    fn send(mut self, result: ExecutionResult) {
+        let dispatcher_sender = self.dispatcher_sender.take().unwrap();
+        dispatcher_sender
+            .send(dispatcher::Message::FromExecutor(self.id, result))
+            .ok();
+    }
+}
+
+impl Drop for DoneSender {
+    fn drop(&mut self) {
+        if let Some(dispatcher_sender) = self.dispatcher_sender.take() {
+            let status = ExecutionStatus::Error("canceled before it started".to_string());
+            dispatcher_sender
+                .send(dispatcher::Message::FromExecutor(self.id, status.into()))
+                .ok();
+        }
+    }
+}
 
 struct DispatcherAdapter {
     dispatcher_sender: DispatcherSender,
     broker_socket_sender: BrokerSocketSender,
+    cache_sender: CacheSender,
+    cgroup_parent: Option<cgroup::CgroupParent>,
+    default_process_limits: ProcessLimits,
+    scratch_root: PathBuf,
+    next_scratch_id: u64,
+}
+
+impl DispatcherAdapter {
+    fn create_scratch_dir(&mut self) -> Result<ScratchDir> {
+        use std::os::unix::fs::DirBuilderExt as _;
+        let path = self.scratch_root.join(self.next_scratch_id.to_string());
+        self.next_scratch_id += 1;
+        std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&path)
+            .map_err(|err| {
+                Error::msg(format!(
+                    "couldn't create scratch directory {}: {err}",
+                    path.display()
+                ))
+            })?;
+        Ok(ScratchDir {
+            path,
+            cache_sender: self.cache_sender.clone(),
+        })
+    }
 }
 
 impl dispatcher::DispatcherDeps for DispatcherAdapter {
-    type ExecutionHandle = executor::Handle;
+    type ExecutionHandle = ExecutionHandle;
 
     fn start_execution(
         &mut self,
         id: ExecutionId,
-        details: ExecutionDetails,
+        mut details: ExecutionDetails,
     ) -> Self::ExecutionHandle {
-        let sender = self.dispatcher_sender.clone();
-        executor::start(details, move |result| {
-            sender
-                .send(dispatcher::Message::FromExecutor(id, result))
-                .ok();
-        })
+        details.process_limits = details.process_limits.or(self.default_process_limits);
+        let output_sender = details
+            .stream_output
+            .then(|| self.dispatcher_sender.clone());
+        let done = DoneSender {
+            id,
+            dispatcher_sender: Some(self.dispatcher_sender.clone()),
+        };
+        let cgroup = match &mut self.cgroup_parent {
+            Some(parent) => parent.create(details.limits).map(Some),
+            None if details.limits == ResourceLimits::default() => Ok(None),
+            None => Err(Error::msg(
+                "worker has no cgroup to enforce resource limits",
+            )),
+        };
+        let scratch_dir = details
+            .scratch
+            .map(|_| self.create_scratch_dir())
+            .transpose();
+        let cache_sender = self.cache_sender.clone();
+        ExecutionHandle(tokio::task::spawn(async move {
+            let (cgroup, scratch_dir) = match (cgroup, scratch_dir) {
+                (Ok(cgroup), Ok(scratch_dir)) => (cgroup, scratch_dir),
+                (Err(err), _) | (_, Err(err)) => {
+                    done.send(ExecutionStatus::Error(err.to_string()).into());
+                    return;
+                }
+            };
+            let handles = match get_layers(&cache_sender, &details.layers).await {
+                Ok(handles) => handles,
+                Err(digest) => {
+                    let error = format!("couldn't fetch layer {digest}");
+                    done.send(ExecutionStatus::Error(error).into());
+                    return;
+                }
+            };
+            let layers = handles
+                .iter()
+                .map(|handle| handle.path().to_owned())
+                .collect();
+            let scratch_path = scratch_dir
+                .as_ref()
+                .map(|scratch_dir| scratch_dir.path.clone());
+            let (finished_sender, finished_receiver) = tokio::sync::oneshot::channel();
+            // Dropping this kills the execution if the task is aborted.
+            let _handle = executor::start(
+                details,
+                layers,
+                scratch_path.as_deref(),
+                cgroup,
+                move |stream, chunk| {
+                    if let Some(output_sender) = &output_sender {
+                        output_sender
+                            .send(dispatcher::Message::OutputFromExecutor(id, stream, chunk))
+                            .ok();
+                    }
+                },
+                move |result| {
+                    // The execution's layers can't be removed until it's done with them, nor can
+                    // its scratch directory.
+                    drop(handles);
+                    drop(scratch_dir);
+                    done.send(result);
+                    finished_sender.send(()).ok();
+                },
+            );
+            finished_receiver.await.ok();
+        }))
     }
 
     fn send_response_to_broker(&mut self, message: proto::WorkerResponse) {
@@ -36,39 +401,98 @@ impl dispatcher::DispatcherDeps for DispatcherAdapter {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn dispatcher_main(
     slots: usize,
+    memory: u64,
     dispatcher_receiver: DispatcherReceiver,
     dispatcher_sender: DispatcherSender,
     broker_socket_sender: BrokerSocketSender,
+    cache_sender: CacheSender,
+    cgroup_parent: Option<cgroup::CgroupParent>,
+    default_process_limits: ProcessLimits,
+    scratch_root: PathBuf,
 ) {
     let adapter = DispatcherAdapter {
         dispatcher_sender,
         broker_socket_sender,
+        cache_sender,
+        cgroup_parent,
+        default_process_limits,
+        scratch_root,
+        next_scratch_id: 0,
     };
-    let mut dispatcher = dispatcher::Dispatcher::new(adapter, slots);
+    let mut dispatcher = dispatcher::Dispatcher::new(adapter, slots, memory);
     channel_reader::run(dispatcher_receiver, |msg| dispatcher.receive_message(msg)).await;
 }
 
+/// The total amount of RAM on this machine, in bytes.
+fn detected_memory() -> Result<u64> {
+    Ok(nix::sys::sysinfo::sysinfo()?.ram_total())
+}
+
+/// Labels describing this machine that every worker advertises.
+fn detected_labels() -> BTreeMap<String, String> {
+    BTreeMap::from([
+        ("arch".to_string(), std::env::consts::ARCH.to_string()),
+        ("os".to_string(), std::env::consts::OS.to_string()),
+    ])
+}
+
 async fn signal_handler(kind: tokio::signal::unix::SignalKind) -> Result<()> {
     tokio::signal::unix::signal(kind)?.recv().await;
     Err(Error::msg(format!("received signal {kind:?}")))
 }
 
 /// The main function for the worker. This should be called on a task of its own. It will return
-/// when a signal is received or when one of the worker tasks completes because of an error.
-pub async fn main(name: String, slots: usize, broker_addr: std::net::SocketAddr) -> Result<()> {
-    let (read_stream, mut write_stream) = tokio::net::TcpStream::connect(&broker_addr)
-        .await?
-        .into_split();
-    let read_stream = tokio::io::BufReader::new(read_stream);
-
-    proto::write_message(
+/// when a signal is received or when one of the worker tasks completes because of an error,
+/// including when the broker misses too many heartbeats. The `key`, if provided, is used to answer
+/// the broker's authentication challenge. If `tls` is provided, the connection to the broker uses
+/// TLS.
+///
+/// The worker advertises `labels` to the broker, along with `arch` and `os` labels describing this
+/// machine, unless `labels` overrides them. It also advertises `memory` bytes of memory for
+/// executions to reserve, which defaults to all of the machine's RAM.
+///
+/// Image layers for executions are fetched and cached as described by `cache`, which also holds
+/// executions' scratch directories. If `cgroup` is provided, executions run in cgroups of their
+/// own inside of it, which enforce their resource limits. Otherwise, executions with resource
+/// limits fail. Executions that don't set all of their [ProcessLimits] get the rest from
+/// `default_process_limits`.
+#[allow(clippy::too_many_arguments)]
+pub async fn main(
+    name: String,
+    slots: usize,
+    memory: Option<u64>,
+    labels: BTreeMap<String, String>,
+    broker_addr: std::net::SocketAddr,
+    key: Option<SharedKey>,
+    tls: Option<tls::ClientOptions>,
+    cache: CacheConfig,
+    cgroup: Option<CgroupConfig>,
+    default_process_limits: ProcessLimits,
+) -> Result<()> {
+    let memory = match memory {
+        Some(memory) => memory,
+        None => detected_memory()?,
+    };
+    let cgroup_parent = cgroup
+        .map(|config| cgroup::CgroupParent::new(config.path, config.defaults))
+        .transpose()?;
+    let connector = tls::Connector::new(tls.as_ref())?;
+    let (read_stream, mut write_stream) = tokio::io::split(connector.connect(&broker_addr).await?);
+    let mut read_stream = tokio::io::BufReader::new(read_stream);
+
+    let heartbeat = proto::send_hello(
+        &mut read_stream,
         &mut write_stream,
-        proto::Hello::Worker {
+        proto::Hello::new(proto::Peer::Worker {
             name,
             slots: slots as u32,
-        },
+            memory,
+            labels: detected_labels().into_iter().chain(labels).collect(),
+        }),
+        key.as_ref(),
     )
     .await?;
 
@@ -77,23 +501,43 @@ pub async fn main(name: String, slots: usize, broker_addr: std::net::SocketAddr)
 
     let (broker_socket_sender, broker_socket_receiver) = tokio::sync::mpsc::unbounded_channel();
 
+    let (cache_sender, cache_receiver) = tokio::sync::mpsc::unbounded_channel();
+    // Executions' scratch directories are passed to them in TMPDIR, so they mustn't be relative.
+    let scratch_root = std::path::absolute(cache::Cache::scratch_root(&cache.root))?;
+    let (cache, cache_adapter) = new_cache(cache, cache_sender.clone())?;
+
     let mut join_set = tokio::task::JoinSet::new();
     join_set.spawn(proto::socket_reader(
         read_stream,
+        proto::DEFAULT_MAX_FRAME_SIZE,
+        heartbeat,
         dispatcher_sender_clone,
         dispatcher::Message::FromBroker,
     ));
-    join_set.spawn(proto::socket_writer(broker_socket_receiver, write_stream));
+    join_set.spawn(proto::socket_writer(
+        broker_socket_receiver,
+        write_stream,
+        heartbeat,
+    ));
     join_set.spawn(async move {
         dispatcher_main(
             slots,
+            memory,
             dispatcher_receiver,
             dispatcher_sender,
             broker_socket_sender,
+            cache_sender,
+            cgroup_parent,
+            default_process_limits,
+            scratch_root,
         )
         .await;
         Ok(())
     });
+    join_set.spawn(async move {
+        cache_main(cache, cache_adapter, cache_receiver).await;
+        Ok(())
+    });
     join_set.spawn(signal_handler(tokio::signal::unix::SignalKind::interrupt()));
     join_set.spawn(signal_handler(tokio::signal::unix::SignalKind::terminate()));
 
@@ -105,3 +549,32 @@ pub async fn main(name: String, slots: usize, broker_addr: std::net::SocketAddr)
             .expect("no task should panic or be canceled")?;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt as _;
+
+    #[test]
+    fn remove_recursively_removes_read_only_directories() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let scratch = temp_dir.path().join("scratch");
+        for dir in ["sub/inner", "unreadable"] {
+            std::fs::create_dir_all(scratch.join(dir)).unwrap();
+        }
+        std::fs::write(scratch.join("sub/inner/file"), b"hello").unwrap();
+        std::fs::write(scratch.join("unreadable/file"), b"hello").unwrap();
+        for (dir, mode) in [("sub/inner", 0o555), ("sub", 0o555), ("unreadable", 0o000)] {
+            let permissions = std::fs::Permissions::from_mode(mode);
+            std::fs::set_permissions(scratch.join(dir), permissions).unwrap();
+        }
+        remove_recursively(&scratch).unwrap();
+        assert!(!scratch.exists());
+    }
+
+    #[test]
+    fn remove_recursively_fails_on_missing_directory() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        assert!(remove_recursively(&temp_dir.path().join("missing")).is_err());
+    }
+}
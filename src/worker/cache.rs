@@ -24,7 +24,7 @@ use std::{
 /// actually doesn't care if they are unique, but the caller would likely be confused if they
 /// weren't.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-pub struct CacheRequestId(u64);
+pub struct CacheRequestId(pub u64);
 
 /// As long as at least one [CacheHandle] is alive for a given [Sha256Digest], the cache won't
 /// delete the underlying directory. [CacheHandle] is [Clone] and [Drop], which it uses to
@@ -94,26 +94,25 @@ pub trait CacheDeps {
     /// false otherwise. Panic on file system error.
     fn file_exists(&mut self, path: &Path) -> bool;
 
-    /// Rename `source` to `destination`. Panic on file system error. Assume that all intermediate
-    /// directories exist for `destination`, and that `source` and `destination` are on the same
-    /// file system.
-    fn rename(&mut self, source: &Path, destination: &Path);
+    /// Rename `source` to `destination`. Assume that all intermediate directories exist for
+    /// `destination`, and that `source` and `destination` are on the same file system.
+    fn rename(&mut self, source: &Path, destination: &Path) -> Result<()>;
 
     /// Remove `path`, and if `path` is a directory, all descendants of `path`. Do this on a
     /// separate thread. Panic on file system error.
     fn remove_recursively_on_thread(&mut self, path: PathBuf);
 
-    /// Ensure `path` exists and is a directory. If it doesn't exist, recusively ensure its parent exists,
-    /// then create it. Panic on file system error or if `path` or any of its ancestors aren't
+    /// Ensure `path` exists and is a directory. If it doesn't exist, recusively ensure its parent
+    /// exists, then create it. Return an error if `path` or any of its ancestors aren't
     /// directories.
-    fn mkdir_recursively(&mut self, path: &Path);
+    fn mkdir_recursively(&mut self, path: &Path) -> Result<()>;
 
     /// The type of the iterator returned by [Self::read_dir].
     type ReadDirIterator: Iterator<Item = PathBuf>;
 
-    /// Return and iterator that will yield all of the children of a directory. Panic on file
-    /// system error or if `path` doesn't exist or isn't a directory.
-    fn read_dir(&mut self, path: &Path) -> Self::ReadDirIterator;
+    /// Return and iterator that will yield all of the children of a directory. Return an error if
+    /// `path` doesn't exist or isn't a directory.
+    fn read_dir(&mut self, path: &Path) -> Result<Self::ReadDirIterator>;
 
     /// Download `digest` from somewhere and extract it into `path`. Assume that `path` does not exist, but
     /// that its parent directory does. Validate the digest while downloading and extracting. When
@@ -154,6 +153,10 @@ pub enum Message {
     /// Tell the [Cache] to decrement the refcount on a [CacheHandle]. These are sent by
     /// [CacheHandleDeps::send_decrement_refcount].
     DecrementRefcount(Sha256Digest),
+
+    /// Tell the [Cache] that an execution is done with a scratch directory in
+    /// [Cache::scratch_root]. It's removed the same way as artifacts are.
+    RemoveScratch(PathBuf),
 }
 
 /// Manage a directory of downloaded, extracted images. Coordinate fetching of these images, and
@@ -163,6 +166,9 @@ pub struct Cache {
     root: PathBuf,
     entries: HashMap<Sha256Digest, CacheEntry>,
     heap: Heap<HashMap<Sha256Digest, CacheEntry>>,
+    /// Digests whose directories couldn't be moved out of the way when they were removed. They
+    /// have to be moved before the digest can be downloaded again.
+    left_behind: HashSet<Sha256Digest>,
     next_priority: u64,
     bytes_used: u64,
     bytes_used_goal: u64,
@@ -170,38 +176,48 @@ pub struct Cache {
 
 impl Cache {
     /// Create a new [Cache] rooted at `root`. The directory `root` and all necessary ancestors
-    /// will be created, along with `{root}/removing` and `{root}/sha256`. Any pre-existing entries
-    /// in `{root}/removing` and `{root}/sha256` will be removed. That implies that the [Cache]
+    /// will be created, along with `{root}/removing`, `{root}/sha256`, and `{root}/scratch`. Any
+    /// pre-existing entries in those directories will be removed. That implies that the [Cache]
     /// doesn't currently keep data stored across invocations.
     ///
     /// `bytes_used_goal` is the goal on-disk size for the cache. The cache will periodically grow
     /// larger than this size, but then shrink back down to this size. Ideally, the cache would use
     /// this as a hard upper bound, but that's not how it currently works.
-    pub fn new(root: &Path, deps: &mut impl CacheDeps, bytes_used_goal: u64) -> Self {
+    ///
+    /// Return an error if the directories can't be created or cleared out.
+    pub fn new(root: &Path, deps: &mut impl CacheDeps, bytes_used_goal: u64) -> Result<Self> {
         let mut path = root.to_owned();
 
         path.push("removing");
-        deps.mkdir_recursively(&path);
-        for child in deps.read_dir(&path) {
+        deps.mkdir_recursively(&path)?;
+        for child in deps.read_dir(&path)? {
             deps.remove_recursively_on_thread(child);
         }
         path.pop();
 
         path.push("sha256");
         if deps.file_exists(&path) {
-            Self::remove_in_background(deps, root, &path);
+            Self::remove_in_background(deps, root, &path)?;
+        }
+        deps.mkdir_recursively(&path)?;
+        path.pop();
+
+        path.push("scratch");
+        if deps.file_exists(&path) {
+            Self::remove_in_background(deps, root, &path)?;
         }
-        deps.mkdir_recursively(&path);
+        deps.mkdir_recursively(&path)?;
         path.pop();
 
-        Cache {
+        Ok(Cache {
             root: root.to_owned(),
             entries: HashMap::default(),
             heap: Heap::default(),
+            left_behind: HashSet::default(),
             next_priority: 0,
             bytes_used: 0,
             bytes_used_goal,
-        }
+        })
     }
 
     /// Receive a message and act on it. See [Message].
@@ -217,8 +233,19 @@ impl Cache {
             }
             IncrementRefcount(digest) => self.receive_increment_refcount(digest),
             DecrementRefcount(digest) => self.receive_decrement_refcount(deps, digest),
+            RemoveScratch(path) => {
+                if let Err(err) = Self::remove_in_background(deps, &self.root, &path) {
+                    eprintln!("couldn't remove {}: {err}", path.display());
+                }
+            }
         }
     }
+
+    /// The directory, in a [Cache] rooted at `root`, for the worker to create executions' scratch
+    /// directories in. Send [Message::RemoveScratch] once one is no longer in use.
+    pub fn scratch_root(root: &Path) -> PathBuf {
+        root.join("scratch")
+    }
 }
 
 /*             _            _
@@ -257,7 +284,7 @@ enum CacheEntry {
 }
 
 impl Cache {
-    fn remove_in_background(deps: &mut impl CacheDeps, root: &Path, source: &Path) {
+    fn remove_in_background(deps: &mut impl CacheDeps, root: &Path, source: &Path) -> Result<()> {
         use rand::Rng;
         let mut target = root.to_owned();
         target.push("removing");
@@ -271,8 +298,19 @@ impl Cache {
                 target.pop();
             }
         }
-        deps.rename(source, &target);
+        deps.rename(source, &target)?;
         deps.remove_recursively_on_thread(target);
+        Ok(())
+    }
+
+    /// Remove the directory for `digest` in the background. If it can't be moved out of the way,
+    /// remember that, so that it can be tried again before the digest is next downloaded.
+    fn remove_digest_in_background(&mut self, deps: &mut impl CacheDeps, digest: Sha256Digest) {
+        let path = Self::cache_path(&self.root, &digest);
+        if let Err(err) = Self::remove_in_background(deps, &self.root, &path) {
+            eprintln!("couldn't remove {}: {err}", path.display());
+            self.left_behind.insert(digest);
+        }
     }
 
     fn cache_path(root: &Path, digest: &Sha256Digest) -> PathBuf {
@@ -308,6 +346,14 @@ impl Cache {
         match self.entries.get_mut(&digest) {
             None => {
                 let cache_path = Self::cache_path(&self.root, &digest);
+                if self.left_behind.contains(&digest) {
+                    if let Err(err) = Self::remove_in_background(deps, &self.root, &cache_path) {
+                        eprintln!("couldn't remove {}: {err}", cache_path.display());
+                        deps.get_completed(request_id, None);
+                        return;
+                    }
+                    self.left_behind.remove(&digest);
+                }
                 deps.download_and_extract(digest.clone(), cache_path);
                 self.entries.insert(
                     digest,
@@ -326,7 +372,8 @@ impl Cache {
                     bytes_used,
                     heap_index,
                     ..
-                } = *entry else {
+                } = *entry
+                else {
                     unreachable!()
                 };
                 *entry = CacheEntry::InUse {
@@ -351,7 +398,7 @@ impl Cache {
                 }
                 let cache_path = Self::cache_path(&self.root, &digest);
                 if deps.file_exists(&cache_path) {
-                    Self::remove_in_background(deps, &self.root, &cache_path);
+                    self.remove_digest_in_background(deps, digest);
                 }
             }
             _ => {
@@ -368,8 +415,7 @@ impl Cache {
                 }
                 Some(digest) => match self.entries.remove(&digest) {
                     Some(CacheEntry::InHeap { bytes_used, .. }) => {
-                        let path = Self::cache_path(&self.root, &digest);
-                        Self::remove_in_background(deps, &self.root, &path);
+                        self.remove_digest_in_background(deps, digest);
                         self.bytes_used = self.bytes_used.checked_sub(bytes_used).unwrap();
                     }
                     _ => {
@@ -388,7 +434,9 @@ impl Cache {
     ) {
         match self.entries.get_mut(&digest) {
             Some(entry @ CacheEntry::DownloadingAndExtracting(_)) => {
-                let CacheEntry::DownloadingAndExtracting(requests) = entry else { unreachable!() };
+                let CacheEntry::DownloadingAndExtracting(requests) = entry else {
+                    unreachable!()
+                };
                 let mut refcount = 0;
                 for request_id in requests.iter() {
                     refcount += 1;
@@ -511,7 +559,8 @@ mod tests {
         }
 
         fn try_fill_bytes(&mut self, dest: &mut [u8]) -> std::result::Result<(), rand_core::Error> {
-            Ok(self.fill_bytes(dest))
+            self.fill_bytes(dest);
+            Ok(())
         }
     }
 
@@ -540,6 +589,7 @@ mod tests {
         messages: Vec<TestMessage>,
         existing_files: HashSet<PathBuf>,
         directories: HashMap<PathBuf, Vec<PathBuf>>,
+        unrenameable_files: HashSet<PathBuf>,
         rng: CountingRng,
         cache_handle_deps: TestCacheHandleDeps,
     }
@@ -556,28 +606,35 @@ mod tests {
             self.existing_files.contains(path)
         }
 
-        fn rename(&mut self, source: &Path, destination: &Path) {
+        fn rename(&mut self, source: &Path, destination: &Path) -> Result<()> {
             self.messages
                 .push(Rename(source.to_owned(), destination.to_owned()));
+            if self.unrenameable_files.contains(source) {
+                Err(anyhow!("permission denied"))
+            } else {
+                Ok(())
+            }
         }
 
         fn remove_recursively_on_thread(&mut self, path: PathBuf) {
             self.messages.push(RemoveRecursively(path.to_owned()));
         }
 
-        fn mkdir_recursively(&mut self, path: &Path) {
+        fn mkdir_recursively(&mut self, path: &Path) -> Result<()> {
             self.messages.push(MkdirRecursively(path.to_owned()));
+            Ok(())
         }
 
         type ReadDirIterator = <Vec<PathBuf> as IntoIterator>::IntoIter;
 
-        fn read_dir(&mut self, path: &Path) -> Self::ReadDirIterator {
+        fn read_dir(&mut self, path: &Path) -> Result<Self::ReadDirIterator> {
             self.messages.push(ReadDir(path.to_owned()));
-            self.directories
+            Ok(self
+                .directories
                 .get(path)
                 .unwrap_or(&vec![])
                 .clone()
-                .into_iter()
+                .into_iter())
         }
 
         fn download_and_extract(&mut self, digest: Sha256Digest, prefix: PathBuf) {
@@ -619,7 +676,8 @@ mod tests {
                 Path::new("/cache/root"),
                 &mut test_cache_deps,
                 bytes_used_goal,
-            );
+            )
+            .unwrap();
             Fixture {
                 test_cache_deps,
                 cache,
@@ -1049,6 +1107,8 @@ mod tests {
             ReadDir(path_buf!("/cache/root/removing")),
             FileExists(path_buf!("/cache/root/sha256")),
             MkdirRecursively(path_buf!("/cache/root/sha256")),
+            FileExists(path_buf!("/cache/root/scratch")),
+            MkdirRecursively(path_buf!("/cache/root/scratch")),
         ]);
     }
 
@@ -1070,6 +1130,8 @@ mod tests {
             RemoveRecursively(short_path!("/cache/root/removing", 20)),
             FileExists(path_buf!("/cache/root/sha256")),
             MkdirRecursively(path_buf!("/cache/root/sha256")),
+            FileExists(path_buf!("/cache/root/scratch")),
+            MkdirRecursively(path_buf!("/cache/root/scratch")),
         ]);
     }
 
@@ -1091,7 +1153,148 @@ mod tests {
             ),
             RemoveRecursively(short_path!("/cache/root/removing", 1)),
             MkdirRecursively(path_buf!("/cache/root/sha256")),
+            FileExists(path_buf!("/cache/root/scratch")),
+            MkdirRecursively(path_buf!("/cache/root/scratch")),
+        ]);
+    }
+
+    #[test]
+    fn new_removes_old_scratch_if_it_exists() {
+        let mut test_cache_deps = TestCacheDeps::default();
+        test_cache_deps
+            .existing_files
+            .insert(path_buf!("/cache/root/scratch"));
+        let mut fixture = Fixture::new(test_cache_deps, 1000);
+        fixture.expect_messages_in_specific_order(vec![
+            MkdirRecursively(path_buf!("/cache/root/removing")),
+            ReadDir(path_buf!("/cache/root/removing")),
+            FileExists(path_buf!("/cache/root/sha256")),
+            MkdirRecursively(path_buf!("/cache/root/sha256")),
+            FileExists(path_buf!("/cache/root/scratch")),
+            FileExists(short_path!("/cache/root/removing", 1)),
+            Rename(
+                path_buf!("/cache/root/scratch"),
+                short_path!("/cache/root/removing", 1),
+            ),
+            RemoveRecursively(short_path!("/cache/root/removing", 1)),
+            MkdirRecursively(path_buf!("/cache/root/scratch")),
+        ]);
+    }
+
+    #[test]
+    fn new_fails_if_old_sha256_cannot_be_moved() {
+        let mut test_cache_deps = TestCacheDeps::default();
+        test_cache_deps
+            .existing_files
+            .insert(path_buf!("/cache/root/sha256"));
+        test_cache_deps
+            .unrenameable_files
+            .insert(path_buf!("/cache/root/sha256"));
+        assert!(Cache::new(Path::new("/cache/root"), &mut test_cache_deps, 1000).is_err());
+    }
+
+    #[test]
+    fn unremovable_entry_is_removed_before_being_downloaded_again() {
+        let mut fixture = Fixture::new_and_clear_messages(1000);
+        fixture
+            .test_cache_deps
+            .unrenameable_files
+            .insert(long_path!("/cache/root/sha256", 42));
+
+        fixture.cache.receive_message(
+            &mut fixture.test_cache_deps,
+            GetRequest(CacheRequestId(1), digest!(42)),
+        );
+        fixture.cache.receive_message(
+            &mut fixture.test_cache_deps,
+            DownloadAndExtractCompleted(digest!(42), Ok(10000)),
+        );
+        fixture.clear_messages();
+        fixture
+            .cache
+            .receive_message(&mut fixture.test_cache_deps, DecrementRefcount(digest!(42)));
+        fixture.expect_messages_in_any_order(vec![
+            FileExists(short_path!("/cache/root/removing", 1)),
+            Rename(
+                long_path!("/cache/root/sha256", 42),
+                short_path!("/cache/root/removing", 1),
+            ),
+        ]);
+
+        // The directory is still in the way, so the request fails rather than downloading into it.
+        fixture.cache.receive_message(
+            &mut fixture.test_cache_deps,
+            GetRequest(CacheRequestId(2), digest!(42)),
+        );
+        fixture.expect_messages_in_any_order(vec![
+            FileExists(short_path!("/cache/root/removing", 2)),
+            Rename(
+                long_path!("/cache/root/sha256", 42),
+                short_path!("/cache/root/removing", 2),
+            ),
+            GetRequestFailed(CacheRequestId(2)),
         ]);
+
+        fixture.test_cache_deps.unrenameable_files.clear();
+        fixture.cache.receive_message(
+            &mut fixture.test_cache_deps,
+            GetRequest(CacheRequestId(3), digest!(42)),
+        );
+        fixture.expect_messages_in_any_order(vec![
+            FileExists(short_path!("/cache/root/removing", 3)),
+            Rename(
+                long_path!("/cache/root/sha256", 42),
+                short_path!("/cache/root/removing", 3),
+            ),
+            RemoveRecursively(short_path!("/cache/root/removing", 3)),
+            DownloadAndExtract(digest!(42), long_path!("/cache/root/sha256", 42)),
+        ]);
+
+        // Once it's been moved, it isn't moved again.
+        fixture.cache.receive_message(
+            &mut fixture.test_cache_deps,
+            DownloadAndExtractCompleted(digest!(42), Ok(100)),
+        );
+        fixture
+            .cache
+            .receive_message(&mut fixture.test_cache_deps, DecrementRefcount(digest!(42)));
+        fixture.clear_messages();
+        fixture.cache.receive_message(
+            &mut fixture.test_cache_deps,
+            GetRequest(CacheRequestId(4), digest!(42)),
+        );
+        fixture.expect_messages_in_any_order(vec![GetRequestSucceeded(
+            CacheRequestId(4),
+            long_path!("/cache/root/sha256", 42),
+        )]);
+    }
+
+    script_test! {
+        unremovable_scratch_is_left_behind;
+        {
+            let mut fixture = Fixture::new_and_clear_messages(1000);
+            fixture
+                .test_cache_deps
+                .unrenameable_files
+                .insert(path_buf!("/cache/root/scratch/3"));
+            fixture
+        };
+
+        RemoveScratch(path_buf!("/cache/root/scratch/3")) => {
+            FileExists(short_path!("/cache/root/removing", 1)),
+            Rename(path_buf!("/cache/root/scratch/3"), short_path!("/cache/root/removing", 1)),
+        };
+    }
+
+    script_test! {
+        remove_scratch_removes_in_background;
+        Fixture::new_and_clear_messages(1000);
+
+        RemoveScratch(path_buf!("/cache/root/scratch/3")) => {
+            FileExists(short_path!("/cache/root/removing", 1)),
+            Rename(path_buf!("/cache/root/scratch/3"), short_path!("/cache/root/removing", 1)),
+            RemoveRecursively(short_path!("/cache/root/removing", 1)),
+        };
     }
 
     #[test]
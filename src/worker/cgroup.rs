@@ -0,0 +1,329 @@
+//! Run executions in cgroups of their own, to limit and account for the resources they use.
+//!
+//! The worker is given a cgroup v2 directory that it owns, usually delegated to it by whatever
+//! started it, like systemd with `Delegate=yes`. Each execution gets a child cgroup in there, which
+//! all of its processes are put in and which is removed once it completes.
+
+use super::sandbox;
+use crate::{Error, ResourceLimits, Result};
+use std::{
+    ffi::CString,
+    io,
+    os::unix::{ffi::OsStrExt as _, process::CommandExt as _},
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+/*              _     _ _
+ *  _ __  _   _| |__ | (_) ___
+ * | '_ \| | | | '_ \| | |/ __|
+ * | |_) | |_| | |_) | | | (__
+ * | .__/ \__,_|_.__/|_|_|\___|
+ * |_|
+ *  FIGLET: public
+ */
+
+/// The cgroup the worker creates executions' cgroups in.
+pub struct CgroupParent {
+    path: PathBuf,
+    controllers: Vec<String>,
+    defaults: ResourceLimits,
+    next_id: u64,
+}
+
+impl CgroupParent {
+    /// Take over the cgroup at `path`, giving executions `defaults` for any limits they don't set.
+    ///
+    /// Cgroups left in it by an earlier worker are removed, after killing any processes still in
+    /// them. The memory, cpu, and pids controllers are enabled for its children, as far as they are
+    /// available. Since a cgroup with processes of its own can't do that, if the worker was started
+    /// in `path`, it moves itself into a child named `worker`.
+    pub fn new(path: PathBuf, defaults: ResourceLimits) -> Result<Self> {
+        let worker = path.join(WORKER_CGROUP);
+        if contains_self(&path)? {
+            mkdir(&worker)?;
+            std::fs::write(worker.join("cgroup.procs"), "0")?;
+        }
+        for entry in std::fs::read_dir(&path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() && entry.path() != worker {
+                kill(&entry.path());
+                remove(&entry.path())?;
+            }
+        }
+        let available = std::fs::read_to_string(path.join("cgroup.controllers"))?;
+        let controllers: Vec<String> = available
+            .split_whitespace()
+            .filter(|controller| CONTROLLERS.contains(controller))
+            .map(str::to_string)
+            .collect();
+        let enable: Vec<String> = controllers.iter().map(|c| format!("+{c}")).collect();
+        std::fs::write(path.join("cgroup.subtree_control"), enable.join(" ")).map_err(|err| {
+            Error::msg(format!(
+                "couldn't enable controllers in cgroup {}: {err}",
+                path.display()
+            ))
+        })?;
+        Ok(CgroupParent {
+            path,
+            controllers,
+            defaults,
+            next_id: 0,
+        })
+    }
+
+    /// Create a cgroup for an execution with `limits`. It's an error to ask for a limit enforced
+    /// by a controller that isn't available.
+    pub fn create(&mut self, limits: ResourceLimits) -> Result<Cgroup> {
+        let limits = limits.or(self.defaults);
+        let mut settings = vec![];
+        if let Some(memory) = limits.memory {
+            self.require("memory")?;
+            settings.push(("memory.max", memory.to_string()));
+            // Kill all of the execution's processes together, rather than leaving it to carry on
+            // without some of them.
+            settings.push(("memory.oom.group", "1".to_string()));
+        }
+        if let Some(cpu_millis) = limits.cpu_millis {
+            self.require("cpu")?;
+            let quota = u64::from(cpu_millis) * CPU_PERIOD_MICROS / 1000;
+            settings.push(("cpu.max", format!("{quota} {CPU_PERIOD_MICROS}")));
+        }
+        if let Some(pids) = limits.pids {
+            self.require("pids")?;
+            settings.push(("pids.max", pids.to_string()));
+        }
+
+        let path = self.path.join(format!("execution-{}", self.next_id));
+        self.next_id += 1;
+        mkdir(&path)?;
+        let cgroup = Cgroup {
+            procs: CString::new(path.join("cgroup.procs").as_os_str().as_bytes())?,
+            memory_limit: limits.memory,
+            path,
+        };
+        for (file, value) in settings {
+            std::fs::write(cgroup.path.join(file), value)?;
+        }
+        if limits.memory.is_some() {
+            // Don't let the execution get around its limit by swapping. This file is missing if
+            // the kernel doesn't account for swap, in which case there is nothing to prevent.
+            std::fs::write(cgroup.path.join("memory.swap.max"), "0").ok();
+        }
+        Ok(cgroup)
+    }
+
+    fn require(&self, controller: &str) -> Result<()> {
+        if self.controllers.iter().any(|c| c == controller) {
+            Ok(())
+        } else {
+            Err(Error::msg(format!(
+                "worker's cgroup doesn't have the {controller} controller"
+            )))
+        }
+    }
+}
+
+/// An execution's cgroup. When dropped, any processes left in it are killed and it is removed.
+pub struct Cgroup {
+    path: PathBuf,
+    procs: CString,
+    memory_limit: Option<u64>,
+}
+
+/// Resources used by the processes in a [Cgroup]. Numbers the kernel doesn't provide are None.
+#[derive(Debug, Default)]
+pub struct CgroupUsage {
+    pub user_time: Option<Duration>,
+    pub system_time: Option<Duration>,
+    pub peak_memory: Option<u64>,
+}
+
+impl Cgroup {
+    /// Make `command` start its process in this cgroup, so that everything it starts is in it too.
+    /// This must be done before the process enters the [sandbox], which has no access to the
+    /// worker's cgroups.
+    pub fn configure(&self, command: &mut Command) {
+        let procs = self.procs.clone();
+        // SAFETY: write_file only makes async-signal-safe calls.
+        unsafe { command.pre_exec(move || sandbox::write_file(&procs, b"0")) };
+    }
+
+    /// The resources used by the cgroup's processes so far.
+    pub fn usage(&self) -> CgroupUsage {
+        let cpu_stat = self.path.join("cpu.stat");
+        let micros = |key| read_keyed(&cpu_stat, key).map(Duration::from_micros);
+        CgroupUsage {
+            user_time: micros("user_usec"),
+            system_time: micros("system_usec"),
+            peak_memory: read_u64(&self.path.join("memory.peak")),
+        }
+    }
+
+    /// If the kernel killed the cgroup's processes for using more memory than its limit, return
+    /// the limit.
+    pub fn out_of_memory(&self) -> Option<u64> {
+        let limit = self.memory_limit?;
+        let oom_kills = read_keyed(&self.path.join("memory.events"), "oom_kill")?;
+        (oom_kills > 0).then_some(limit)
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        kill(&self.path);
+        // The killed processes take a moment to go away, so don't wait for them here.
+        let path = std::mem::take(&mut self.path);
+        std::thread::spawn(move || {
+            if let Err(err) = remove(&path) {
+                eprintln!("couldn't remove cgroup {}: {err}", path.display());
+            }
+        });
+    }
+}
+
+/*             _            _
+ *  _ __  _ __(_)_   ____ _| |_ ___
+ * | '_ \| '__| \ \ / / _` | __/ _ \
+ * | |_) | |  | |\ V / (_| | ||  __/
+ * | .__/|_|  |_| \_/ \__,_|\__\___|
+ * |_|
+ *  FIGLET: private
+ */
+
+/// The controllers the worker uses to enforce [ResourceLimits].
+const CONTROLLERS: [&str; 3] = ["cpu", "memory", "pids"];
+
+/// The child of the [CgroupParent] the worker moves itself into, if it starts in the parent.
+const WORKER_CGROUP: &str = "worker";
+
+/// The period over which an execution's CPU limit is enforced. This is the kernel's default.
+const CPU_PERIOD_MICROS: u64 = 100_000;
+
+/// How long to keep trying to remove a cgroup whose processes have been killed.
+const REMOVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn mkdir(path: &Path) -> Result<()> {
+    match std::fs::create_dir(path) {
+        Err(err) if err.kind() != io::ErrorKind::AlreadyExists => Err(Error::msg(format!(
+            "couldn't create cgroup {}: {err}",
+            path.display()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Whether the worker's process is directly in the cgroup at `path`.
+fn contains_self(path: &Path) -> Result<bool> {
+    let pid = std::process::id().to_string();
+    let procs = std::fs::read_to_string(path.join("cgroup.procs"))
+        .map_err(|err| Error::msg(format!("couldn't read cgroup {}: {err}", path.display())))?;
+    Ok(procs.lines().any(|line| line == pid))
+}
+
+/// Kill every process in the cgroup at `path`. Older kernels can't do this, in which case the
+/// processes are left alone, and the cgroup can't be removed until they exit.
+fn kill(path: &Path) {
+    std::fs::write(path.join("cgroup.kill"), "1").ok();
+}
+
+/// Remove the cgroup at `path`, waiting for its processes to go away.
+fn remove(path: &Path) -> io::Result<()> {
+    let start = std::time::Instant::now();
+    loop {
+        match std::fs::remove_dir(path) {
+            Err(err) if err.raw_os_error() == Some(nix::libc::EBUSY) => {
+                if start.elapsed() > REMOVE_TIMEOUT {
+                    return Err(err);
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            result => return result,
+        }
+    }
+}
+
+fn read_u64(path: &Path) -> Option<u64> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Read the value for `key` from a file of "key value" lines, like `cpu.stat`.
+fn read_keyed(path: &Path, key: &str) -> Option<u64> {
+    std::fs::read_to_string(path)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix(' ')?.parse().ok())
+}
+
+/*  _            _
+ * | |_ ___  ___| |_ ___
+ * | __/ _ \/ __| __/ __|
+ * | ||  __/\__ \ |_\__ \
+ *  \__\___||___/\__|___/
+ *  FIGLET: tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make a directory that looks enough like an empty cgroup with `controllers` available for
+    /// [CgroupParent::new] to take it over.
+    fn fake_cgroup(controllers: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("cgroup.procs"), "").unwrap();
+        std::fs::write(dir.path().join("cgroup.controllers"), controllers).unwrap();
+        std::fs::write(dir.path().join("cgroup.subtree_control"), "").unwrap();
+        dir
+    }
+
+    #[test]
+    fn only_known_controllers_enabled() {
+        let dir = fake_cgroup("cpuset cpu io memory hugetlb pids\n");
+        CgroupParent::new(dir.path().to_owned(), ResourceLimits::default()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("cgroup.subtree_control")).unwrap(),
+            "+cpu +memory +pids"
+        );
+    }
+
+    #[test]
+    fn limit_without_controller_is_error() {
+        let dir = fake_cgroup("cpu memory\n");
+        let mut parent =
+            CgroupParent::new(dir.path().to_owned(), ResourceLimits::default()).unwrap();
+        let limits = ResourceLimits {
+            pids: Some(10),
+            ..Default::default()
+        };
+        assert!(parent.create(limits).is_err());
+        assert!(!dir.path().join("execution-0").exists());
+    }
+
+    #[test]
+    fn default_limit_without_controller_is_error() {
+        let dir = fake_cgroup("cpu memory\n");
+        let defaults = ResourceLimits {
+            pids: Some(10),
+            ..Default::default()
+        };
+        let mut parent = CgroupParent::new(dir.path().to_owned(), defaults).unwrap();
+        assert!(parent.create(ResourceLimits::default()).is_err());
+    }
+
+    #[test]
+    fn read_keyed_finds_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cpu.stat");
+        std::fs::write(
+            &path,
+            "usage_usec 30\nuser_usec 10\nuser_usec_x 5\nsystem_usec 20\n",
+        )
+        .unwrap();
+        assert_eq!(read_keyed(&path, "user_usec"), Some(10));
+        assert_eq!(read_keyed(&path, "system_usec"), Some(20));
+        assert_eq!(read_keyed(&path, "user"), None);
+        assert_eq!(read_keyed(&dir.path().join("missing"), "user_usec"), None);
+    }
+}
@@ -3,7 +3,7 @@
 
 use crate::{
     proto::{WorkerRequest, WorkerResponse},
-    ExecutionDetails, ExecutionId, ExecutionResult,
+    ExecutionDetails, ExecutionId, ExecutionResult, OutputStream,
 };
 use std::collections::{HashMap, VecDeque};
 
@@ -16,16 +16,20 @@ use std::collections::{HashMap, VecDeque};
  *  FIGLET: public
  */
 
-/// Manage executions based on the slot count and requests from the broker. If the broker
-/// sends more execution requests than there are slots, the extra requests are queued in a FIFO
-/// queue. It's up to the broker to order the requests properly.
+/// Manage executions based on the slot count, the amount of memory, and requests from the broker.
+/// Each execution reserves the slots and memory it declares in its [ExecutionDetails] while it
+/// runs. If the broker sends more execution requests than there are resources for, the extra
+/// requests are queued in a FIFO queue. It's up to the broker to order the requests properly.
 ///
 /// All methods are completely nonblocking. They will never block the task or the thread.
 pub struct Dispatcher<D: DispatcherDeps> {
     deps: D,
     slots: usize,
+    memory: u64,
+    used_slots: usize,
+    used_memory: u64,
     queued: VecDeque<(ExecutionId, ExecutionDetails)>,
-    executing: HashMap<ExecutionId, D::ExecutionHandle>,
+    executing: HashMap<ExecutionId, Executing<D>>,
 }
 
 /// The external dependencies for [Dispatcher]. All of these methods must be asynchronous: they
@@ -39,8 +43,10 @@ pub trait DispatcherDeps {
     /// It must be safe to drop the handle after the execution has terminated.
     type ExecutionHandle;
 
-    /// Start a new execution. When the execution terminates, the notification must come through as
-    /// a [Message::FromExecutor] message.
+    /// Start a new execution. Any output from the execution must come through as
+    /// [Message::OutputFromExecutor] messages, all of which must arrive before the execution's
+    /// termination notification. When the execution terminates, the notification must come through
+    /// as a [Message::FromExecutor] message.
     fn start_execution(
         &mut self,
         id: ExecutionId,
@@ -55,17 +61,21 @@ pub trait DispatcherDeps {
 #[derive(Debug)]
 pub enum Message {
     FromBroker(WorkerRequest),
+    OutputFromExecutor(ExecutionId, OutputStream, Vec<u8>),
     FromExecutor(ExecutionId, ExecutionResult),
 }
 
 impl<D: DispatcherDeps> Dispatcher<D> {
-    /// Create a new dispatcher with the provided slot count. The slot count must be a positive
-    /// number.
-    pub fn new(deps: D, slots: usize) -> Self {
+    /// Create a new dispatcher with the provided slot count and amount of memory in bytes. The
+    /// slot count must be a positive number.
+    pub fn new(deps: D, slots: usize, memory: u64) -> Self {
         assert!(slots > 0);
         Dispatcher {
             deps,
             slots,
+            memory,
+            used_slots: 0,
+            used_memory: 0,
             queued: VecDeque::new(),
             executing: HashMap::new(),
         }
@@ -76,23 +86,36 @@ impl<D: DispatcherDeps> Dispatcher<D> {
     pub fn receive_message(&mut self, msg: Message) {
         match msg {
             Message::FromBroker(WorkerRequest::EnqueueExecution(id, details)) => {
-                self.queued.push_back((id, details));
+                self.queued.push_back((id, *details));
                 self.possibly_start_execution();
             }
             Message::FromBroker(WorkerRequest::CancelExecution(id)) => {
-                // Remove execution handle from executing map, which will drop it, which will tell
-                // the executor to kill the process.
-                if self.executing.remove(&id).is_none() {
+                // Drop the execution's handle, which will tell the executor to kill the process.
+                // The process may take a while to go away, so its resources stay reserved until the
+                // executor tells us it's done.
+                match self.executing.get_mut(&id) {
+                    Some(executing) => executing.handle = None,
                     // If it's not in the executing map, then it may be in the queue.
-                    self.queued.retain(|x| x.0 != id);
+                    None => self.queued.retain(|x| x.0 != id),
+                }
+            }
+            // Heartbeats are consumed by [crate::proto::socket_reader] and never make it here.
+            Message::FromBroker(WorkerRequest::Heartbeat) => {}
+            Message::OutputFromExecutor(id, stream, chunk) => {
+                // Like with completion, output from canceled executions isn't forwarded.
+                if self.executing.get(&id).is_some_and(Executing::is_running) {
+                    self.deps
+                        .send_response_to_broker(WorkerResponse::ExecutionOutput(
+                            id, stream, chunk,
+                        ));
                 }
             }
             Message::FromExecutor(id, result) => {
-                // If there is no entry in the executing map, then the execution has been canceled
-                // and we don't need to send any message to the broker.
-                if self.executing.remove(&id).is_some() {
+                // If the execution has been canceled, we don't need to send any message to the
+                // broker.
+                if self.finish_execution(id) {
                     self.deps
-                        .send_response_to_broker(WorkerResponse(id, result));
+                        .send_response_to_broker(WorkerResponse::ExecutionCompleted(id, result));
                 }
                 self.possibly_start_execution();
             }
@@ -109,14 +132,61 @@ impl<D: DispatcherDeps> Dispatcher<D> {
  *  FIGLET: private
  */
 
+/// An execution that has been started, along with the resources reserved for it. The handle is
+/// dropped when the execution is canceled, but the entry remains until the executor reports back.
+struct Executing<D: DispatcherDeps> {
+    handle: Option<D::ExecutionHandle>,
+    slots: usize,
+    memory: u64,
+}
+
+impl<D: DispatcherDeps> Executing<D> {
+    fn is_running(&self) -> bool {
+        self.handle.is_some()
+    }
+}
+
 impl<D: DispatcherDeps> Dispatcher<D> {
+    /// The slots and memory to reserve for an execution. These are capped at what this worker has,
+    /// so that an execution asking for more than that still runs, by itself, instead of never
+    /// running at all.
+    fn reservation(&self, details: &ExecutionDetails) -> (usize, u64) {
+        (
+            (details.slots as usize).clamp(1, self.slots),
+            details.memory.unwrap_or(0).min(self.memory),
+        )
+    }
+
     fn possibly_start_execution(&mut self) {
-        if self.executing.len() < self.slots {
-            if let Some((id, details)) = self.queued.pop_front() {
-                let handle = self.deps.start_execution(id, details);
-                if self.executing.insert(id, handle).is_some() {
-                    panic!("duplicate id {id:?}");
-                }
+        while let Some((_, details)) = self.queued.front() {
+            let (slots, memory) = self.reservation(details);
+            if self.used_slots + slots > self.slots || self.used_memory + memory > self.memory {
+                break;
+            }
+            let (id, details) = self.queued.pop_front().unwrap();
+            let handle = self.deps.start_execution(id, details);
+            let executing = Executing {
+                handle: Some(handle),
+                slots,
+                memory,
+            };
+            if self.executing.insert(id, executing).is_some() {
+                panic!("duplicate id {id:?}");
+            }
+            self.used_slots += slots;
+            self.used_memory += memory;
+        }
+    }
+
+    /// Remove the execution from the executing map, dropping its handle and releasing its
+    /// resources. Return false if it wasn't executing or had been canceled.
+    fn finish_execution(&mut self, id: ExecutionId) -> bool {
+        match self.executing.remove(&id) {
+            None => false,
+            Some(executing) => {
+                self.used_slots -= executing.slots;
+                self.used_memory -= executing.memory;
+                executing.is_running()
             }
         }
     }
@@ -142,7 +212,7 @@ mod tests {
 
     #[derive(Clone, Debug, PartialEq)]
     enum TestMessage {
-        StartExecution(ExecutionId, ExecutionDetails),
+        StartExecution(ExecutionId, Box<ExecutionDetails>),
         DropExecutionHandle(ExecutionId),
         SendResponseToBroker(WorkerResponse),
     }
@@ -183,7 +253,9 @@ mod tests {
             id: ExecutionId,
             details: ExecutionDetails,
         ) -> ExecutionHandle {
-            self.borrow_mut().messages.push(StartExecution(id, details));
+            self.borrow_mut()
+                .messages
+                .push(StartExecution(id, Box::new(details)));
             ExecutionHandle {
                 id,
                 test_state: self.clone(),
@@ -197,6 +269,8 @@ mod tests {
         }
     }
 
+    const MEMORY: u64 = 1 << 30;
+
     struct Fixture {
         test_state: Rc<RefCell<TestState>>,
         dispatcher: Dispatcher<Rc<RefCell<TestState>>>,
@@ -205,7 +279,7 @@ mod tests {
     impl Fixture {
         fn new(slots: usize) -> Self {
             let test_state = Rc::new(RefCell::new(TestState::new()));
-            let dispatcher = Dispatcher::new(test_state.clone(), slots);
+            let dispatcher = Dispatcher::new(test_state.clone(), slots, MEMORY);
             Fixture {
                 test_state,
                 dispatcher,
@@ -243,82 +317,99 @@ mod tests {
     script_test! {
         enqueue_1,
         2,
-        FromBroker(EnqueueExecution(eid![1], details![1])) => { StartExecution(eid![1], details![1]) };
+        FromBroker(EnqueueExecution(eid![1], Box::new(details![1]))) => { StartExecution(eid![1], Box::new(details![1])) };
     }
 
     script_test! {
         enqueue_2,
         2,
-        FromBroker(EnqueueExecution(eid![1], details![1])) => { StartExecution(eid![1], details![1]) };
-        FromBroker(EnqueueExecution(eid![2], details![2])) => { StartExecution(eid![2], details![2]) };
+        FromBroker(EnqueueExecution(eid![1], Box::new(details![1]))) => { StartExecution(eid![1], Box::new(details![1])) };
+        FromBroker(EnqueueExecution(eid![2], Box::new(details![2]))) => { StartExecution(eid![2], Box::new(details![2])) };
     }
 
     script_test! {
         enqueue_3_with_2_slots,
         2,
-        FromBroker(EnqueueExecution(eid![1], details![1])) => { StartExecution(eid![1], details![1]) };
-        FromBroker(EnqueueExecution(eid![2], details![2])) => { StartExecution(eid![2], details![2]) };
-        FromBroker(EnqueueExecution(eid![3], details![3])) => {};
+        FromBroker(EnqueueExecution(eid![1], Box::new(details![1]))) => { StartExecution(eid![1], Box::new(details![1])) };
+        FromBroker(EnqueueExecution(eid![2], Box::new(details![2]))) => { StartExecution(eid![2], Box::new(details![2])) };
+        FromBroker(EnqueueExecution(eid![3], Box::new(details![3]))) => {};
     }
 
     script_test! {
         complete_1,
         2,
-        FromBroker(EnqueueExecution(eid![1], details![1])) => { StartExecution(eid![1], details![1]) };
+        FromBroker(EnqueueExecution(eid![1], Box::new(details![1]))) => { StartExecution(eid![1], Box::new(details![1])) };
         FromExecutor(eid![1], result![1]) => {
             DropExecutionHandle(eid![1]),
-            SendResponseToBroker(WorkerResponse(eid![1], result![1])),
+            SendResponseToBroker(WorkerResponse::ExecutionCompleted(eid![1], result![1])),
         };
     }
 
     script_test! {
         complete_1_while_blocked,
         2,
-        FromBroker(EnqueueExecution(eid![1], details![1])) => { StartExecution(eid![1], details![1]) };
-        FromBroker(EnqueueExecution(eid![2], details![2])) => { StartExecution(eid![2], details![2]) };
-        FromBroker(EnqueueExecution(eid![3], details![3])) => {};
+        FromBroker(EnqueueExecution(eid![1], Box::new(details![1]))) => { StartExecution(eid![1], Box::new(details![1])) };
+        FromBroker(EnqueueExecution(eid![2], Box::new(details![2]))) => { StartExecution(eid![2], Box::new(details![2])) };
+        FromBroker(EnqueueExecution(eid![3], Box::new(details![3]))) => {};
         FromExecutor(eid![1], result![1]) => {
             DropExecutionHandle(eid![1]),
-            SendResponseToBroker(WorkerResponse(eid![1], result![1])),
-            StartExecution(eid![3], details![3]),
+            SendResponseToBroker(WorkerResponse::ExecutionCompleted(eid![1], result![1])),
+            StartExecution(eid![3], Box::new(details![3])),
         };
     }
 
     script_test! {
         enqueue_2_complete_1_enqueue_1,
         2,
-        FromBroker(EnqueueExecution(eid![1], details![1])) => { StartExecution(eid![1], details![1]) };
-        FromBroker(EnqueueExecution(eid![2], details![2])) => { StartExecution(eid![2], details![2]) };
+        FromBroker(EnqueueExecution(eid![1], Box::new(details![1]))) => { StartExecution(eid![1], Box::new(details![1])) };
+        FromBroker(EnqueueExecution(eid![2], Box::new(details![2]))) => { StartExecution(eid![2], Box::new(details![2])) };
         FromExecutor(eid![1], result![1]) => {
             DropExecutionHandle(eid![1]),
-            SendResponseToBroker(WorkerResponse(eid![1], result![1]))
+            SendResponseToBroker(WorkerResponse::ExecutionCompleted(eid![1], result![1]))
         };
-        FromBroker(EnqueueExecution(eid![3], details![3])) => { StartExecution(eid![3], details![3]) };
+        FromBroker(EnqueueExecution(eid![3], Box::new(details![3]))) => { StartExecution(eid![3], Box::new(details![3])) };
     }
 
     script_test! {
         cancel_queued,
         2,
-        FromBroker(EnqueueExecution(eid![1], details![1])) => { StartExecution(eid![1], details![1]) };
-        FromBroker(EnqueueExecution(eid![2], details![2])) => { StartExecution(eid![2], details![2]) };
-        FromBroker(EnqueueExecution(eid![3], details![3])) => {};
-        FromBroker(EnqueueExecution(eid![4], details![4])) => {};
+        FromBroker(EnqueueExecution(eid![1], Box::new(details![1]))) => { StartExecution(eid![1], Box::new(details![1])) };
+        FromBroker(EnqueueExecution(eid![2], Box::new(details![2]))) => { StartExecution(eid![2], Box::new(details![2])) };
+        FromBroker(EnqueueExecution(eid![3], Box::new(details![3]))) => {};
+        FromBroker(EnqueueExecution(eid![4], Box::new(details![4]))) => {};
         FromBroker(CancelExecution(eid![3])) => {};
         FromExecutor(eid![1], result![1]) => {
             DropExecutionHandle(eid![1]),
-            SendResponseToBroker(WorkerResponse(eid![1], result![1])),
-            StartExecution(eid![4], details![4]),
+            SendResponseToBroker(WorkerResponse::ExecutionCompleted(eid![1], result![1])),
+            StartExecution(eid![4], Box::new(details![4])),
         };
     }
 
     script_test! {
         cancel_executing,
         2,
-        FromBroker(EnqueueExecution(eid![1], details![1])) => { StartExecution(eid![1], details![1]) };
-        FromBroker(EnqueueExecution(eid![2], details![2])) => { StartExecution(eid![2], details![2]) };
-        FromBroker(EnqueueExecution(eid![3], details![3])) => {};
+        FromBroker(EnqueueExecution(eid![1], Box::new(details![1]))) => { StartExecution(eid![1], Box::new(details![1])) };
+        FromBroker(EnqueueExecution(eid![2], Box::new(details![2]))) => { StartExecution(eid![2], Box::new(details![2])) };
+        FromBroker(EnqueueExecution(eid![3], Box::new(details![3]))) => {};
+        FromBroker(CancelExecution(eid![2])) => { DropExecutionHandle(eid![2]) };
+        FromExecutor(eid![2], result![2]) => { StartExecution(eid![3], Box::new(details![3])) };
+    }
+
+    script_test! {
+        canceled_execution_holds_resources_until_it_exits,
+        2,
+        FromBroker(EnqueueExecution(eid![1], Box::new(details![1]))) => { StartExecution(eid![1], Box::new(details![1])) };
+        FromBroker(EnqueueExecution(eid![2], Box::new(details![2]))) => { StartExecution(eid![2], Box::new(details![2])) };
+        FromBroker(EnqueueExecution(eid![3], Box::new(details![3]))) => {};
         FromBroker(CancelExecution(eid![2])) => { DropExecutionHandle(eid![2]) };
-        FromExecutor(eid![2], result![2]) => { StartExecution(eid![3], details![3]) };
+        FromBroker(EnqueueExecution(eid![4], Box::new(details![4]))) => {};
+        OutputFromExecutor(eid![2], OutputStream::Stdout, b"out".to_vec()) => {};
+        FromExecutor(eid![2], result![2]) => { StartExecution(eid![3], Box::new(details![3])) };
+        FromExecutor(eid![1], result![1]) => {
+            DropExecutionHandle(eid![1]),
+            SendResponseToBroker(WorkerResponse::ExecutionCompleted(eid![1], result![1])),
+            StartExecution(eid![4], Box::new(details![4])),
+        };
     }
 
     script_test! {
@@ -326,17 +417,17 @@ mod tests {
         2,
         FromBroker(CancelExecution(eid![2])) => {};
         FromBroker(CancelExecution(eid![2])) => {};
-        FromBroker(EnqueueExecution(eid![1], details![1])) => { StartExecution(eid![1], details![1]) };
-        FromBroker(EnqueueExecution(eid![2], details![2])) => { StartExecution(eid![2], details![2]) };
-        FromBroker(EnqueueExecution(eid![3], details![3])) => {};
-        FromBroker(EnqueueExecution(eid![4], details![4])) => {};
+        FromBroker(EnqueueExecution(eid![1], Box::new(details![1]))) => { StartExecution(eid![1], Box::new(details![1])) };
+        FromBroker(EnqueueExecution(eid![2], Box::new(details![2]))) => { StartExecution(eid![2], Box::new(details![2])) };
+        FromBroker(EnqueueExecution(eid![3], Box::new(details![3]))) => {};
+        FromBroker(EnqueueExecution(eid![4], Box::new(details![4]))) => {};
         FromBroker(CancelExecution(eid![4])) => {};
         FromBroker(CancelExecution(eid![4])) => {};
         FromBroker(CancelExecution(eid![4])) => {};
         FromBroker(CancelExecution(eid![2])) => { DropExecutionHandle(eid![2]) };
         FromBroker(CancelExecution(eid![2])) => {};
         FromBroker(CancelExecution(eid![2])) => {};
-        FromExecutor(eid![2], result![2]) => { StartExecution(eid![3], details![3]) };
+        FromExecutor(eid![2], result![2]) => { StartExecution(eid![3], Box::new(details![3])) };
         FromBroker(CancelExecution(eid![2])) => {};
         FromBroker(CancelExecution(eid![2])) => {};
     }
@@ -353,9 +444,123 @@ mod tests {
         let mut fixture = Fixture::new(2);
         fixture
             .dispatcher
-            .receive_message(FromBroker(EnqueueExecution(eid![1], details![1])));
+            .receive_message(FromBroker(EnqueueExecution(eid![1], Box::new(details![1]))));
         fixture
             .dispatcher
-            .receive_message(FromBroker(EnqueueExecution(eid![1], details![2])));
+            .receive_message(FromBroker(EnqueueExecution(eid![1], Box::new(details![2]))));
+    }
+
+    script_test! {
+        output_forwarded,
+        2,
+        FromBroker(EnqueueExecution(eid![1], Box::new(details![1]))) => { StartExecution(eid![1], Box::new(details![1])) };
+        OutputFromExecutor(eid![1], OutputStream::Stdout, b"out".to_vec()) => {
+            SendResponseToBroker(WorkerResponse::ExecutionOutput(eid![1], OutputStream::Stdout, b"out".to_vec())),
+        };
+        OutputFromExecutor(eid![1], OutputStream::Stderr, b"err".to_vec()) => {
+            SendResponseToBroker(WorkerResponse::ExecutionOutput(eid![1], OutputStream::Stderr, b"err".to_vec())),
+        };
+    }
+
+    script_test! {
+        output_from_canceled_execution_dropped,
+        2,
+        FromBroker(EnqueueExecution(eid![1], Box::new(details![1]))) => { StartExecution(eid![1], Box::new(details![1])) };
+        FromBroker(CancelExecution(eid![1])) => { DropExecutionHandle(eid![1]) };
+        OutputFromExecutor(eid![1], OutputStream::Stdout, b"out".to_vec()) => {};
+    }
+
+    macro_rules! slots_details {
+        [$n:expr, $slots:expr] => {
+            ExecutionDetails { slots: $slots, ..details![$n] }
+        };
+    }
+
+    macro_rules! memory_details {
+        [$n:expr, $memory:expr] => {
+            ExecutionDetails { memory: Some($memory), ..details![$n] }
+        };
+    }
+
+    script_test! {
+        multi_slot_execution_waits_for_enough_free_slots,
+        4,
+        FromBroker(EnqueueExecution(eid![1], Box::new(details![1]))) => { StartExecution(eid![1], Box::new(details![1])) };
+        FromBroker(EnqueueExecution(eid![2], Box::new(slots_details![2, 4]))) => {};
+        FromBroker(EnqueueExecution(eid![3], Box::new(details![3]))) => {};
+        FromExecutor(eid![1], result![1]) => {
+            DropExecutionHandle(eid![1]),
+            SendResponseToBroker(WorkerResponse::ExecutionCompleted(eid![1], result![1])),
+            StartExecution(eid![2], Box::new(slots_details![2, 4])),
+        };
+        FromExecutor(eid![2], result![2]) => {
+            DropExecutionHandle(eid![2]),
+            SendResponseToBroker(WorkerResponse::ExecutionCompleted(eid![2], result![2])),
+            StartExecution(eid![3], Box::new(details![3])),
+        };
+    }
+
+    script_test! {
+        multi_slot_completion_starts_several_executions,
+        2,
+        FromBroker(EnqueueExecution(eid![1], Box::new(slots_details![1, 2]))) => {
+            StartExecution(eid![1], Box::new(slots_details![1, 2])),
+        };
+        FromBroker(EnqueueExecution(eid![2], Box::new(details![2]))) => {};
+        FromBroker(EnqueueExecution(eid![3], Box::new(details![3]))) => {};
+        FromExecutor(eid![1], result![1]) => {
+            DropExecutionHandle(eid![1]),
+            SendResponseToBroker(WorkerResponse::ExecutionCompleted(eid![1], result![1])),
+            StartExecution(eid![2], Box::new(details![2])),
+            StartExecution(eid![3], Box::new(details![3])),
+        };
+    }
+
+    script_test! {
+        execution_needing_more_slots_than_worker_has_runs_alone,
+        2,
+        FromBroker(EnqueueExecution(eid![1], Box::new(slots_details![1, 4]))) => {
+            StartExecution(eid![1], Box::new(slots_details![1, 4])),
+        };
+        FromBroker(EnqueueExecution(eid![2], Box::new(details![2]))) => {};
+        FromExecutor(eid![1], result![1]) => {
+            DropExecutionHandle(eid![1]),
+            SendResponseToBroker(WorkerResponse::ExecutionCompleted(eid![1], result![1])),
+            StartExecution(eid![2], Box::new(details![2])),
+        };
+    }
+
+    script_test! {
+        canceled_multi_slot_execution_releases_slots,
+        2,
+        FromBroker(EnqueueExecution(eid![1], Box::new(slots_details![1, 2]))) => {
+            StartExecution(eid![1], Box::new(slots_details![1, 2])),
+        };
+        FromBroker(EnqueueExecution(eid![2], Box::new(details![2]))) => {};
+        FromBroker(CancelExecution(eid![1])) => { DropExecutionHandle(eid![1]) };
+        FromExecutor(eid![1], result![1]) => { StartExecution(eid![2], Box::new(details![2])) };
+    }
+
+    script_test! {
+        memory_limits_concurrent_executions,
+        4,
+        FromBroker(EnqueueExecution(eid![1], Box::new(memory_details![1, MEMORY / 4 * 3]))) => {
+            StartExecution(eid![1], Box::new(memory_details![1, MEMORY / 4 * 3])),
+        };
+        FromBroker(EnqueueExecution(eid![2], Box::new(memory_details![2, MEMORY / 2]))) => {};
+        FromExecutor(eid![1], result![1]) => {
+            DropExecutionHandle(eid![1]),
+            SendResponseToBroker(WorkerResponse::ExecutionCompleted(eid![1], result![1])),
+            StartExecution(eid![2], Box::new(memory_details![2, MEMORY / 2])),
+        };
+    }
+
+    script_test! {
+        execution_needing_more_memory_than_worker_has_runs_alone,
+        4,
+        FromBroker(EnqueueExecution(eid![1], Box::new(memory_details![1, MEMORY * 2]))) => {
+            StartExecution(eid![1], Box::new(memory_details![1, MEMORY * 2])),
+        };
+        FromBroker(EnqueueExecution(eid![2], Box::new(memory_details![2, 1]))) => {};
     }
 }
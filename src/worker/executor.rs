@@ -1,7 +1,19 @@
 //! Easily start and stop processes.
 
-use crate::{ExecutionDetails, ExecutionResult};
-use nix::{sys::signal::Signal, unistd::Pid};
+use super::{cgroup::Cgroup, sandbox};
+use crate::{
+    CapturedOutput, ExecutionDetails, ExecutionResult, ExecutionStatus, OutputStream,
+    ProcessLimits, ResourceUsage, Termination, MAX_CAPTURED_OUTPUT,
+};
+use nix::{libc, sys::signal::Signal, unistd::Pid};
+use std::{
+    collections::VecDeque,
+    os::unix::process::{CommandExt as _, ExitStatusExt as _},
+    path::{Path, PathBuf},
+    process::{Child, ExitStatus},
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncReadExt as _};
 
 /*              _     _ _
  *  _ __  _   _| |__ | (_) ___
@@ -13,18 +25,60 @@ use nix::{sys::signal::Signal, unistd::Pid};
  */
 
 /// Start a process (i.e. execution) and call the provided callback when it completes. The process
-/// will be killed when the returned [Handle] is dropped, unless it has already completed. The
+/// will be terminated when the returned [Handle] is dropped, unless it has already completed. The
 /// provided callback is always called on a separate task, even if an error occurs immediately.
+///
+/// When the process has to be stopped, because the [Handle] was dropped or it timed out, it's sent
+/// the termination signal from `details`. If it's still running at the end of its grace period, it
+/// is killed with SIGKILL. The [ExecutionResult] says which of the two stopped it.
+///
+/// The process is started in a session of its own. Whether it completes, times out, or is killed,
+/// everything left in its process group is killed along with it. Descendants that leave the
+/// process group can only be caught by running the process in the sandbox or in a cgroup.
+///
+/// The process's stdout and stderr are captured, and `output` is called with each chunk of output
+/// as it is read. Chunks are at most [OUTPUT_CHUNK_SIZE] bytes. All output is delivered before
+/// `done` is called. In addition, the tail of each stream is kept in the [ExecutionResult] passed
+/// to `done`, as requested by the limits in `details`.
+///
+/// If `layers` isn't empty, the process runs in the sandbox with the layers, which are directories
+/// on the worker, stacked up as its root file system. They must exist until `done` is called.
+///
+/// If `details` asks for a scratch directory, `scratch_dir` is the empty directory on the worker to
+/// use for it. If the process runs in a root file system, the scratch directory is mounted on
+/// `/tmp` in there.
+///
+/// The process starts with the [ProcessLimits] from `details`, which it passes on to everything it
+/// starts.
+///
+/// If `cgroup` is provided, the process and everything it starts run in it. Its statistics go into
+/// the reported resource usage, and the process is reported as
+/// [ExecutionStatus::OutOfMemory] if the kernel killed it for exceeding the cgroup's memory limit.
 pub fn start(
     details: ExecutionDetails,
+    layers: Vec<PathBuf>,
+    scratch_dir: Option<&Path>,
+    cgroup: Option<Cgroup>,
+    output: impl FnMut(OutputStream, Vec<u8>) + Send + 'static,
     done: impl FnOnce(ExecutionResult) + Send + 'static,
 ) -> Handle {
-    Handle(start_with_killer(details, done, ()))
+    Handle(start_with_killer(
+        details,
+        layers,
+        scratch_dir,
+        cgroup,
+        output,
+        done,
+        (),
+    ))
 }
 
-/// A handle that will kill the running process when dropped. If the process has already completed,
-/// or if it failed to start, then dropping the Handle does nothing.
-pub struct Handle(GenericHandle<()>);
+/// The largest chunk of output passed to the `output` callback of [start].
+pub const OUTPUT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A handle that will terminate the running process, and its process group, when dropped. If the
+/// process has already completed, or if it failed to start, then dropping the Handle does nothing.
+pub struct Handle(#[allow(dead_code)] GenericHandle<()>);
 
 /*             _            _
  *  _ __  _ __(_)_   ____ _| |_ ___
@@ -36,18 +90,23 @@ pub struct Handle(GenericHandle<()>);
  */
 
 trait Killer: Send + 'static {
+    /// Send `signal` to the process group led by `pid`.
     fn kill(&mut self, pid: Pid, signal: Signal);
 }
 
 impl Killer for () {
     fn kill(&mut self, pid: Pid, signal: Signal) {
-        nix::sys::signal::kill(pid, signal).ok();
+        nix::sys::signal::killpg(pid, signal).ok();
     }
 }
 
 struct GenericHandle<K: Killer> {
     pid: Pid,
+    signal: Signal,
     done_receiver: tokio::sync::oneshot::Receiver<()>,
+    /// Dropped along with the handle, which tells the waiter that the process has been sent
+    /// `signal`, and that it's time to start the grace period.
+    _canceled_sender: tokio::sync::oneshot::Sender<()>,
     killer: K,
 }
 
@@ -56,54 +115,509 @@ impl<K: Killer> Drop for GenericHandle<K> {
         match self.done_receiver.try_recv() {
             Ok(()) => {}
             Err(_) => {
-                self.killer.kill(self.pid, Signal::SIGKILL);
+                self.killer.kill(self.pid, self.signal);
+            }
+        }
+    }
+}
+
+/// How to stop a process that has to end early.
+#[derive(Clone, Copy)]
+struct TerminationPolicy {
+    signal: Signal,
+    grace_period: Duration,
+}
+
+impl TerminationPolicy {
+    fn new(details: &ExecutionDetails) -> std::io::Result<Self> {
+        let signal = Signal::try_from(details.termination_signal).map_err(|_| {
+            std::io::Error::other(format!(
+                "invalid termination signal {}",
+                details.termination_signal
+            ))
+        })?;
+        Ok(TerminationPolicy {
+            signal,
+            grace_period: details.grace_period,
+        })
+    }
+
+    /// The signal to send first. Without a grace period, there's no point in asking nicely.
+    fn first_signal(&self) -> Signal {
+        if self.grace_period.is_zero() {
+            Signal::SIGKILL
+        } else {
+            self.signal
+        }
+    }
+}
+
+/// How long to keep reading output after the child has exited, if its stdout or stderr are still
+/// open. This only happens when the child leaves behind descendants that inherited them, and that
+/// got away from it by leaving its process group.
+const OUTPUT_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Read a chunk from `pipe` into `buf`, returning the number of bytes read. Read errors are treated
+/// as end of file. If `pipe` is None, because it has already been closed, never return.
+async fn read_chunk(pipe: &mut Option<impl AsyncRead + Unpin>, buf: &mut [u8]) -> usize {
+    match pipe {
+        None => std::future::pending().await,
+        Some(pipe) => pipe.read(buf).await.unwrap_or(0),
+    }
+}
+
+/// Read the child's stdout and stderr until both are closed, passing each chunk to `output` as it
+/// arrives.
+async fn forward_output(
+    mut stdout: Option<tokio::process::ChildStdout>,
+    mut stderr: Option<tokio::process::ChildStderr>,
+    output: &mut impl FnMut(OutputStream, Vec<u8>),
+) {
+    let mut stdout_buf = vec![0; OUTPUT_CHUNK_SIZE];
+    let mut stderr_buf = vec![0; OUTPUT_CHUNK_SIZE];
+    while stdout.is_some() || stderr.is_some() {
+        tokio::select! {
+            n = read_chunk(&mut stdout, &mut stdout_buf) => match n {
+                0 => stdout = None,
+                n => output(OutputStream::Stdout, stdout_buf[..n].to_vec()),
+            },
+            n = read_chunk(&mut stderr, &mut stderr_buf) => match n {
+                0 => stderr = None,
+                n => output(OutputStream::Stderr, stderr_buf[..n].to_vec()),
+            },
+        }
+    }
+}
+
+/// Keeps the last `limit` bytes written to an output stream, counting the bytes dropped before
+/// them.
+struct Capture {
+    limit: usize,
+    tail: VecDeque<u8>,
+    truncated: u64,
+}
+
+impl Capture {
+    fn new(limit: u64) -> Self {
+        Capture {
+            limit: limit.min(MAX_CAPTURED_OUTPUT) as usize,
+            tail: VecDeque::new(),
+            truncated: 0,
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        self.tail.extend(chunk);
+        let excess = self.tail.len().saturating_sub(self.limit);
+        self.tail.drain(..excess);
+        self.truncated += excess as u64;
+    }
+
+    fn finish(capture: Option<Self>) -> CapturedOutput {
+        match capture {
+            None => CapturedOutput::None,
+            Some(Capture {
+                tail, truncated: 0, ..
+            }) => CapturedOutput::Complete(tail.into()),
+            Some(Capture {
+                tail, truncated, ..
+            }) => CapturedOutput::Truncated {
+                tail: tail.into(),
+                truncated,
+            },
+        }
+    }
+}
+
+fn timeval_to_duration(timeval: libc::timeval) -> Duration {
+    Duration::new(timeval.tv_sec as u64, timeval.tv_usec as u32 * 1000)
+}
+
+/// Wait for the child to exit, then kill everything left in its process group and reap the child,
+/// returning its exit status and resource usage. The child isn't reaped until its process group
+/// has been killed, so that its pid, which is also the group's id, can't be reused in the
+/// meantime. Only `wait4` can get the resource usage of one particular child, and it blocks, so
+/// it's run on a blocking thread.
+async fn wait4(pid: Pid) -> std::io::Result<(ExitStatus, libc::rusage)> {
+    let wait = tokio::task::spawn_blocking(move || {
+        loop {
+            // SAFETY: siginfo is a plain C struct, for which all zeros is a valid value, and it's
+            // valid for writes for the duration of the call.
+            let ret = unsafe {
+                let mut siginfo: libc::siginfo_t = std::mem::zeroed();
+                libc::waitid(
+                    libc::P_PID,
+                    pid.as_raw() as libc::id_t,
+                    &mut siginfo,
+                    libc::WEXITED | libc::WNOWAIT,
+                )
+            };
+            if ret != -1 {
+                break;
+            }
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+        nix::sys::signal::killpg(pid, Signal::SIGKILL).ok();
+
+        let mut status = 0;
+        // SAFETY: rusage is a plain C struct, for which all zeros is a valid value.
+        let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+        loop {
+            // SAFETY: status and rusage are valid for writes for the duration of the call.
+            if unsafe { libc::wait4(pid.as_raw(), &mut status, 0, &mut rusage) } != -1 {
+                return Ok((ExitStatus::from_raw(status), rusage));
+            }
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+    });
+    wait.await
+        .unwrap_or_else(|error| Err(std::io::Error::other(error)))
+}
+
+/// Wait for the child to exit. If it's still running after `timeout`, or once `canceled` fires,
+/// terminate its process group according to `policy`. Return its exit status, along with how long
+/// it ran for if it timed out, and how it was stopped if it had to be.
+async fn wait_or_terminate(
+    pid: Pid,
+    timeout: Option<Duration>,
+    policy: TerminationPolicy,
+    canceled: tokio::sync::oneshot::Receiver<()>,
+) -> (
+    std::io::Result<(ExitStatus, libc::rusage)>,
+    Option<Duration>,
+    Option<Termination>,
+) {
+    let start = tokio::time::Instant::now();
+    let wait = wait4(pid);
+    tokio::pin!(wait);
+    let timed_out = tokio::select! {
+        status = &mut wait => return (status, None, None),
+        () = sleep_until_timeout(timeout) => {
+            nix::sys::signal::killpg(pid, policy.first_signal()).ok();
+            true
+        }
+        // The handle has already sent the signal.
+        _ = canceled => false,
+    };
+    let (status, termination) = if policy.first_signal() == Signal::SIGKILL {
+        (wait.await, Termination::Killed)
+    } else {
+        match tokio::time::timeout(policy.grace_period, &mut wait).await {
+            Ok(status) => (status, Termination::Graceful),
+            Err(_) => {
+                nix::sys::signal::killpg(pid, Signal::SIGKILL).ok();
+                (wait.await, Termination::Killed)
             }
         }
+    };
+    (
+        status,
+        timed_out.then(|| start.elapsed()),
+        Some(termination),
+    )
+}
+
+async fn sleep_until_timeout(timeout: Option<Duration>) {
+    match timeout {
+        None => std::future::pending().await,
+        Some(timeout) => tokio::time::sleep(timeout).await,
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn waiter(
-    mut child: tokio::process::Child,
+    mut child: std::process::Child,
+    cgroup: Option<Cgroup>,
     done_sender: tokio::sync::oneshot::Sender<()>,
+    canceled_receiver: tokio::sync::oneshot::Receiver<()>,
+    timeout: Option<Duration>,
+    policy: TerminationPolicy,
+    mut stdout_capture: Option<Capture>,
+    mut stderr_capture: Option<Capture>,
+    mut output: impl FnMut(OutputStream, Vec<u8>) + Send + 'static,
     done: impl FnOnce(ExecutionResult) + Send + 'static,
 ) {
-    use std::os::unix::process::ExitStatusExt;
-    done(match child.wait().await {
-        Err(error) => ExecutionResult::Error(error.to_string()),
-        Ok(status) => match status.code() {
-            Some(code) => ExecutionResult::Exited(code as u8),
-            None => ExecutionResult::Signalled(status.signal().unwrap() as u8),
+    let start = tokio::time::Instant::now();
+    let status = {
+        let mut capture_and_output = |stream, chunk: Vec<u8>| {
+            let capture = match stream {
+                OutputStream::Stdout => &mut stdout_capture,
+                OutputStream::Stderr => &mut stderr_capture,
+            };
+            if let Some(capture) = capture {
+                capture.push(&chunk);
+            }
+            output(stream, chunk);
+        };
+        let forward = forward_output(
+            child
+                .stdout
+                .take()
+                .and_then(|stdout| tokio::process::ChildStdout::from_std(stdout).ok()),
+            child
+                .stderr
+                .take()
+                .and_then(|stderr| tokio::process::ChildStderr::from_std(stderr).ok()),
+            &mut capture_and_output,
+        );
+        let exit = wait_or_terminate(
+            Pid::from_raw(child.id() as i32),
+            timeout,
+            policy,
+            canceled_receiver,
+        );
+        tokio::pin!(forward, exit);
+        tokio::select! {
+            () = &mut forward => exit.await,
+            status = &mut exit => {
+                // Everything the child wrote is already in the pipes. However, its descendants may
+                // still be holding the pipes open, so don't wait forever for them to be closed.
+                tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, forward).await.ok();
+                status
+            }
+        }
+    };
+    let wall_time = start.elapsed();
+    // The child has been reaped, so its pid, and the process group id, may be reused by now.
+    // Dropping the handle mustn't signal them, even if it's dropped by `done`.
+    done_sender.send(()).ok();
+    // The cgroup's numbers include descendants that the child didn't wait for, so they're
+    // preferred when there is one.
+    let cgroup_usage = cgroup.as_ref().map(Cgroup::usage).unwrap_or_default();
+    let out_of_memory = cgroup.as_ref().and_then(Cgroup::out_of_memory);
+    drop(cgroup);
+    let (status, timed_out, termination) = status;
+    let resource_usage = status.as_ref().ok().map(|(_, rusage)| ResourceUsage {
+        wall_time,
+        user_time: cgroup_usage
+            .user_time
+            .unwrap_or_else(|| timeval_to_duration(rusage.ru_utime)),
+        system_time: cgroup_usage
+            .system_time
+            .unwrap_or_else(|| timeval_to_duration(rusage.ru_stime)),
+        // Linux reports the maximum resident set size in kilobytes.
+        max_rss: rusage.ru_maxrss as u64 * 1024,
+        peak_memory: cgroup_usage.peak_memory,
+    });
+    done(ExecutionResult {
+        status: match (status, timed_out, out_of_memory) {
+            (_, Some(elapsed), _) => ExecutionStatus::TimedOut(elapsed),
+            (Err(error), None, _) => ExecutionStatus::Error(error.to_string()),
+            (Ok(_), None, Some(limit)) => ExecutionStatus::OutOfMemory(limit),
+            (Ok((status, _)), None, None) => match status.code() {
+                Some(code) => ExecutionStatus::Exited(code),
+                None => ExecutionStatus::signalled(status.signal().unwrap(), status.core_dumped()),
+            },
         },
+        stdout: Capture::finish(stdout_capture),
+        stderr: Capture::finish(stderr_capture),
+        resource_usage,
+        // The broker counts retries, since only it knows about them.
+        attempts: 1,
+        termination,
     });
-    done_sender.send(()).ok();
+}
+
+/// Make `command` set `limits` for its process before running the program. Since the process
+/// can't raise its hard limits, it's an error to ask for more than the worker's own.
+fn set_process_limits(
+    command: &mut std::process::Command,
+    limits: ProcessLimits,
+) -> std::io::Result<()> {
+    let mut rlimits = vec![];
+    for (resource, name, limit) in [
+        (libc::RLIMIT_CORE, "core file size", limits.core_file_size),
+        (libc::RLIMIT_NOFILE, "open files", limits.open_files),
+        (libc::RLIMIT_CPU, "CPU time", limits.cpu_seconds),
+        (libc::RLIMIT_AS, "address space", limits.address_space),
+        (libc::RLIMIT_FSIZE, "file size", limits.file_size),
+    ] {
+        let Some(limit) = limit else { continue };
+        let mut current = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: current is valid for writes.
+        if unsafe { libc::getrlimit(resource, &mut current) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if current.rlim_max != libc::RLIM_INFINITY && limit > current.rlim_max {
+            return Err(std::io::Error::other(format!(
+                "{name} limit of {limit} is higher than the worker's limit of {}",
+                current.rlim_max
+            )));
+        }
+        rlimits.push((
+            resource,
+            libc::rlimit {
+                rlim_cur: limit,
+                rlim_max: limit,
+            },
+        ));
+    }
+    if !rlimits.is_empty() {
+        // SAFETY: setrlimit is async-signal-safe, and each limit is valid for reads.
+        unsafe {
+            command.pre_exec(move || {
+                for (resource, rlimit) in &rlimits {
+                    if libc::setrlimit(*resource, rlimit) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            })
+        };
+    }
+    Ok(())
+}
+
+/// Spawn the process for `details`, in `cgroup` if provided, and with `scratch_dir` as its scratch
+/// directory if it asked for one. If it runs in a root file system made of `layers`, also return
+/// the directory that the root file system is assembled in, which must be kept until the process
+/// exits.
+fn spawn(
+    details: ExecutionDetails,
+    layers: &[PathBuf],
+    scratch_dir: Option<&Path>,
+    cgroup: Option<&Cgroup>,
+) -> std::io::Result<(Child, Option<tempfile::TempDir>)> {
+    let mut command = std::process::Command::new(details.program);
+    if details.clear_environment {
+        command.env_clear();
+    }
+    if let Some(cgroup) = cgroup {
+        cgroup.configure(&mut command);
+    }
+    let scratch_dir = details.scratch.zip(scratch_dir);
+    let sandbox_scratch_dir = scratch_dir.map(|(options, path)| sandbox::ScratchDir {
+        path,
+        quota: options.quota,
+    });
+    let in_scratch_dir = scratch_dir.is_some_and(|(options, _)| options.working_directory);
+    let scratch = if layers.is_empty() {
+        let working_directory = match scratch_dir {
+            Some((_, path)) if in_scratch_dir => Some(path.to_owned()),
+            // The execution shares the worker's file system, so its root is the root.
+            _ => details
+                .working_directory
+                .map(|working_directory| Path::new("/").join(working_directory)),
+        };
+        if let Some(working_directory) = working_directory {
+            command.current_dir(working_directory);
+        }
+        if let Some((_, path)) = scratch_dir {
+            command.env("TMPDIR", path);
+        }
+        let has_quota = scratch_dir.is_some_and(|(options, _)| options.quota.is_some());
+        if details.sandbox || details.isolate_network || has_quota {
+            sandbox::configure(
+                &mut command,
+                None,
+                details.isolate_network,
+                sandbox_scratch_dir,
+            );
+        } else {
+            // SAFETY: setsid is async-signal-safe.
+            unsafe { command.pre_exec(|| Ok(nix::unistd::setsid().map(drop)?)) };
+        }
+        None
+    } else {
+        let working_directory = if in_scratch_dir {
+            PathBuf::from("/tmp")
+        } else {
+            details.working_directory.unwrap_or_default()
+        };
+        if scratch_dir.is_some() {
+            command.env("TMPDIR", "/tmp");
+        }
+        let scratch = tempfile::tempdir()?;
+        sandbox::configure(
+            &mut command,
+            Some(sandbox::RootFs {
+                layers,
+                scratch: scratch.path(),
+                working_directory: &working_directory,
+            }),
+            details.isolate_network,
+            sandbox_scratch_dir,
+        );
+        Some(scratch)
+    };
+    set_process_limits(&mut command, details.process_limits)?;
+    let child = command
+        .args(details.arguments)
+        .envs(details.environment)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    Ok((child, scratch))
 }
 
 fn start_with_killer<K: Killer>(
     details: ExecutionDetails,
+    layers: Vec<PathBuf>,
+    scratch_dir: Option<&Path>,
+    cgroup: Option<Cgroup>,
+    output: impl FnMut(OutputStream, Vec<u8>) + Send + 'static,
     done: impl FnOnce(ExecutionResult) + Send + 'static,
     killer: K,
 ) -> GenericHandle<K> {
     let (done_sender, done_receiver) = tokio::sync::oneshot::channel();
-    let result = tokio::process::Command::new(details.program)
-        .args(details.arguments)
-        .stdin(std::process::Stdio::null())
-        .spawn();
-    match result {
+    let (canceled_sender, canceled_receiver) = tokio::sync::oneshot::channel();
+    let stdout_capture = details.stdout_limit.map(Capture::new);
+    let stderr_capture = details.stderr_limit.map(Capture::new);
+    let timeout = details.timeout;
+    let spawned = TerminationPolicy::new(&details).and_then(|policy| {
+        let (child, scratch) = spawn(details, &layers, scratch_dir, cgroup.as_ref())?;
+        Ok((child, scratch, policy))
+    });
+    match spawned {
         Err(error) => {
             done_sender.send(()).ok();
-            tokio::task::spawn(async move { done(ExecutionResult::Error(error.to_string())) });
+            tokio::task::spawn(
+                async move { done(ExecutionStatus::Error(error.to_string()).into()) },
+            );
             GenericHandle {
                 pid: Pid::from_raw(0),
+                signal: Signal::SIGKILL,
                 done_receiver,
+                _canceled_sender: canceled_sender,
                 killer,
             }
         }
-        Ok(child) => {
-            let pid = Pid::from_raw(child.id().unwrap() as i32);
-            tokio::task::spawn(async move { waiter(child, done_sender, done).await });
+        Ok((child, scratch, policy)) => {
+            let pid = Pid::from_raw(child.id() as i32);
+            let done = move |result| {
+                drop(scratch);
+                done(result)
+            };
+            tokio::task::spawn(async move {
+                waiter(
+                    child,
+                    cgroup,
+                    done_sender,
+                    canceled_receiver,
+                    timeout,
+                    policy,
+                    stdout_capture,
+                    stderr_capture,
+                    output,
+                    done,
+                )
+                .await
+            });
             GenericHandle {
                 pid,
+                signal: policy.first_signal(),
                 done_receiver,
+                _canceled_sender: canceled_sender,
                 killer,
             }
         }
@@ -121,7 +635,12 @@ fn start_with_killer<K: Killer>(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::{Arc, Mutex};
+    use crate::{worker::cgroup::CgroupParent, ResourceLimits, Scratch};
+    use std::collections::BTreeMap;
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    };
     use tempfile;
 
     macro_rules! bash {
@@ -132,6 +651,7 @@ mod tests {
                     "-c".to_string(),
                     format!($($tokens),*),
                 ],
+                ..Default::default()
             }
         };
     }
@@ -140,34 +660,86 @@ mod tests {
         ExecutionDetails {
             program: "a_program_that_does_not_exist".to_string(),
             arguments: vec![],
+            ..Default::default()
         }
     }
 
     async fn start_and_await(details: ExecutionDetails) -> ExecutionResult {
+        start_and_await_with_layers(details, vec![]).await
+    }
+
+    async fn start_and_await_with_layers(
+        details: ExecutionDetails,
+        layers: Vec<PathBuf>,
+    ) -> ExecutionResult {
         let (tx, rx) = tokio::sync::oneshot::channel();
-        let _handle = start(details, move |result| tx.send(result).unwrap());
+        let _handle = start(
+            details,
+            layers,
+            None,
+            None,
+            |_, _| {},
+            move |result| tx.send(result).unwrap(),
+        );
         rx.await.unwrap()
     }
 
+    async fn start_and_await_status(details: ExecutionDetails) -> ExecutionStatus {
+        start_and_await(details).await.status
+    }
+
+    /// Run the execution to completion and return the result along with all of the chunks of
+    /// output, in the order they were received.
+    async fn start_and_await_output(
+        details: ExecutionDetails,
+    ) -> (ExecutionResult, Vec<(OutputStream, Vec<u8>)>) {
+        let chunks = Arc::new(Mutex::new(vec![]));
+        let chunks_clone = chunks.clone();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let _handle = start(
+            details,
+            vec![],
+            None,
+            None,
+            move |stream, chunk| chunks_clone.lock().unwrap().push((stream, chunk)),
+            move |result| tx.send(result).unwrap(),
+        );
+        let result = rx.await.unwrap();
+        let chunks = std::mem::take(&mut *chunks.lock().unwrap());
+        (result, chunks)
+    }
+
+    fn collect_stream(chunks: &[(OutputStream, Vec<u8>)], stream: OutputStream) -> Vec<u8> {
+        chunks
+            .iter()
+            .filter(|(s, _)| *s == stream)
+            .flat_map(|(_, chunk)| chunk.iter().copied())
+            .collect()
+    }
+
     impl Killer for Arc<Mutex<Option<Signal>>> {
         fn kill(&mut self, pid: Pid, signal: Signal) {
             assert!(self.lock().unwrap().replace(signal).is_none());
-            nix::sys::signal::kill(pid, signal).ok();
+            nix::sys::signal::killpg(pid, signal).ok();
         }
     }
 
     async fn start_and_await_with_logging_killer(
         details: ExecutionDetails,
-    ) -> (ExecutionResult, Option<Signal>) {
+    ) -> (ExecutionStatus, Option<Signal>) {
         let killer = Arc::new(Mutex::new(None));
         let (tx, rx) = tokio::sync::oneshot::channel();
         let _handle = start_with_killer(
             details,
+            vec![],
+            None,
+            None,
+            |_, _| {},
             move |result| tx.send(result).unwrap(),
             killer.clone(),
         );
         let signal = *killer.lock().unwrap();
-        (rx.await.unwrap(), signal)
+        (rx.await.unwrap().status, signal)
     }
 
     #[tokio::test]
@@ -188,40 +760,78 @@ mod tests {
         let (tx, rx) = tokio::sync::oneshot::channel();
         let _ = start(
             bash!("sleep infinity && touch {}", tempfile.display()),
+            vec![],
+            None,
+            None,
+            |_, _| {},
             move |result| tx.send(result).unwrap(),
         );
         let result = rx.await.unwrap();
-        assert_eq!(result, ExecutionResult::Signalled(9));
+        assert_eq!(result.status, ExecutionStatus::signalled(15, false));
+        assert_eq!(result.termination, Some(Termination::Graceful));
         assert!(!tempfile.exists());
     }
 
     #[tokio::test]
     async fn exited_0_result() {
         assert_eq!(
-            start_and_await(bash!("exit 0")).await,
-            ExecutionResult::Exited(0)
+            start_and_await_status(bash!("exit 0")).await,
+            ExecutionStatus::Exited(0)
         );
     }
 
     #[tokio::test]
     async fn exited_1_result() {
         assert_eq!(
-            start_and_await(bash!("exit 1")).await,
-            ExecutionResult::Exited(1)
+            start_and_await_status(bash!("exit 1")).await,
+            ExecutionStatus::Exited(1)
         );
     }
 
     #[tokio::test]
     async fn signalled_15_result() {
         assert_eq!(
-            start_and_await(bash!("kill $$")).await,
-            ExecutionResult::Signalled(15)
+            start_and_await_status(bash!("kill $$")).await,
+            ExecutionStatus::signalled(15, false)
+        );
+    }
+
+    #[tokio::test]
+    async fn signal_named_without_core_dump() {
+        assert_eq!(
+            start_and_await_status(bash!("ulimit -c 0; kill -SEGV $$")).await,
+            ExecutionStatus::Signalled {
+                signal: 11,
+                name: "SIGSEGV".to_string(),
+                core_dumped: false,
+            }
+        );
+    }
+
+    // Where the core goes is up to the host's core_pattern. If it's piped to a handler, whether
+    // the handler takes it isn't up to us.
+    #[tokio::test]
+    #[ignore = "needs core dumps written to files"]
+    async fn signal_named_with_core_dump() {
+        // The core file may be written to the working directory.
+        let working_directory = tempfile::tempdir().unwrap();
+        assert_eq!(
+            start_and_await_status(ExecutionDetails {
+                working_directory: Some(working_directory.path().to_owned()),
+                ..bash!("ulimit -c unlimited; kill -SEGV $$")
+            })
+            .await,
+            ExecutionStatus::Signalled {
+                signal: 11,
+                name: "SIGSEGV".to_string(),
+                core_dumped: true,
+            }
         );
     }
 
     #[tokio::test]
     async fn unable_to_execute_result() {
-        if let ExecutionResult::Error(_) = start_and_await(bad_program()).await {
+        if let ExecutionStatus::Error(_) = start_and_await_status(bad_program()).await {
         } else {
             panic!("expected error");
         }
@@ -233,12 +843,19 @@ mod tests {
         let guard = mutex.lock().unwrap();
         let mutex_clone = mutex.clone();
         let (tx, rx) = tokio::sync::oneshot::channel();
-        let _handle = start(bad_program(), move |result| {
-            let _guard = mutex_clone.try_lock().unwrap();
-            tx.send(result).unwrap()
-        });
+        let _handle = start(
+            bad_program(),
+            vec![],
+            None,
+            None,
+            |_, _| {},
+            move |result| {
+                let _guard = mutex_clone.try_lock().unwrap();
+                tx.send(result).unwrap()
+            },
+        );
         drop(guard);
-        if let ExecutionResult::Error(_) = rx.await.unwrap() {
+        if let ExecutionStatus::Error(_) = rx.await.unwrap().status {
         } else {
             panic!("expected error");
         }
@@ -247,21 +864,50 @@ mod tests {
     #[tokio::test]
     async fn handle_does_not_signal_if_process_exited() {
         let (result, killed) = start_and_await_with_logging_killer(bash!("exit 1")).await;
-        assert_eq!(result, ExecutionResult::Exited(1));
+        assert_eq!(result, ExecutionStatus::Exited(1));
         assert!(killed.is_none());
     }
 
+    #[tokio::test]
+    async fn handle_dropped_by_done_callback_does_not_signal() {
+        // The worker drops the handle as soon as it hears from the done callback, so it may be
+        // dropped before the callback even returns.
+        let killer = Arc::new(Mutex::new(None));
+        let handle = Arc::new(Mutex::new(None));
+        let handle_clone = handle.clone();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        {
+            // The callback can't take the handle until it's been stored.
+            let mut guard = handle.lock().unwrap();
+            *guard = Some(start_with_killer(
+                bash!("exit 0"),
+                vec![],
+                None,
+                None,
+                |_, _| {},
+                move |result| {
+                    drop(handle_clone.lock().unwrap().take());
+                    tx.send(result).unwrap();
+                },
+                killer.clone(),
+            ));
+        }
+        assert_eq!(rx.await.unwrap().status, ExecutionStatus::Exited(0));
+        assert!(handle.lock().unwrap().is_none());
+        assert!(killer.lock().unwrap().is_none());
+    }
+
     #[tokio::test]
     async fn handle_does_not_signal_if_process_killed() {
         let (result, killed) = start_and_await_with_logging_killer(bash!("kill $$")).await;
-        assert_eq!(result, ExecutionResult::Signalled(15));
+        assert_eq!(result, ExecutionStatus::signalled(15, false));
         assert!(killed.is_none());
     }
 
     #[tokio::test]
     async fn handle_does_not_signal_if_process_does_not_start() {
         let (result, killed) = start_and_await_with_logging_killer(bad_program()).await;
-        if let ExecutionResult::Error(_) = result {
+        if let ExecutionStatus::Error(_) = result {
         } else {
             panic!("expected error");
         }
@@ -274,12 +920,1338 @@ mod tests {
         let killer = Arc::new(Mutex::new(None));
         let handle = start_with_killer(
             bash!("sleep infinity"),
+            vec![],
+            None,
+            None,
+            |_, _| {},
+            move |result| tx.send(result).unwrap(),
+            killer.clone(),
+        );
+        drop(handle);
+        let result = rx.await.unwrap();
+        assert_eq!(result.status, ExecutionStatus::signalled(15, false));
+        assert_eq!(*killer.lock().unwrap(), Some(Signal::SIGTERM));
+    }
+
+    #[tokio::test]
+    async fn handle_sends_sigkill_on_drop_if_no_grace_period() {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let killer = Arc::new(Mutex::new(None));
+        let handle = start_with_killer(
+            ExecutionDetails {
+                grace_period: Duration::ZERO,
+                ..bash!("trap '' TERM; sleep infinity")
+            },
+            vec![],
+            None,
+            None,
+            |_, _| {},
             move |result| tx.send(result).unwrap(),
             killer.clone(),
         );
         drop(handle);
         let result = rx.await.unwrap();
-        assert_eq!(result, ExecutionResult::Signalled(9));
+        assert_eq!(result.status, ExecutionStatus::signalled(9, false));
+        assert_eq!(result.termination, Some(Termination::Killed));
         assert_eq!(*killer.lock().unwrap(), Some(Signal::SIGKILL));
     }
+
+    /// Start `details`, which must print something once it's ready to be stopped, then drop its
+    /// handle and return the result.
+    async fn start_and_cancel_when_ready(details: ExecutionDetails) -> ExecutionResult {
+        let (output_tx, mut output_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let handle = start(
+            ExecutionDetails {
+                stdout_limit: Some(1024),
+                ..details
+            },
+            vec![],
+            None,
+            None,
+            move |_, chunk| drop(output_tx.send(chunk)),
+            move |result| tx.send(result).unwrap(),
+        );
+        output_rx.recv().await.unwrap();
+        drop(handle);
+        rx.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn canceled_execution_can_clean_up_within_grace_period() {
+        let result = start_and_cancel_when_ready(bash!(
+            "trap 'echo cleaning up; exit 0' TERM; echo ready; sleep infinity & wait"
+        ))
+        .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert_eq!(result.termination, Some(Termination::Graceful));
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"ready\ncleaning up\n".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn canceled_execution_killed_at_end_of_grace_period() {
+        let start = tokio::time::Instant::now();
+        let grace_period = Duration::from_millis(200);
+        let result = start_and_cancel_when_ready(ExecutionDetails {
+            grace_period,
+            ..bash!("trap '' TERM; echo ready; sleep infinity")
+        })
+        .await;
+        assert_eq!(result.status, ExecutionStatus::signalled(9, false));
+        assert_eq!(result.termination, Some(Termination::Killed));
+        assert!(start.elapsed() >= grace_period);
+    }
+
+    #[tokio::test]
+    async fn canceled_execution_sent_its_termination_signal() {
+        let result = start_and_cancel_when_ready(ExecutionDetails {
+            termination_signal: libc::SIGUSR1,
+            ..bash!("trap 'echo got USR1; exit 0' USR1; echo ready; sleep infinity & wait")
+        })
+        .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"ready\ngot USR1\n".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn timed_out_execution_can_clean_up_within_grace_period() {
+        let result = start_and_await(ExecutionDetails {
+            timeout: Some(Duration::from_millis(100)),
+            stdout_limit: Some(1024),
+            ..bash!("trap 'echo cleaning up; exit 0' TERM; sleep infinity & wait")
+        })
+        .await;
+        assert!(matches!(result.status, ExecutionStatus::TimedOut(_)));
+        assert_eq!(result.termination, Some(Termination::Graceful));
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"cleaning up\n".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn timed_out_execution_killed_at_end_of_grace_period() {
+        let result = start_and_await(ExecutionDetails {
+            timeout: Some(Duration::from_millis(100)),
+            grace_period: Duration::from_millis(100),
+            ..bash!("trap '' TERM; sleep infinity")
+        })
+        .await;
+        match result.status {
+            ExecutionStatus::TimedOut(elapsed) => assert!(elapsed >= Duration::from_millis(200)),
+            status => panic!("expected timeout, got {status:?}"),
+        }
+        assert_eq!(result.termination, Some(Termination::Killed));
+    }
+
+    #[tokio::test]
+    async fn execution_ending_on_its_own_has_no_termination() {
+        assert_eq!(start_and_await(bash!("exit 0")).await.termination, None);
+    }
+
+    #[tokio::test]
+    async fn invalid_termination_signal_is_error() {
+        let result = start_and_await(ExecutionDetails {
+            termination_signal: 1000,
+            ..bash!("exit 0")
+        })
+        .await;
+        assert_eq!(
+            result.status,
+            ExecutionStatus::Error("invalid termination signal 1000".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn process_limits_applied() {
+        let result = start_and_await(ExecutionDetails {
+            process_limits: ProcessLimits {
+                core_file_size: Some(0),
+                open_files: Some(64),
+                cpu_seconds: Some(10),
+                address_space: Some(1 << 30),
+                file_size: Some(1 << 20),
+            },
+            stdout_limit: Some(1024),
+            ..bash!("echo $(ulimit -c) $(ulimit -Hn) $(ulimit -t) $(ulimit -v) $(ulimit -f)")
+        })
+        .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"0 64 10 1048576 1024\n".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn process_limits_applied_in_sandbox() {
+        let result = start_and_await(ExecutionDetails {
+            process_limits: ProcessLimits {
+                open_files: Some(64),
+                ..Default::default()
+            },
+            ..sandboxed(bash!("ulimit -Hn"))
+        })
+        .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert_eq!(result.stdout, CapturedOutput::Complete(b"64\n".to_vec()));
+    }
+
+    /// The test process's hard limit on open files, which executions inherit.
+    fn open_files_hard_limit() -> u64 {
+        let mut limit = nix::libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: limit is valid for writes.
+        assert_eq!(
+            unsafe { nix::libc::getrlimit(nix::libc::RLIMIT_NOFILE, &mut limit) },
+            0
+        );
+        limit.rlim_max
+    }
+
+    #[tokio::test]
+    async fn unset_process_limits_left_alone() {
+        let result = start_and_await(ExecutionDetails {
+            stdout_limit: Some(1024),
+            ..bash!("ulimit -Hn")
+        })
+        .await;
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(format!("{}\n", open_files_hard_limit()).into_bytes())
+        );
+    }
+
+    #[tokio::test]
+    async fn execution_exceeding_file_size_limit_signalled() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = start_and_await(ExecutionDetails {
+            process_limits: ProcessLimits {
+                core_file_size: Some(0),
+                file_size: Some(1024),
+                ..Default::default()
+            },
+            ..bash!(
+                "exec head -c 2048 /dev/zero > {}/file",
+                dir.path().display()
+            )
+        })
+        .await;
+        assert_eq!(
+            result.status,
+            ExecutionStatus::signalled(nix::libc::SIGXFSZ, false)
+        );
+        assert_eq!(
+            std::fs::metadata(dir.path().join("file")).unwrap().len(),
+            1024
+        );
+    }
+
+    #[tokio::test]
+    async fn process_limit_higher_than_workers_is_error() {
+        let limit = open_files_hard_limit() + 1;
+        let result = start_and_await(ExecutionDetails {
+            process_limits: ProcessLimits {
+                open_files: Some(limit),
+                ..Default::default()
+            },
+            ..bash!("exit 0")
+        })
+        .await;
+        assert_eq!(
+            result.status,
+            ExecutionStatus::Error(format!(
+                "open files limit of {limit} is higher than the worker's limit of {}",
+                limit - 1
+            ))
+        );
+    }
+
+    /// An argument for `sleep` that no other process is using, so that tests can find the
+    /// processes they start.
+    fn unique_sleep_argument() -> String {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        format!("{}.{id}", 100_000 + std::process::id())
+    }
+
+    /// Whether any process that hasn't exited has `argument` on its command line. The command
+    /// lines of zombies are empty, so they don't count.
+    fn running_with_argument(argument: &str) -> bool {
+        std::fs::read_dir("/proc").unwrap().any(|entry| {
+            std::fs::read(entry.unwrap().path().join("cmdline")).is_ok_and(|cmdline| {
+                cmdline
+                    .split(|byte| *byte == 0)
+                    .any(|arg| arg == argument.as_bytes())
+            })
+        })
+    }
+
+    /// Wait for there to be a process with `argument` on its command line if `running`, or for
+    /// there to be none if not.
+    async fn await_running_with_argument(argument: &str, running: bool) {
+        let start = std::time::Instant::now();
+        while running_with_argument(argument) != running {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "process with argument {argument} running: {}",
+                !running
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Start `details`, which must print something once it has started `sleep` with `argument` in
+    /// the background, and wait for that `sleep` to be running.
+    async fn start_with_sleeping_descendant(
+        details: ExecutionDetails,
+        argument: &str,
+    ) -> (Handle, tokio::sync::oneshot::Receiver<ExecutionResult>) {
+        let (output_tx, mut output_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let handle = start(
+            details,
+            vec![],
+            None,
+            None,
+            move |_, chunk| output_tx.send(chunk).unwrap(),
+            move |result| tx.send(result).unwrap(),
+        );
+        output_rx.recv().await.unwrap();
+        await_running_with_argument(argument, true).await;
+        (handle, rx)
+    }
+
+    #[tokio::test]
+    async fn descendants_killed_when_execution_completes() {
+        let argument = unique_sleep_argument();
+        let (_handle, rx) = start_with_sleeping_descendant(
+            bash!("sleep {argument} & echo started; sleep 0.5"),
+            &argument,
+        )
+        .await;
+        assert_eq!(rx.await.unwrap().status, ExecutionStatus::Exited(0));
+        await_running_with_argument(&argument, false).await;
+    }
+
+    #[tokio::test]
+    async fn descendants_killed_when_execution_times_out() {
+        let argument = unique_sleep_argument();
+        let (_handle, rx) = start_with_sleeping_descendant(
+            ExecutionDetails {
+                timeout: Some(Duration::from_millis(500)),
+                ..bash!("sleep {argument} & echo started; wait")
+            },
+            &argument,
+        )
+        .await;
+        assert!(matches!(
+            rx.await.unwrap().status,
+            ExecutionStatus::TimedOut(_)
+        ));
+        await_running_with_argument(&argument, false).await;
+    }
+
+    #[tokio::test]
+    async fn descendants_killed_when_handle_dropped() {
+        let argument = unique_sleep_argument();
+        let (handle, rx) = start_with_sleeping_descendant(
+            bash!("sleep {argument} & echo started; wait"),
+            &argument,
+        )
+        .await;
+        drop(handle);
+        assert_eq!(
+            rx.await.unwrap().status,
+            ExecutionStatus::signalled(15, false)
+        );
+        await_running_with_argument(&argument, false).await;
+    }
+
+    #[tokio::test]
+    async fn sandboxed_descendants_leaving_process_group_killed() {
+        let argument = unique_sleep_argument();
+        let (_handle, rx) = start_with_sleeping_descendant(
+            sandboxed(bash!("setsid sleep {argument} & echo started; sleep 0.5")),
+            &argument,
+        )
+        .await;
+        assert_eq!(rx.await.unwrap().status, ExecutionStatus::Exited(0));
+        await_running_with_argument(&argument, false).await;
+    }
+
+    #[tokio::test]
+    async fn stdout_and_stderr_forwarded() {
+        let (result, chunks) =
+            start_and_await_output(bash!("echo out; echo err >&2; exit 3")).await;
+        assert_eq!(result.status, ExecutionStatus::Exited(3));
+        assert_eq!(collect_stream(&chunks, OutputStream::Stdout), b"out\n");
+        assert_eq!(collect_stream(&chunks, OutputStream::Stderr), b"err\n");
+    }
+
+    #[tokio::test]
+    async fn no_output() {
+        let (result, chunks) = start_and_await_output(bash!("exit 0")).await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert!(chunks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn large_output_is_chunked() {
+        let size = 3 * OUTPUT_CHUNK_SIZE + 1;
+        let (result, chunks) = start_and_await_output(bash!("head -c {size} /dev/zero")).await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert!(chunks
+            .iter()
+            .all(|(_, chunk)| !chunk.is_empty() && chunk.len() <= OUTPUT_CHUNK_SIZE));
+        assert_eq!(collect_stream(&chunks, OutputStream::Stdout), vec![0; size]);
+    }
+
+    #[tokio::test]
+    async fn output_from_exited_child_not_held_up_by_descendants() {
+        let (result, chunks) =
+            start_and_await_output(bash!("echo out; sleep infinity & exit 0")).await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert_eq!(collect_stream(&chunks, OutputStream::Stdout), b"out\n");
+    }
+
+    #[tokio::test]
+    async fn output_not_captured_by_default() {
+        let result = start_and_await(bash!("echo out; echo err >&2")).await;
+        assert_eq!(result.stdout, CapturedOutput::None);
+        assert_eq!(result.stderr, CapturedOutput::None);
+    }
+
+    #[tokio::test]
+    async fn output_captured_within_limits() {
+        let result = start_and_await(ExecutionDetails {
+            stdout_limit: Some(4),
+            stderr_limit: Some(100),
+            ..bash!("echo out; echo err >&2")
+        })
+        .await;
+        assert_eq!(result.stdout, CapturedOutput::Complete(b"out\n".to_vec()));
+        assert_eq!(result.stderr, CapturedOutput::Complete(b"err\n".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn only_requested_stream_captured() {
+        let result = start_and_await(ExecutionDetails {
+            stderr_limit: Some(100),
+            ..bash!("echo out; echo err >&2")
+        })
+        .await;
+        assert_eq!(result.stdout, CapturedOutput::None);
+        assert_eq!(result.stderr, CapturedOutput::Complete(b"err\n".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn output_over_limit_keeps_tail() {
+        let result = start_and_await(ExecutionDetails {
+            stdout_limit: Some(3),
+            ..bash!("echo -n abcdefgh")
+        })
+        .await;
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Truncated {
+                tail: b"fgh".to_vec(),
+                truncated: 5
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn large_output_over_limit_keeps_tail() {
+        let size = 3 * OUTPUT_CHUNK_SIZE;
+        let result = start_and_await(ExecutionDetails {
+            stdout_limit: Some(OUTPUT_CHUNK_SIZE as u64 + 1),
+            ..bash!("head -c {size} /dev/zero; echo -n x")
+        })
+        .await;
+        let mut tail = vec![0; OUTPUT_CHUNK_SIZE];
+        tail.push(b'x');
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Truncated {
+                tail,
+                truncated: 2 * OUTPUT_CHUNK_SIZE as u64
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn zero_limit_only_counts() {
+        let result = start_and_await(ExecutionDetails {
+            stdout_limit: Some(0),
+            ..bash!("echo -n abc")
+        })
+        .await;
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Truncated {
+                tail: vec![],
+                truncated: 3
+            }
+        );
+    }
+
+    #[test]
+    fn capture_limit_clamped() {
+        assert_eq!(Capture::new(u64::MAX).limit as u64, MAX_CAPTURED_OUTPUT);
+    }
+
+    fn with_environment(
+        details: ExecutionDetails,
+        clear_environment: bool,
+        environment: &[(&str, &str)],
+    ) -> ExecutionDetails {
+        ExecutionDetails {
+            environment: environment
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            clear_environment,
+            stdout_limit: Some(1024),
+            ..details
+        }
+    }
+
+    #[tokio::test]
+    async fn environment_variables_set() {
+        let result = start_and_await(with_environment(
+            bash!("echo -n $FOO,$BAR"),
+            false,
+            &[("FOO", "foo"), ("BAR", "bar baz")],
+        ))
+        .await;
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"foo,bar baz".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn environment_inherited_by_default() {
+        let result = start_and_await(with_environment(
+            bash!("echo -n ${{PATH:+set}}"),
+            false,
+            &[],
+        ))
+        .await;
+        assert_eq!(result.stdout, CapturedOutput::Complete(b"set".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn environment_variable_overrides_inherited() {
+        let result = start_and_await(with_environment(
+            bash!("echo -n $HOME"),
+            false,
+            &[("HOME", "/nonexistent")],
+        ))
+        .await;
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"/nonexistent".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn cleared_environment_only_has_requested_variables() {
+        // With a cleared environment there is no PATH, so the program is given by absolute path.
+        let result = start_and_await(with_environment(
+            ExecutionDetails {
+                program: "/usr/bin/env".to_string(),
+                ..Default::default()
+            },
+            true,
+            &[("FOO", "foo")],
+        ))
+        .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"FOO=foo\n".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn working_directory_absolute() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let result = start_and_await(ExecutionDetails {
+            working_directory: Some(tempdir.path().to_path_buf()),
+            stdout_limit: Some(1024),
+            ..bash!("pwd -P")
+        })
+        .await;
+        let expected = format!("{}\n", tempdir.path().canonicalize().unwrap().display());
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(expected.into_bytes())
+        );
+    }
+
+    #[tokio::test]
+    async fn working_directory_relative_to_root() {
+        let result = start_and_await(ExecutionDetails {
+            working_directory: Some("usr/bin".into()),
+            stdout_limit: Some(1024),
+            ..bash!("pwd")
+        })
+        .await;
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"/usr/bin\n".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_working_directory_is_error() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let status = start_and_await_status(ExecutionDetails {
+            working_directory: Some(tempdir.path().join("missing")),
+            ..bash!("exit 0")
+        })
+        .await;
+        assert!(matches!(status, ExecutionStatus::Error(_)));
+    }
+
+    #[tokio::test]
+    async fn timeout_kills_execution() {
+        let timeout = Duration::from_millis(100);
+        let result = start_and_await(ExecutionDetails {
+            timeout: Some(timeout),
+            stdout_limit: Some(1024),
+            ..bash!("echo started; sleep infinity")
+        })
+        .await;
+        match result.status {
+            ExecutionStatus::TimedOut(elapsed) => assert!(elapsed >= timeout),
+            status => panic!("expected timeout, got {status:?}"),
+        }
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"started\n".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn execution_finishing_within_timeout_not_killed() {
+        let (status, killed) = start_and_await_with_logging_killer(ExecutionDetails {
+            timeout: Some(Duration::from_secs(60)),
+            ..bash!("exit 2")
+        })
+        .await;
+        assert_eq!(status, ExecutionStatus::Exited(2));
+        assert!(killed.is_none());
+    }
+
+    #[tokio::test]
+    async fn resource_usage_reported() {
+        let result = start_and_await(bash!("sleep 0.1")).await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        let usage = result.resource_usage.unwrap();
+        assert!(usage.wall_time >= Duration::from_millis(100));
+        assert!(usage.max_rss > 0);
+    }
+
+    #[tokio::test]
+    async fn cpu_time_reported() {
+        let result = start_and_await(bash!("for i in {{1..200000}}; do :; done")).await;
+        let usage = result.resource_usage.unwrap();
+        assert!(usage.user_time + usage.system_time > Duration::ZERO);
+        assert!(usage.user_time + usage.system_time <= usage.wall_time);
+    }
+
+    #[tokio::test]
+    async fn resource_usage_reported_for_killed_execution() {
+        let result = start_and_await(ExecutionDetails {
+            timeout: Some(Duration::from_millis(100)),
+            ..bash!("sleep infinity")
+        })
+        .await;
+        assert!(matches!(result.status, ExecutionStatus::TimedOut(_)));
+        assert!(result.resource_usage.is_some());
+    }
+
+    #[tokio::test]
+    async fn no_resource_usage_if_execution_does_not_start() {
+        assert!(start_and_await(bad_program())
+            .await
+            .resource_usage
+            .is_none());
+    }
+
+    fn sandboxed(details: ExecutionDetails) -> ExecutionDetails {
+        ExecutionDetails {
+            sandbox: true,
+            stdout_limit: Some(1024),
+            ..details
+        }
+    }
+
+    #[tokio::test]
+    async fn sandboxed_execution_runs_as_root_and_pid_1() {
+        let result = start_and_await(sandboxed(bash!("echo $$ $(id -u) $(id -g)"))).await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert_eq!(result.stdout, CapturedOutput::Complete(b"1 0 0\n".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn sandboxed_execution_cannot_see_worker_processes() {
+        let pid = std::process::id();
+        assert_eq!(
+            start_and_await_status(bash!("test -e /proc/{pid}")).await,
+            ExecutionStatus::Exited(0)
+        );
+        assert_eq!(
+            start_and_await_status(sandboxed(bash!("test -e /proc/{pid}"))).await,
+            ExecutionStatus::Exited(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn sandboxed_execution_has_own_hostname() {
+        let result = start_and_await(sandboxed(bash!("cat /proc/sys/kernel/hostname"))).await;
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"meticulous\n".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn sandboxed_execution_killing_its_process_group_spares_worker() {
+        // The kernel doesn't let processes in the sandbox kill its PID 1, so only the inner shell
+        // dies.
+        let result = start_and_await(sandboxed(bash!("bash -c 'kill -KILL 0'; echo $?"))).await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert_eq!(result.stdout, CapturedOutput::Complete(b"137\n".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn sandboxed_execution_exit_code_reported() {
+        assert_eq!(
+            start_and_await_status(sandboxed(bash!("exit 3"))).await,
+            ExecutionStatus::Exited(3)
+        );
+    }
+
+    #[tokio::test]
+    async fn sandboxed_execution_killed_on_timeout() {
+        let result = start_and_await(ExecutionDetails {
+            timeout: Some(Duration::from_millis(100)),
+            grace_period: Duration::ZERO,
+            ..sandboxed(bash!("echo started; sleep infinity"))
+        })
+        .await;
+        assert!(matches!(result.status, ExecutionStatus::TimedOut(_)));
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"started\n".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn sandboxed_execution_sent_termination_signal() {
+        let result = start_and_await(ExecutionDetails {
+            timeout: Some(Duration::from_millis(100)),
+            ..sandboxed(bash!(
+                "trap 'echo cleaning up; exit 0' TERM; sleep infinity & wait"
+            ))
+        })
+        .await;
+        assert!(matches!(result.status, ExecutionStatus::TimedOut(_)));
+        assert_eq!(result.termination, Some(Termination::Graceful));
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"cleaning up\n".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn sandboxed_execution_resource_usage_reported() {
+        let result = start_and_await(sandboxed(bash!("for i in {{1..200000}}; do :; done"))).await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        let usage = result.resource_usage.unwrap();
+        assert!(usage.user_time + usage.system_time > Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn sandboxed_execution_unable_to_execute_result() {
+        let result = start_and_await(sandboxed(bad_program())).await;
+        assert!(matches!(result.status, ExecutionStatus::Error(_)));
+        assert!(result.resource_usage.is_none());
+    }
+
+    fn network_isolated(details: ExecutionDetails) -> ExecutionDetails {
+        ExecutionDetails {
+            isolate_network: true,
+            stdout_limit: Some(1024),
+            stderr_limit: Some(1024),
+            ..details
+        }
+    }
+
+    #[tokio::test]
+    async fn network_isolated_execution_runs_in_sandbox() {
+        let result = start_and_await(network_isolated(bash!("echo $$"))).await;
+        assert_eq!(result.stdout, CapturedOutput::Complete(b"1\n".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn network_isolated_execution_has_only_loopback() {
+        let result = start_and_await(network_isolated(bash!(
+            "tail -n +3 /proc/net/dev | cut -d: -f1 | tr -d ' '"
+        )))
+        .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert_eq!(result.stdout, CapturedOutput::Complete(b"lo\n".to_vec()));
+    }
+
+    fn stderr_contains(result: &ExecutionResult, expected: &str) -> bool {
+        let CapturedOutput::Complete(stderr) = &result.stderr else {
+            panic!("stderr not captured: {:?}", result.stderr);
+        };
+        String::from_utf8_lossy(stderr).contains(expected)
+    }
+
+    #[tokio::test]
+    async fn network_isolated_execution_has_loopback_up() {
+        // With loopback down, connecting would fail with "Network is unreachable".
+        let result = start_and_await(network_isolated(bash!("echo > /dev/tcp/127.0.0.1/1"))).await;
+        assert_eq!(result.status, ExecutionStatus::Exited(1));
+        assert!(stderr_contains(&result, "Connection refused"));
+    }
+
+    #[tokio::test]
+    async fn network_isolated_execution_cannot_reach_network() {
+        let result = start_and_await(network_isolated(bash!("echo > /dev/tcp/192.0.2.1/80"))).await;
+        assert_eq!(result.status, ExecutionStatus::Exited(1));
+        assert!(stderr_contains(&result, "Network is unreachable"));
+    }
+
+    #[tokio::test]
+    async fn network_isolated_execution_with_layers_has_only_loopback() {
+        let bash = bash_layer();
+        let result =
+            start_and_await_in_layers(network_isolated(bash!("echo $(</proc/net/dev)")), &[&bash])
+                .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        let CapturedOutput::Complete(stdout) = result.stdout else {
+            panic!("stdout not captured");
+        };
+        let interfaces = String::from_utf8(stdout).unwrap().matches(':').count();
+        assert_eq!(interfaces, 1);
+    }
+
+    /// A layer with just bash and the libraries it needs.
+    fn bash_layer() -> tempfile::TempDir {
+        let layer = tempfile::tempdir().unwrap();
+        let ldd = std::process::Command::new("ldd")
+            .arg("/bin/bash")
+            .output()
+            .unwrap();
+        let ldd = String::from_utf8(ldd.stdout).unwrap();
+        let libraries = ldd
+            .split_whitespace()
+            .filter(|word| word.starts_with('/'))
+            .map(Path::new);
+        for file in libraries.chain([Path::new("/bin/bash")]) {
+            let target = layer.path().join(file.strip_prefix("/").unwrap());
+            std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+            std::fs::copy(file, target).unwrap();
+        }
+        layer
+    }
+
+    /// A layer with the given files, given as paths relative to the root and their contents.
+    fn files_layer(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let layer = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let target = layer.path().join(path);
+            std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+            std::fs::write(target, contents).unwrap();
+        }
+        layer
+    }
+
+    async fn start_and_await_in_layers(
+        details: ExecutionDetails,
+        layers: &[&tempfile::TempDir],
+    ) -> ExecutionResult {
+        let layers = layers.iter().map(|layer| layer.path().to_owned()).collect();
+        start_and_await_with_layers(
+            ExecutionDetails {
+                stdout_limit: Some(1024),
+                ..details
+            },
+            layers,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn layers_make_up_root_file_system() {
+        let bash = bash_layer();
+        let files = files_layer(&[("etc/message", "hello")]);
+        let result =
+            start_and_await_in_layers(bash!("echo $(< /etc/message)"), &[&bash, &files]).await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert_eq!(result.stdout, CapturedOutput::Complete(b"hello\n".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn upper_layers_override_lower_layers() {
+        let bash = bash_layer();
+        let lower = files_layer(&[("etc/message", "lower"), ("etc/other", "other")]);
+        let upper = files_layer(&[("etc/message", "upper")]);
+        let result = start_and_await_in_layers(
+            bash!("echo $(< /etc/message) $(< /etc/other)"),
+            &[&bash, &lower, &upper],
+        )
+        .await;
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"upper other\n".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn worker_file_system_hidden_by_layers() {
+        let bash = bash_layer();
+        let result =
+            start_and_await_in_layers(bash!("test -e {}", bash.path().display()), &[&bash]).await;
+        assert_eq!(result.status, ExecutionStatus::Exited(1));
+    }
+
+    #[tokio::test]
+    async fn working_directory_relative_to_layers_root() {
+        let bash = bash_layer();
+        let files = files_layer(&[("work/file", "")]);
+        let result = start_and_await_in_layers(
+            ExecutionDetails {
+                working_directory: Some(PathBuf::from("work")),
+                ..bash!("echo $PWD; test -e file")
+            },
+            &[&bash, &files],
+        )
+        .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert_eq!(result.stdout, CapturedOutput::Complete(b"/work\n".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn changes_to_layers_discarded() {
+        let bash = bash_layer();
+        let files = files_layer(&[("etc/message", "hello")]);
+        let result = start_and_await_in_layers(
+            bash!("echo bye > /etc/message && echo tmp > /tmp/file && echo $(< /etc/message)"),
+            &[&bash, &files],
+        )
+        .await;
+        assert_eq!(result.stdout, CapturedOutput::Complete(b"bye\n".to_vec()));
+        assert_eq!(
+            std::fs::read_to_string(files.path().join("etc/message")).unwrap(),
+            "hello"
+        );
+        assert!(!files.path().join("tmp").exists());
+    }
+
+    #[tokio::test]
+    async fn missing_program_in_layers_is_error() {
+        let files = files_layer(&[("etc/message", "hello")]);
+        let result = start_and_await_in_layers(bash!("exit 0"), &[&files]).await;
+        assert!(matches!(result.status, ExecutionStatus::Error(_)));
+    }
+
+    async fn start_and_await_with_scratch_dir(
+        details: ExecutionDetails,
+        layers: &[&tempfile::TempDir],
+        scratch_dir: &Path,
+    ) -> ExecutionResult {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let _handle = start(
+            ExecutionDetails {
+                scratch: details.scratch.or(Some(Scratch::default())),
+                stdout_limit: Some(1024),
+                stderr_limit: Some(1024),
+                ..details
+            },
+            layers.iter().map(|layer| layer.path().to_owned()).collect(),
+            Some(scratch_dir),
+            None,
+            |_, _| {},
+            move |result| tx.send(result).unwrap(),
+        );
+        rx.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn scratch_dir_in_tmpdir() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let result = start_and_await_with_scratch_dir(
+            bash!("echo $TMPDIR; pwd; echo hello > $TMPDIR/file"),
+            &[],
+            scratch_dir.path(),
+        )
+        .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        let expected = format!(
+            "{}\n{}\n",
+            scratch_dir.path().display(),
+            std::env::current_dir().unwrap().display()
+        );
+        assert_eq!(result.stdout, CapturedOutput::Complete(expected.into()));
+        assert_eq!(
+            std::fs::read(scratch_dir.path().join("file")).unwrap(),
+            b"hello\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn scratch_dir_tmpdir_overridden_by_environment() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let result = start_and_await_with_scratch_dir(
+            ExecutionDetails {
+                environment: BTreeMap::from([("TMPDIR".to_string(), "/elsewhere".to_string())]),
+                ..bash!("echo $TMPDIR")
+            },
+            &[],
+            scratch_dir.path(),
+        )
+        .await;
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"/elsewhere\n".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn scratch_dir_as_working_directory() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let result = start_and_await_with_scratch_dir(
+            ExecutionDetails {
+                working_directory: Some(PathBuf::from("/")),
+                scratch: Some(Scratch {
+                    working_directory: true,
+                    quota: None,
+                }),
+                ..bash!("pwd")
+            },
+            &[],
+            scratch_dir.path(),
+        )
+        .await;
+        let expected = format!("{}\n", scratch_dir.path().display());
+        assert_eq!(result.stdout, CapturedOutput::Complete(expected.into()));
+    }
+
+    #[tokio::test]
+    async fn sandboxed_execution_uses_scratch_dir() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let result = start_and_await_with_scratch_dir(
+            sandboxed(bash!("echo hello > $TMPDIR/file")),
+            &[],
+            scratch_dir.path(),
+        )
+        .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert_eq!(
+            std::fs::read(scratch_dir.path().join("file")).unwrap(),
+            b"hello\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn scratch_dir_mounted_on_tmp_in_layers() {
+        let bash = bash_layer();
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let result = start_and_await_with_scratch_dir(
+            ExecutionDetails {
+                scratch: Some(Scratch {
+                    working_directory: true,
+                    quota: None,
+                }),
+                ..bash!("echo $TMPDIR $PWD; echo hello > file")
+            },
+            &[&bash],
+            scratch_dir.path(),
+        )
+        .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert_eq!(
+            result.stdout,
+            CapturedOutput::Complete(b"/tmp /tmp\n".to_vec())
+        );
+        assert_eq!(
+            std::fs::read(scratch_dir.path().join("file")).unwrap(),
+            b"hello\n"
+        );
+    }
+
+    const SCRATCH_QUOTA: u64 = 64 * 1024;
+
+    #[tokio::test]
+    async fn scratch_dir_quota_enforced() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let result = start_and_await_with_scratch_dir(
+            ExecutionDetails {
+                scratch: Some(Scratch {
+                    working_directory: false,
+                    quota: Some(SCRATCH_QUOTA),
+                }),
+                ..bash!(
+                    "head -c {} /dev/zero > $TMPDIR/small && head -c {} /dev/zero > $TMPDIR/big",
+                    SCRATCH_QUOTA / 2,
+                    SCRATCH_QUOTA
+                )
+            },
+            &[],
+            scratch_dir.path(),
+        )
+        .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(1));
+        assert!(stderr_contains(&result, "No space left on device"));
+        // The files went into a tmpfs, not the directory on the worker.
+        assert_eq!(std::fs::read_dir(scratch_dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn scratch_dir_quota_enforced_in_working_directory() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let result = start_and_await_with_scratch_dir(
+            ExecutionDetails {
+                scratch: Some(Scratch {
+                    working_directory: true,
+                    quota: Some(SCRATCH_QUOTA),
+                }),
+                ..bash!("head -c {} /dev/zero > big", SCRATCH_QUOTA * 2)
+            },
+            &[],
+            scratch_dir.path(),
+        )
+        .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(1));
+        assert!(stderr_contains(&result, "No space left on device"));
+    }
+
+    #[tokio::test]
+    async fn scratch_dir_quota_enforced_in_layers() {
+        let bash = bash_layer();
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let result = start_and_await_with_scratch_dir(
+            ExecutionDetails {
+                scratch: Some(Scratch {
+                    working_directory: false,
+                    quota: Some(SCRATCH_QUOTA),
+                }),
+                // The layer only has bash, so bash has to write the file itself.
+                ..bash!("printf %{}s x > /tmp/big", SCRATCH_QUOTA * 2)
+            },
+            &[&bash],
+            scratch_dir.path(),
+        )
+        .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(1));
+        assert!(stderr_contains(&result, "No space left on device"));
+    }
+
+    /// A cgroup for tests to create executions' cgroups in, made inside of the cgroup the tests run
+    /// in. It's removed when dropped.
+    ///
+    /// The tests that use this are ignored by default, since they need a cgroup v2 hierarchy that
+    /// they can create cgroups in, with the controllers they use enabled. Run them with
+    /// `cargo test -- --ignored` where that's available.
+    struct TestCgroup {
+        path: PathBuf,
+        parent: CgroupParent,
+    }
+
+    impl TestCgroup {
+        /// Return None if cgroup v2 isn't mounted, or if the tests can't create cgroups in it.
+        fn new() -> Option<Self> {
+            static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+            let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").unwrap();
+            let mount = mountinfo.lines().find_map(|line| {
+                let (fields, file_system) = line.split_once(" - ")?;
+                file_system
+                    .starts_with("cgroup2 ")
+                    .then(|| PathBuf::from(fields.split(' ').nth(4).unwrap()))
+            })?;
+            let cgroups = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+            let own = cgroups.lines().find_map(|line| line.strip_prefix("0::/"))?;
+            let path = mount.join(own).join(format!(
+                "meticulous-test-{}-{}",
+                std::process::id(),
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::create_dir(&path).ok()?;
+            let parent = CgroupParent::new(path.clone(), ResourceLimits::default()).unwrap();
+            Some(TestCgroup { path, parent })
+        }
+
+        fn has_controller(&self, controller: &str) -> bool {
+            std::fs::read_to_string(self.path.join("cgroup.controllers"))
+                .unwrap()
+                .split_whitespace()
+                .any(|c| c == controller)
+        }
+
+        async fn start_and_await(&mut self, details: ExecutionDetails) -> ExecutionResult {
+            let cgroup = self.parent.create(details.limits).unwrap();
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            let _handle = start(
+                details,
+                vec![],
+                None,
+                Some(cgroup),
+                |_, _| {},
+                move |result| tx.send(result).unwrap(),
+            );
+            rx.await.unwrap()
+        }
+    }
+
+    impl Drop for TestCgroup {
+        fn drop(&mut self) {
+            // The executions' cgroups are removed in the background, and this one can't be
+            // removed until they're gone.
+            let start = std::time::Instant::now();
+            while let Err(err) = std::fs::remove_dir(&self.path) {
+                assert!(start.elapsed() < Duration::from_secs(10), "{err}");
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    macro_rules! test_cgroup {
+        () => {
+            TestCgroup::new().expect("can't create cgroups")
+        };
+        ($controller:literal) => {{
+            let cgroup = test_cgroup!();
+            assert!(
+                cgroup.has_controller($controller),
+                "no {} controller",
+                $controller
+            );
+            cgroup
+        }};
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a cgroup v2 hierarchy to create cgroups in"]
+    async fn execution_runs_in_cgroup() {
+        let mut cgroup = test_cgroup!();
+        let result = cgroup
+            .start_and_await(ExecutionDetails {
+                stdout_limit: Some(1024),
+                ..bash!("grep ^0:: /proc/self/cgroup")
+            })
+            .await;
+        let stdout = String::from_utf8(result.stdout.bytes().to_vec()).unwrap();
+        assert!(stdout.ends_with("/execution-0\n"), "{stdout}");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a cgroup v2 hierarchy to create cgroups in"]
+    async fn sandboxed_execution_runs_in_cgroup() {
+        let mut cgroup = test_cgroup!();
+        let result = cgroup
+            .start_and_await(sandboxed(bash!("cat /proc/self/cgroup")))
+            .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        let expected = format!(
+            "/{}/execution-0\n",
+            cgroup.path.file_name().unwrap().to_str().unwrap()
+        );
+        let stdout = String::from_utf8(result.stdout.bytes().to_vec()).unwrap();
+        assert!(stdout.contains(&expected), "{stdout}");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a cgroup v2 hierarchy to create cgroups in"]
+    async fn cgroup_cpu_time_includes_orphaned_descendants() {
+        let mut cgroup = test_cgroup!();
+        // The busy loop is orphaned when its subshell exits, so it's never waited for, and its CPU
+        // time only shows up in the cgroup.
+        let result = cgroup
+            .start_and_await(bash!("(while :; do :; done > /dev/null 2>&1 &); sleep 0.5"))
+            .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        let usage = result.resource_usage.unwrap();
+        assert!(usage.user_time + usage.system_time >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a cgroup v2 hierarchy to create cgroups in, with the cpu controller"]
+    async fn cgroup_cpu_limit_applied() {
+        let mut cgroup = test_cgroup!("cpu");
+        let result = cgroup
+            .start_and_await(ExecutionDetails {
+                limits: ResourceLimits {
+                    cpu_millis: Some(100),
+                    ..Default::default()
+                },
+                ..bash!("while :; do :; done & sleep 1; kill $!")
+            })
+            .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        // Without the limit, the busy loop would use about as much CPU time as the execution took.
+        let usage = result.resource_usage.unwrap();
+        assert!(
+            usage.user_time + usage.system_time < usage.wall_time / 2,
+            "{usage:?}"
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a cgroup v2 hierarchy to create cgroups in, with the pids controller"]
+    async fn cgroup_pids_limit_applied() {
+        let mut cgroup = test_cgroup!("pids");
+        // With bash itself, only two of the sleeps fit. Bash retries the third fork once the first
+        // two sleeps exit, saying so on stderr.
+        let result = cgroup
+            .start_and_await(ExecutionDetails {
+                limits: ResourceLimits {
+                    pids: Some(3),
+                    ..Default::default()
+                },
+                stderr_limit: Some(1024),
+                ..bash!("sleep 1 & sleep 1 & sleep 1 & wait")
+            })
+            .await;
+        assert_eq!(result.status, ExecutionStatus::Exited(0));
+        assert!(stderr_contains(
+            &result,
+            "fork: retry: Resource temporarily unavailable"
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a cgroup v2 hierarchy to create cgroups in, with the memory controller"]
+    async fn execution_exceeding_memory_limit_reported_as_out_of_memory() {
+        let mut cgroup = test_cgroup!("memory");
+        let limit = 32 << 20;
+        let result = cgroup
+            .start_and_await(ExecutionDetails {
+                limits: ResourceLimits {
+                    memory: Some(limit),
+                    ..Default::default()
+                },
+                // tail has to keep the whole line in memory, since it never ends.
+                ..bash!("head -c 1G /dev/zero | tail -n 1")
+            })
+            .await;
+        assert_eq!(result.status, ExecutionStatus::OutOfMemory(limit));
+        assert!(result.resource_usage.unwrap().peak_memory.unwrap() >= limit / 2);
+    }
 }
@@ -0,0 +1,196 @@
+//! Fetch image layers into the worker's [super::cache::Cache].
+//!
+//! Layers are tar files named by their SHA-256 digest. The worker gets them from a layer directory,
+//! which is usually shared between workers, and extracts them into the cache, verifying the digest
+//! along the way.
+
+use crate::{Error, Result, Sha256Digest};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+/*              _     _ _
+ *  _ __  _   _| |__ | (_) ___
+ * | '_ \| | | | '_ \| | |/ __|
+ * | |_) | |_| | |_) | | | (__
+ * | .__/ \__,_|_.__/|_|_|\___|
+ * |_|
+ *  FIGLET: public
+ */
+
+/// Extract the tar file read from `input` into `output`, making sure that its SHA-256 digest is
+/// `digest`. Return the size of the tar file. If the digest doesn't match, an error is returned,
+/// but `output` may have been partially or fully populated.
+pub fn extract_verified(input: impl Read, digest: Sha256Digest, output: &Path) -> Result<u64> {
+    let mut counting_reader = CountingReader::new(input);
+    let mut sha_verifier = Sha256Verifier::new(&mut counting_reader, digest);
+    tar::Archive::new(&mut sha_verifier).unpack(output)?;
+    read_to_end(sha_verifier)?;
+    Ok(counting_reader.bytes_read())
+}
+
+/// The path of the tar file for `digest` in `layer_dir`.
+pub fn layer_path(layer_dir: &Path, digest: &Sha256Digest) -> PathBuf {
+    layer_dir.join(format!("{digest}.tar"))
+}
+
+/// Extract the layer for `digest` from `layer_dir` into `output`. Return the number of bytes the
+/// layer uses, for the cache's accounting.
+pub fn fetch_layer(layer_dir: &Path, digest: Sha256Digest, output: &Path) -> Result<u64> {
+    let path = layer_path(layer_dir, &digest);
+    let file = std::fs::File::open(&path)
+        .map_err(|err| Error::msg(format!("couldn't open {}: {err}", path.display())))?;
+    extract_verified(std::io::BufReader::new(file), digest, output)
+}
+
+/*             _            _
+ *  _ __  _ __(_)_   ____ _| |_ ___
+ * | '_ \| '__| \ \ / / _` | __/ _ \
+ * | |_) | |  | |\ V / (_| | ||  __/
+ * | .__/|_|  |_| \_/ \__,_|\__\___|
+ * |_|
+ *  FIGLET: private
+ */
+
+struct Sha256Verifier<DelegateT> {
+    hasher: Option<sha2::Sha256>,
+    delegate: DelegateT,
+    expected: Sha256Digest,
+}
+
+impl<DelegateT> Sha256Verifier<DelegateT> {
+    fn new(delegate: DelegateT, expected: Sha256Digest) -> Self {
+        use sha2::Digest;
+        Sha256Verifier {
+            hasher: Some(sha2::Sha256::new()),
+            delegate,
+            expected,
+        }
+    }
+}
+
+impl<DelegateT: Read> Read for Sha256Verifier<DelegateT> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use sha2::Digest;
+
+        // Take the hasher before we read. If there is an error reading, then we'll leave the
+        // struct without a hasher, so it won't report success later.
+        let hasher = self.hasher.take();
+        let size = self.delegate.read(buf)?;
+        if size > 0 {
+            self.hasher = hasher;
+            match &mut self.hasher {
+                None => {
+                    return Err(std::io::Error::other(
+                        "Unexepcted read of non-zero bytes after read of zero bytes or error",
+                    ));
+                }
+                Some(hasher) => {
+                    hasher.update(&buf[..size]);
+                }
+            }
+        } else if let Some(hasher) = hasher {
+            // If there is no hasher, we already validated the digest.
+            if Sha256Digest(hasher.finalize().into()) != self.expected {
+                return Err(std::io::Error::other("SHA-256 digest didn't match"));
+            }
+        }
+        Ok(size)
+    }
+}
+
+struct CountingReader<DelegateT> {
+    delegate: DelegateT,
+    count: u64,
+}
+
+impl<DelegateT> CountingReader<DelegateT> {
+    fn new(delegate: DelegateT) -> Self {
+        CountingReader { delegate, count: 0 }
+    }
+
+    fn bytes_read(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<DelegateT: Read> Read for CountingReader<DelegateT> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.delegate.read(buf)?;
+        self.count += count as u64;
+        Ok(count)
+    }
+}
+
+fn read_to_end(mut input: impl Read) -> std::io::Result<()> {
+    let mut buf = [0u8; 4096];
+    while input.read(&mut buf)? > 0 {}
+    Ok(())
+}
+
+/*  _            _
+ * | |_ ___  ___| |_ ___
+ * | __/ _ \/ __| __/ __|
+ * | ||  __/\__ \ |_\__ \
+ *  \__\___||___/\__|___/
+ *  FIGLET: tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tar_with_file(name: &str, contents: &[u8]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, name, contents).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    fn digest_of(bytes: &[u8]) -> Sha256Digest {
+        use sha2::Digest;
+        Sha256Digest(sha2::Sha256::digest(bytes).into())
+    }
+
+    #[test]
+    fn fetch_layer_extracts_and_reports_size() {
+        let layer_dir = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let tar = tar_with_file("etc/message", b"hello\n");
+        let digest = digest_of(&tar);
+        std::fs::write(layer_path(layer_dir.path(), &digest), &tar).unwrap();
+
+        let size = fetch_layer(layer_dir.path(), digest, &output.path().join("layer")).unwrap();
+        assert_eq!(size, tar.len() as u64);
+        assert_eq!(
+            std::fs::read(output.path().join("layer/etc/message")).unwrap(),
+            b"hello\n"
+        );
+    }
+
+    #[test]
+    fn fetch_layer_with_wrong_digest_is_error() {
+        let layer_dir = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let tar = tar_with_file("etc/message", b"hello\n");
+        let digest = Sha256Digest::from(1u32);
+        std::fs::write(layer_path(layer_dir.path(), &digest), &tar).unwrap();
+
+        assert!(fetch_layer(layer_dir.path(), digest, &output.path().join("layer")).is_err());
+    }
+
+    #[test]
+    fn fetch_missing_layer_is_error() {
+        let layer_dir = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        assert!(fetch_layer(
+            layer_dir.path(),
+            Sha256Digest::from(1u32),
+            &output.path().join("layer")
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,511 @@
+//! Run executions in their own Linux namespaces, so they can't see or disturb each other or the
+//! worker.
+//!
+//! The sandbox is entered between `fork` and `exec`, so everything here has to be
+//! async-signal-safe: no allocation, no locks, just system calls on data prepared beforehand.
+
+use nix::libc;
+use std::{
+    ffi::{CStr, CString},
+    io,
+    os::unix::{ffi::OsStrExt as _, process::CommandExt as _},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+/*              _     _ _
+ *  _ __  _   _| |__ | (_) ___
+ * | '_ \| | | | '_ \| | |/ __|
+ * | |_) | |_| | |_) | | | (__
+ * | .__/ \__,_|_.__/|_|_|\___|
+ * |_|
+ *  FIGLET: public
+ */
+
+/// Make `command` run in new user, mount, PID, IPC, and UTS namespaces. This doesn't require any
+/// privileges, as long as the kernel allows unprivileged user namespaces.
+///
+/// Inside the sandbox, the program runs as root, with the worker's user and group mapped to root,
+/// and as PID 1. It gets its own `/proc`, showing only the processes in the sandbox, its own
+/// hostname, and its own session, so it can't signal the worker even by killing its process group.
+///
+/// Since only the children of a process are put in a new PID namespace, the process started by
+/// `command` stays outside of the sandbox and forks the program into it. This process exits the
+/// same way the program does, so it stands in for the program. It passes the signals it gets on to
+/// the program, except for those that can't be caught. If it is killed, the kernel kills everything
+/// in the sandbox. Note that, as PID 1, the program doesn't get any signal it hasn't installed a
+/// handler for, other than SIGKILL.
+///
+/// If `root` is provided, the program runs in that root file system instead of the worker's. In
+/// that case, `command` must not have a current directory set: use [RootFs::working_directory]
+/// instead.
+///
+/// If `isolate_network` is true, the program also gets a network namespace of its own, with
+/// nothing in it but the loopback interface, which is brought up.
+///
+/// If `scratch` is provided, it's the program's scratch directory. In a `root`, it's mounted on
+/// `/tmp`.
+pub fn configure(
+    command: &mut Command,
+    root: Option<RootFs>,
+    isolate_network: bool,
+    scratch: Option<ScratchDir>,
+) {
+    let uid_map = id_map(nix::unistd::getuid().as_raw());
+    let gid_map = id_map(nix::unistd::getgid().as_raw());
+    // Invalid paths are reported when the command is spawned, like they are for the command's
+    // other arguments.
+    let root = root.map(|root| RootPaths::new(&root).map_err(|_| io::ErrorKind::InvalidInput));
+    let scratch =
+        scratch.map(|scratch| ScratchPaths::new(&scratch).map_err(|_| io::ErrorKind::InvalidInput));
+    // SAFETY: enter only makes async-signal-safe calls, and doesn't touch any memory shared with
+    // the parent.
+    unsafe {
+        command.pre_exec(move || {
+            let root = match &root {
+                None => None,
+                Some(Ok(root)) => Some(root),
+                Some(Err(kind)) => return Err((*kind).into()),
+            };
+            let scratch = match &scratch {
+                None => None,
+                Some(Ok(scratch)) => Some(scratch),
+                Some(Err(kind)) => return Err((*kind).into()),
+            };
+            enter(&uid_map, &gid_map, root, isolate_network, scratch)
+        })
+    };
+}
+
+/// A root file system for the sandbox, made by stacking `layers` on top of each other, bottom
+/// layer first. The layers are never modified: changes the program makes to its root file system
+/// are kept in memory and thrown away when the sandbox goes away.
+pub struct RootFs<'a> {
+    pub layers: &'a [PathBuf],
+
+    /// An empty directory for the sandbox to mount over. It must not contain any of the layers.
+    pub scratch: &'a Path,
+
+    /// The directory to start the program in, relative to the new root.
+    pub working_directory: &'a Path,
+}
+
+/// A scratch directory on the worker for the program to keep temporary files in.
+pub struct ScratchDir<'a> {
+    pub path: &'a Path,
+
+    /// If provided, the program can't store more than this many bytes in the scratch directory.
+    /// It gets a tmpfs of this size mounted over the directory, which then goes unused.
+    pub quota: Option<u64>,
+}
+
+/// Write `contents` to the existing file at `path`. This only makes async-signal-safe calls, so it
+/// can be used between `fork` and `exec`.
+pub fn write_file(path: &CStr, contents: &[u8]) -> io::Result<()> {
+    // SAFETY: path is NUL-terminated, and contents is valid for reads of its length.
+    unsafe {
+        let fd = check(libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC))?;
+        let written = libc::write(fd, contents.as_ptr().cast(), contents.len());
+        libc::close(fd);
+        if written != contents.len() as isize {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/*             _            _
+ *  _ __  _ __(_)_   ____ _| |_ ___
+ * | '_ \| '__| \ \ / / _` | __/ _ \
+ * | |_) | |  | |\ V / (_| | ||  __/
+ * | .__/|_|  |_| \_/ \__,_|\__\___|
+ * |_|
+ *  FIGLET: private
+ */
+
+/// The hostname executions see in the sandbox.
+const HOSTNAME: &[u8] = b"meticulous";
+
+/// Contents for a `uid_map` or `gid_map` file that map `id` outside of the sandbox to root inside.
+fn id_map(id: u32) -> CString {
+    CString::new(format!("0 {id} 1")).unwrap()
+}
+
+fn cstring(path: &Path) -> Result<CString, std::ffi::NulError> {
+    CString::new(path.as_os_str().as_bytes())
+}
+
+/// Everything needed to set up a [ScratchDir], prepared before forking.
+struct ScratchPaths {
+    path: CString,
+    tmpfs_options: Option<CString>,
+}
+
+impl ScratchPaths {
+    fn new(scratch: &ScratchDir) -> Result<Self, std::ffi::NulError> {
+        Ok(ScratchPaths {
+            path: cstring(scratch.path)?,
+            tmpfs_options: scratch
+                .quota
+                .map(|quota| CString::new(format!("size={quota},mode=700")))
+                .transpose()?,
+        })
+    }
+}
+
+/// Everything needed to set up a [RootFs], prepared before forking.
+struct RootPaths {
+    scratch: CString,
+    upper: CString,
+    work: CString,
+    root: CString,
+    overlay_options: CString,
+    proc: CString,
+    dev: CString,
+    tmp: CString,
+    working_directory: CString,
+}
+
+impl RootPaths {
+    fn new(root_fs: &RootFs) -> Result<Self, std::ffi::NulError> {
+        let root = root_fs.scratch.join("root");
+        // Overlayfs wants the top layer first.
+        let mut lower = root_fs
+            .layers
+            .iter()
+            .rev()
+            .map(|layer| layer.as_os_str().as_bytes())
+            .collect::<Vec<_>>()
+            .join(&b':');
+        let mut overlay_options = b"lowerdir=".to_vec();
+        overlay_options.append(&mut lower);
+        overlay_options.extend_from_slice(b",upperdir=");
+        overlay_options.extend_from_slice(root_fs.scratch.join("upper").as_os_str().as_bytes());
+        overlay_options.extend_from_slice(b",workdir=");
+        overlay_options.extend_from_slice(root_fs.scratch.join("work").as_os_str().as_bytes());
+        Ok(RootPaths {
+            scratch: cstring(root_fs.scratch)?,
+            upper: cstring(&root_fs.scratch.join("upper"))?,
+            work: cstring(&root_fs.scratch.join("work"))?,
+            overlay_options: CString::new(overlay_options)?,
+            proc: cstring(&root.join("proc"))?,
+            dev: cstring(&root.join("dev"))?,
+            tmp: cstring(&root.join("tmp"))?,
+            root: cstring(&root)?,
+            working_directory: cstring(&Path::new("/").join(root_fs.working_directory))?,
+        })
+    }
+}
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Runs in the process started by the [Command]. Returns in the program's process, in the
+/// sandbox, so that the [Command] can go on to exec it.
+fn enter(
+    uid_map: &CStr,
+    gid_map: &CStr,
+    root: Option<&RootPaths>,
+    isolate_network: bool,
+    scratch: Option<&ScratchPaths>,
+) -> io::Result<()> {
+    let mut namespaces = libc::CLONE_NEWUSER
+        | libc::CLONE_NEWNS
+        | libc::CLONE_NEWPID
+        | libc::CLONE_NEWIPC
+        | libc::CLONE_NEWUTS;
+    if isolate_network {
+        namespaces |= libc::CLONE_NEWNET;
+    }
+    // SAFETY: neither call takes any pointers.
+    unsafe {
+        check(libc::setsid())?;
+        check(libc::unshare(namespaces))?;
+    }
+    // An unprivileged process can only map its own user and group, and can only map its group
+    // once it has given up the ability to call setgroups.
+    write_file(c"/proc/self/setgroups", b"deny")?;
+    write_file(c"/proc/self/uid_map", uid_map.to_bytes())?;
+    write_file(c"/proc/self/gid_map", gid_map.to_bytes())?;
+    // The program tells the supervisor through this pipe once it will die with the supervisor.
+    let mut armed = [0; 2];
+    // SAFETY: armed is valid for writes of two fds, and we're already in a freshly forked child,
+    // which has only one thread.
+    unsafe {
+        check(libc::pipe2(armed.as_mut_ptr(), libc::O_CLOEXEC))?;
+        match check(libc::fork())? {
+            0 => {
+                libc::close(armed[0]);
+                init(armed[1], root, isolate_network, scratch)
+            }
+            pid => {
+                libc::close(armed[1]);
+                supervise(pid, armed[0])
+            }
+        }
+    }
+}
+
+/// Set up the program's process, which is PID 1 in the new PID namespace. `armed` is the write
+/// end of the pipe the supervisor waits on.
+fn init(
+    armed: libc::c_int,
+    root: Option<&RootPaths>,
+    isolate_network: bool,
+    scratch: Option<&ScratchPaths>,
+) -> io::Result<()> {
+    // SAFETY: these calls only take pointers to NUL-terminated strings, and HOSTNAME and the byte
+    // written to armed, which are valid for reads of their lengths.
+    unsafe {
+        // If the supervisor is killed, take down the whole sandbox with it. Since the program is
+        // PID 1, the kernel kills everything else in the sandbox when it dies.
+        check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+        // If the supervisor died before that, nothing will kill us, but its end of the pipe has
+        // been closed, so the write fails, if SIGPIPE doesn't kill us first.
+        let written = libc::write(armed, [0u8].as_ptr().cast(), 1);
+        libc::close(armed);
+        if written != 1 {
+            return Err(io::Error::last_os_error());
+        }
+        check(libc::setsid())?;
+        // Keep our mounts from propagating back to the worker's mount namespace.
+        check(libc::mount(
+            std::ptr::null(),
+            c"/".as_ptr(),
+            std::ptr::null(),
+            libc::MS_REC | libc::MS_PRIVATE,
+            std::ptr::null(),
+        ))?;
+        match root {
+            None => {
+                mount_proc(c"/proc")?;
+                if let Some(scratch) = scratch.filter(|scratch| scratch.tmpfs_options.is_some()) {
+                    mount_scratch(scratch, &scratch.path)?;
+                    // The program may have been started in the scratch directory, which is now
+                    // hidden underneath the tmpfs.
+                    reenter_working_directory()?;
+                }
+            }
+            Some(root) => enter_root(root, scratch)?,
+        }
+        check(libc::sethostname(HOSTNAME.as_ptr().cast(), HOSTNAME.len()))?;
+    }
+    if isolate_network {
+        bring_up_loopback()?;
+    }
+    Ok(())
+}
+
+/// Bring up the loopback interface, which starts out down in a new network namespace.
+fn bring_up_loopback() -> io::Result<()> {
+    // SAFETY: ifreq is plain old data, for which all zeroes is valid, and the ioctls only use the
+    // request for the duration of the call.
+    unsafe {
+        let socket = check(libc::socket(
+            libc::AF_INET,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            0,
+        ))?;
+        let mut request: libc::ifreq = std::mem::zeroed();
+        for (dst, src) in request.ifr_name.iter_mut().zip(b"lo") {
+            *dst = *src as libc::c_char;
+        }
+        let result = check(libc::ioctl(socket, libc::SIOCGIFFLAGS, &mut request)).and_then(|_| {
+            request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+            check(libc::ioctl(socket, libc::SIOCSIFFLAGS, &request))
+        });
+        libc::close(socket);
+        result.map(drop)
+    }
+}
+
+fn mount_proc(target: &CStr) -> io::Result<()> {
+    // SAFETY: these calls only take pointers to NUL-terminated strings.
+    unsafe {
+        check(libc::mount(
+            c"proc".as_ptr(),
+            target.as_ptr(),
+            c"proc".as_ptr(),
+            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+            std::ptr::null(),
+        ))?;
+    }
+    Ok(())
+}
+
+/// Mount the scratch directory on `target`: a tmpfs the size of its quota, if it has one, or else
+/// the directory itself.
+fn mount_scratch(scratch: &ScratchPaths, target: &CStr) -> io::Result<()> {
+    // SAFETY: these calls only take pointers to NUL-terminated strings.
+    unsafe {
+        match &scratch.tmpfs_options {
+            Some(options) => check(libc::mount(
+                c"tmpfs".as_ptr(),
+                target.as_ptr(),
+                c"tmpfs".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                options.as_ptr().cast(),
+            ))?,
+            None => check(libc::mount(
+                scratch.path.as_ptr(),
+                target.as_ptr(),
+                std::ptr::null(),
+                libc::MS_BIND,
+                std::ptr::null(),
+            ))?,
+        };
+    }
+    Ok(())
+}
+
+/// Change to the working directory by its path, in case something has been mounted over it.
+fn reenter_working_directory() -> io::Result<()> {
+    let mut path = [0 as libc::c_char; libc::PATH_MAX as usize];
+    // SAFETY: path is valid for writes of its length, and getcwd NUL-terminates it on success.
+    unsafe {
+        if libc::getcwd(path.as_mut_ptr(), path.len()).is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        check(libc::chdir(path.as_ptr()))?;
+    }
+    Ok(())
+}
+
+/// Create a directory with exactly the given mode, unless it's already there.
+fn ensure_dir(path: &CStr, mode: libc::mode_t) -> io::Result<()> {
+    // SAFETY: path is NUL-terminated.
+    unsafe {
+        match check(libc::mkdir(path.as_ptr(), mode)) {
+            Err(err) if err.raw_os_error() == Some(libc::EEXIST) => Ok(()),
+            // Don't let the worker's umask get in the way.
+            Ok(_) => check(libc::chmod(path.as_ptr(), mode)).map(drop),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Assemble the root file system in a tmpfs mounted on the scratch directory, then make it the
+/// root and change to the working directory in it. The program's own scratch directory, if it has
+/// one, goes on `/tmp`.
+fn enter_root(root: &RootPaths, scratch: Option<&ScratchPaths>) -> io::Result<()> {
+    // SAFETY: these calls only take pointers to NUL-terminated strings.
+    unsafe {
+        check(libc::mount(
+            c"tmpfs".as_ptr(),
+            root.scratch.as_ptr(),
+            c"tmpfs".as_ptr(),
+            0,
+            std::ptr::null(),
+        ))?;
+        for dir in [&root.upper, &root.work, &root.root] {
+            check(libc::mkdir(dir.as_ptr(), 0o755))?;
+        }
+        check(libc::mount(
+            c"overlay".as_ptr(),
+            root.root.as_ptr(),
+            c"overlay".as_ptr(),
+            0,
+            root.overlay_options.as_ptr().cast(),
+        ))?;
+        ensure_dir(&root.proc, 0o555)?;
+        ensure_dir(&root.dev, 0o755)?;
+        ensure_dir(&root.tmp, 0o1777)?;
+        if let Some(scratch) = scratch {
+            mount_scratch(scratch, &root.tmp)?;
+        }
+        check(libc::mount(
+            c"/dev".as_ptr(),
+            root.dev.as_ptr(),
+            std::ptr::null(),
+            libc::MS_BIND | libc::MS_REC,
+            std::ptr::null(),
+        ))?;
+        mount_proc(&root.proc)?;
+        check(libc::chdir(root.root.as_ptr()))?;
+        check(libc::mount(
+            c".".as_ptr(),
+            c"/".as_ptr(),
+            std::ptr::null(),
+            libc::MS_MOVE,
+            std::ptr::null(),
+        ))?;
+        check(libc::chroot(c".".as_ptr()))?;
+        check(libc::chdir(root.working_directory.as_ptr()))?;
+    }
+    Ok(())
+}
+
+/// The program's pid, outside of the sandbox, for [forward_signal].
+static PROGRAM: AtomicI32 = AtomicI32::new(0);
+
+/// Signals the supervisor doesn't pass on to the program: those that can't be caught, and those
+/// that are about the supervisor itself.
+const UNFORWARDED_SIGNALS: [libc::c_int; 9] = [
+    libc::SIGKILL,
+    libc::SIGSTOP,
+    libc::SIGCHLD,
+    libc::SIGSEGV,
+    libc::SIGBUS,
+    libc::SIGFPE,
+    libc::SIGILL,
+    libc::SIGTRAP,
+    libc::SIGSYS,
+];
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    // SAFETY: kill is async-signal-safe.
+    unsafe { libc::kill(PROGRAM.load(Ordering::Relaxed), signal) };
+}
+
+/// Wait for the program to terminate, then exit the same way it did. This runs in the process
+/// started by the [Command], outside of the sandbox.
+fn supervise(pid: libc::pid_t, armed: libc::c_int) -> ! {
+    // SAFETY: forward_signal is async-signal-safe, read's and waitpid's pointers are valid for
+    // writes, and setrlimit's limit for reads.
+    unsafe {
+        // Don't start supervising until the program will die with us. If it fails before then,
+        // the read sees the end of the pipe, and waitpid reaps it below.
+        let mut byte = 0u8;
+        libc::read(armed, (&mut byte as *mut u8).cast(), 1);
+        PROGRAM.store(pid, Ordering::Relaxed);
+        for signal in 1..32 {
+            if !UNFORWARDED_SIGNALS.contains(&signal) {
+                let handler: extern "C" fn(libc::c_int) = forward_signal;
+                libc::signal(signal, handler as libc::sighandler_t);
+            }
+        }
+        // Close everything, including our copies of the program's stdout and stderr and the pipe
+        // the [Command] uses to tell whether the program was successfully exec'd, so that they
+        // aren't held open after the program exits.
+        if libc::syscall(libc::SYS_close_range, 0, libc::c_uint::MAX, 0) == -1 {
+            for fd in 0..1024 {
+                libc::close(fd);
+            }
+        }
+        let mut status = 0;
+        while libc::waitpid(pid, &mut status, 0) == -1 {
+            if *libc::__errno_location() != libc::EINTR {
+                libc::_exit(127);
+            }
+        }
+        if libc::WIFEXITED(status) {
+            libc::_exit(libc::WEXITSTATUS(status));
+        }
+        // Die from the same signal, without dumping a core of our own.
+        let signal = libc::WTERMSIG(status);
+        let no_core = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+        libc::_exit(128 + signal)
+    }
+}